@@ -3,22 +3,22 @@ use chrono::Utc;
 
 fn fallible_sonyflake() {
     let now = Utc::now();
-    let mut sf = Settings::new().set_start_time(now).into_sonyflake().unwrap();
+    let sf = Settings::new().set_start_time(now).into_sonyflake().unwrap();
     let next_id = sf.next_id().unwrap();
     println!("{}", next_id);
 
-    let mut sf = SonyFlake::new(Settings::new().set_start_time(now)).unwrap();
+    let sf = SonyFlake::new(Settings::new().set_start_time(now)).unwrap();
     let next_id = sf.next_id().unwrap();
     println!("{}", next_id);
 }
 
 fn infallible_sonyflake() {
     let now = Utc::now();
-    let mut sf = Settings::new().set_start_time(now).into_infallible_sonyflake().unwrap();
+    let sf = Settings::new().set_start_time(now).into_infallible_sonyflake().unwrap();
     let next_id = sf.next_id();
     println!("{}", next_id);
 
-    let mut sf = InfallibleSonyFlake::new(Settings::new().set_start_time(now)).unwrap();
+    let sf = InfallibleSonyFlake::new(Settings::new().set_start_time(now)).unwrap();
     let next_id = sf.next_id();
     println!("{}", next_id);
 }
@@ -31,7 +31,7 @@ struct CustomMachineID {
 impl MachineID for CustomMachineID {
     fn machine_id(&mut self) -> Result<u16, Box<dyn std::error::Error + Send + Sync + 'static>> {
         self.counter += 1;
-        if self.counter % 2 != 0 {
+        if self.counter % 2 == 1 {
             Ok(self.id)
         } else {
             Err(Box::new("NaN".parse::<u32>().unwrap_err()))
@@ -43,16 +43,12 @@ struct CustomMachineIDChecker;
 
 impl MachineIDChecker for CustomMachineIDChecker {
     fn check_machine_id(&self, id: u16) -> bool {
-        if id % 2 != 0 {
-            true
-        } else {
-            false
-        }
+        id % 2 == 1
     }
 }
 
 fn with_custom_machine_id_and_checker() {
-    let mut sf = Settings::new()
+    let sf = Settings::new()
         .set_machine_id(Box::new(CustomMachineID { counter: 0, id: 1 }))
         .set_check_machine_id(Box::new(CustomMachineIDChecker {}))
         .into_infallible_sonyflake().unwrap();
@@ -0,0 +1,54 @@
+//! Compares the multi-threaded throughput of `AtomicSonyFlake` and the lock-based `SonyFlake`.
+//!
+//! Run with `cargo bench --bench atomic_throughput`. Both generators use a 16-bit sequence,
+//! so the clock does not cap their throughput.
+
+use infallible_sonyflake::{AtomicSonyFlake, BitLayout, Settings};
+use std::time::{Duration, Instant};
+
+const THREADS: usize = 8;
+const IDS: usize = 50000;
+
+fn settings() -> Settings {
+    Settings::new()
+        .set_machine_id_value(7)
+        .set_bit_layout(BitLayout::new(39, 16, 8).unwrap())
+}
+
+/// Generates `IDS` ids on each of `THREADS` threads, returning the time taken.
+fn elapsed<F>(next_id: F) -> Duration
+where
+    F: FnMut() -> u64 + Clone + Send + 'static,
+{
+    let start = Instant::now();
+    let threads = (0..THREADS)
+        .map(|_| {
+            let mut next_id = next_id.clone();
+            std::thread::spawn(move || {
+                for _ in 0..IDS {
+                    std::hint::black_box(next_id());
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for t in threads {
+        t.join().expect("thread panicked");
+    }
+    start.elapsed()
+}
+
+fn main() {
+    let per_sec = |elapsed: Duration| (THREADS * IDS) as f64 / elapsed.as_secs_f64();
+
+    let atomic = AtomicSonyFlake::new(settings()).unwrap();
+    let atomic_elapsed = elapsed(move || atomic.next_id().unwrap());
+
+    let mutex = settings().into_sonyflake().unwrap();
+    let mutex_elapsed = elapsed(move || mutex.next_id().unwrap());
+
+    println!(
+        "atomic: {:.0} ids/sec, mutex: {:.0} ids/sec",
+        per_sec(atomic_elapsed),
+        per_sec(mutex_elapsed)
+    );
+}
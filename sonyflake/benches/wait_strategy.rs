@@ -0,0 +1,54 @@
+//! Compares the tail latency of `WaitStrategy::Sleep` and `WaitStrategy::Spin` under sequence saturation.
+//!
+//! Run with `cargo bench --bench wait_strategy`. Every call which used up the sequence of a window waits
+//! for the next one; the overshoot is how long the call took beyond the wait it asked for.
+
+use infallible_sonyflake::{MachineID, Settings, WaitStrategy};
+use std::time::{Duration, Instant};
+
+/// windows to saturate per strategy
+const WINDOWS: usize = 200;
+
+struct FixedMachineID(u16);
+
+impl MachineID for FixedMachineID {
+    fn machine_id(&mut self) -> Result<u16, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        Ok(self.0)
+    }
+}
+
+fn overshoots(strategy: WaitStrategy) -> Vec<Duration> {
+    let sf = Settings::new()
+        .set_machine_id(Box::new(FixedMachineID(1)))
+        .set_wait_strategy(strategy)
+        .into_sonyflake()
+        .unwrap();
+
+    let mut overshoots = Vec::with_capacity(WINDOWS);
+    while overshoots.len() < WINDOWS {
+        let start = Instant::now();
+        let (_, wait) = sf.next_id_with_wait().unwrap();
+        if !wait.is_zero() {
+            overshoots.push(start.elapsed().saturating_sub(wait));
+        }
+    }
+    overshoots.sort();
+    overshoots
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    sorted[((sorted.len() - 1) as f64 * p).round() as usize]
+}
+
+fn main() {
+    for strategy in [WaitStrategy::Sleep, WaitStrategy::Spin] {
+        let overshoots = overshoots(strategy);
+        println!(
+            "{:?}: overshoot p50 {:?}, p99 {:?}, max {:?}",
+            strategy,
+            percentile(&overshoots, 0.5),
+            percentile(&overshoots, 0.99),
+            overshoots[overshoots.len() - 1]
+        );
+    }
+}
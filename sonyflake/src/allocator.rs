@@ -0,0 +1,115 @@
+use crate::{Error, IdBlock, SonyFlake};
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+/// a lease request: the number of ids wanted and where to send the block
+type Lease = (u32, Sender<Result<IdBlock, Error>>);
+
+/// `RangeAllocator` leases blocks of contiguous ids to workers over a channel.
+///
+/// A coordinator owns the allocator and its [`SonyFlake`], and calls [`serve`] to answer lease
+/// requests. Workers hold a [`RangeClient`] and lease an [`IdBlock`] whenever they run out of ids,
+/// then generate ids locally from the block without any per-id coordination. Blocks are reserved
+/// with [`SonyFlake::reserve_block`], so they never overlap each other or ids generated directly.
+///
+/// [`SonyFlake`]: struct.SonyFlake.html
+/// [`serve`]: struct.RangeAllocator.html#method.serve
+/// [`RangeClient`]: struct.RangeClient.html
+/// [`IdBlock`]: struct.IdBlock.html
+/// [`SonyFlake::reserve_block`]: struct.SonyFlake.html#method.reserve_block
+#[derive(Debug)]
+pub struct RangeAllocator {
+    sf: SonyFlake,
+    requests: Receiver<Lease>,
+}
+
+impl RangeAllocator {
+    /// Create a new `RangeAllocator` reserving blocks from `sf`, and a client to request them with.
+    pub fn new(sf: SonyFlake) -> (Self, RangeClient) {
+        let (tx, rx) = channel();
+        (Self { sf, requests: rx }, RangeClient { requests: tx })
+    }
+
+    /// Answer lease requests until every [`RangeClient`] has been dropped.
+    ///
+    /// [`RangeClient`]: struct.RangeClient.html
+    pub fn serve(self) {
+        while let Ok((n, reply)) = self.requests.recv() {
+            // the worker may have given up waiting, which is fine
+            let _ = reply.send(self.sf.reserve_block(n));
+        }
+    }
+}
+
+/// `RangeClient` requests blocks of ids from a [`RangeAllocator`]. Clone it to hand one to each worker.
+///
+/// [`RangeAllocator`]: struct.RangeAllocator.html
+#[derive(Debug, Clone)]
+pub struct RangeClient {
+    requests: Sender<Lease>,
+}
+
+impl RangeClient {
+    /// Lease a block of `n` contiguous ids, blocking until the allocator answers.
+    /// Returns `Error::AllocatorClosed` if the allocator is no longer serving requests.
+    pub fn lease(&self, n: u32) -> Result<IdBlock, Error> {
+        let (tx, rx) = channel();
+        self.requests.send((n, tx)).map_err(|_| Error::AllocatorClosed)?;
+        rx.recv().map_err(|_| Error::AllocatorClosed)?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RangeAllocator;
+    use crate::{Error, IDParts, Settings};
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_range_allocator() {
+        let sf = Settings::new()
+            .set_machine_id_value(9)
+            .into_sonyflake()
+            .unwrap();
+        let (allocator, client) = RangeAllocator::new(sf);
+        let coordinator = std::thread::spawn(move || allocator.serve());
+
+        let workers = (0..3)
+            .map(|_| {
+                let client = client.clone();
+                std::thread::spawn(move || {
+                    let mut ids = Vec::new();
+                    for _ in 0..10 {
+                        let block = client.lease(300).unwrap();
+                        assert_eq!(block.len(), 300);
+                        ids.extend(block);
+                    }
+                    ids
+                })
+            })
+            .collect::<Vec<_>>();
+        drop(client);
+
+        let mut ids = HashSet::new();
+        for worker in workers {
+            for id in worker.join().expect("worker panicked") {
+                assert_eq!(IDParts::decompose(id).get_machine_id(), 9);
+                assert!(ids.insert(id), "duplicate id: {}", id);
+            }
+        }
+        assert_eq!(ids.len(), 3 * 10 * 300);
+
+        // all clients are gone, so the coordinator stops serving
+        coordinator.join().unwrap();
+    }
+
+    #[test]
+    fn test_range_allocator_closed() {
+        let sf = Settings::new()
+            .set_machine_id_value(9)
+            .into_sonyflake()
+            .unwrap();
+        let (allocator, client) = RangeAllocator::new(sf);
+        drop(allocator);
+        assert!(matches!(client.lease(1).unwrap_err(), Error::AllocatorClosed));
+    }
+}
@@ -0,0 +1,163 @@
+use crate::{check_borrowed_bits, sequence_mask, BitLayout, Error, MachineID, MachineIDChecker, Settings, UnitClock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// bit length of the sequence in the packed state, enough for any layout
+const STATE_SEQUENCE_BITS: u32 = 16;
+
+/// `AtomicSonyFlake` is a distributed unique ID generator whose state, the elapsed time and the sequence,
+/// is packed into a single `AtomicU64` and advanced with a compare-and-swap loop instead of under a lock.
+///
+/// Threads never block each other: a thread losing a race just retries with the fresh state. When the
+/// sequence of a time window is used up, the thread which takes the first id of the next window sleeps
+/// until that window starts, without holding anything up for the others.
+///
+/// It honors the start time, machine id, time unit, bit layout and clock of the [`Settings`]; the other options
+/// need the state kept behind the lock of [`SonyFlake`]. Clones share the same state.
+///
+/// [`Settings`]: struct.Settings.html
+/// [`SonyFlake`]: struct.SonyFlake.html
+#[derive(Debug, Clone)]
+pub struct AtomicSonyFlake {
+    start_time: i64,
+    clock: Arc<UnitClock>,
+    layout: BitLayout,
+    machine_id: u16,
+    state: Arc<AtomicU64>,
+}
+
+impl AtomicSonyFlake {
+    /// Create a new `AtomicSonyFlake`.
+    pub fn new<M: MachineID, C: MachineIDChecker>(mut st: Settings<M, C>) -> Result<Self, Error> {
+        let layout = st.bit_layout;
+        let time_unit = st.get_time_unit()?;
+        let start_time = st.get_start_time()?;
+        let clock = Arc::new(UnitClock::new(st.nano_clock.take(), time_unit));
+
+        let machine_id = st.get_and_check_machine_id()?;
+        check_borrowed_bits(layout, 0, machine_id)?;
+
+        Ok(Self {
+            start_time,
+            clock,
+            layout,
+            machine_id,
+            state: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    /// Generate the next unique id.
+    /// After the time overflows, next_id returns an error.
+    pub fn next_id(&self) -> Result<u64, Error> {
+        let mask_sequence = sequence_mask(self.layout.sequence);
+
+        let current = self.clock.elapsed_time(self.start_time);
+        let mut state = self.state.load(Ordering::Acquire);
+        loop {
+            let (elapsed_time, sequence) = unpack(state);
+
+            let (elapsed_time, sequence) = if elapsed_time < current {
+                (current, 0)
+            } else {
+                // elapsed_time >= current
+                match sequence.wrapping_add(1) & mask_sequence {
+                    0 => (elapsed_time + 1, 0),
+                    sequence => (elapsed_time, sequence),
+                }
+            };
+
+            if elapsed_time >= 1 << self.layout.time {
+                return Err(Error::TimeOverflow);
+            }
+
+            match self.state.compare_exchange_weak(
+                state,
+                pack(elapsed_time, sequence),
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => {
+                    let overtime = elapsed_time - current;
+                    if overtime > 0 {
+                        std::thread::sleep(self.clock.sleep_time(overtime));
+                    }
+                    return Ok(self.layout.compose(elapsed_time, sequence, self.machine_id));
+                }
+                Err(actual) => state = actual,
+            }
+        }
+    }
+
+    /// Returns the machine id of the generator.
+    pub fn machine_id(&self) -> u16 {
+        self.machine_id
+    }
+
+    /// Returns the allocation of the bits of the ids, see `Settings::set_bit_layout`.
+    pub fn bit_layout(&self) -> BitLayout {
+        self.layout
+    }
+}
+
+fn pack(elapsed_time: i64, sequence: u16) -> u64 {
+    (elapsed_time as u64) << STATE_SEQUENCE_BITS | sequence as u64
+}
+
+fn unpack(state: u64) -> (i64, u16) {
+    ((state >> STATE_SEQUENCE_BITS) as i64, state as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AtomicSonyFlake;
+    use crate::{BitLayout, Error, IDParts, Settings};
+    use std::collections::HashSet;
+
+    const THREADS: usize = 8;
+    const IDS: usize = 50000;
+
+    #[test]
+    fn test_atomic_sonyflake() {
+        let sf = AtomicSonyFlake::new(Settings::new().set_machine_id_value(7)).unwrap();
+
+        let mut last = 0;
+        // crosses several windows with the default 8-bit sequence
+        for _ in 0..1000 {
+            let id = sf.next_id().unwrap();
+            assert!(id > last);
+            last = id;
+            assert_eq!(IDParts::decompose(id).get_machine_id(), 7);
+        }
+
+        sf.state.store(super::pack(1 << 39, 0), std::sync::atomic::Ordering::Release);
+        assert!(matches!(sf.next_id().unwrap_err(), Error::TimeOverflow));
+    }
+
+    #[test]
+    fn test_atomic_sonyflake_stress() {
+        // a wide sequence, so the clock does not slow down the threads
+        let sf = AtomicSonyFlake::new(
+            Settings::new()
+                .set_machine_id_value(7)
+                .set_bit_layout(BitLayout::new(39, 16, 8).unwrap()),
+        )
+        .unwrap();
+
+        let threads = (0..THREADS)
+            .map(|_| {
+                let sf = sf.clone();
+                std::thread::spawn(move || (0..IDS).map(|_| sf.next_id().unwrap()).collect::<Vec<_>>())
+            })
+            .collect::<Vec<_>>();
+
+        let mut unique = HashSet::new();
+        for t in threads {
+            let thread_ids = t.join().expect("thread panicked");
+            assert!(thread_ids.windows(2).all(|w| w[0] < w[1]));
+            for id in thread_ids {
+                assert!(unique.insert(id), "duplicate id: {}", id);
+            }
+        }
+        assert_eq!(unique.len(), THREADS * IDS);
+    }
+}
@@ -0,0 +1,212 @@
+use crate::MachineID;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpStream};
+use std::time::Duration;
+
+/// the link-local address of the EC2 instance metadata service
+const IMDS_ENDPOINT: ([u8; 4], u16) = ([169, 254, 169, 254], 80);
+
+/// lifetime of the IMDSv2 session tokens asked for, the service allows up to 6 hours
+const TOKEN_TTL_SECONDS: u32 = 21600;
+
+/// `AmazonEC2MachineID` is a [`MachineID`] returning the lower 16 bits of the private IPv4 address of
+/// the EC2 instance, as read from the instance metadata service (IMDS), like `AmazonEC2MachineID` of
+/// the Go `awsutil` package.
+///
+/// It asks for an IMDSv2 session token first and falls back to IMDSv1 if the service does not issue
+/// one. If the service does not answer within the timeout, 1 second by default, or the answer is not
+/// an IPv4 address, the generator is not created and `Error::MachineIdFailed` tells why.
+///
+/// ```rust,no_run
+/// use infallible_sonyflake::{AmazonEC2MachineID, Settings};
+///
+/// let sf = Settings::new()
+///     .set_machine_id(Box::new(AmazonEC2MachineID::new()))
+///     .into_sonyflake()
+///     .unwrap();
+/// ```
+///
+/// [`MachineID`]: trait.MachineID.html
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct AmazonEC2MachineID {
+    endpoint: SocketAddr,
+    timeout: Duration,
+}
+
+impl Default for AmazonEC2MachineID {
+    fn default() -> Self {
+        Self {
+            endpoint: SocketAddr::from(IMDS_ENDPOINT),
+            timeout: Duration::from_secs(1),
+        }
+    }
+}
+
+impl AmazonEC2MachineID {
+    /// Create a new `AmazonEC2MachineID` querying `169.254.169.254` with a timeout of 1 second.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the timeout of each step of the queries: connecting, sending and receiving.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Query the metadata service at `endpoint` instead, e.g. a proxy or a mock in tests.
+    pub fn with_endpoint(mut self, endpoint: SocketAddr) -> Self {
+        self.endpoint = endpoint;
+        self
+    }
+
+    /// Returns the private IPv4 address of the instance.
+    pub fn private_ipv4(&self) -> Result<Ipv4Addr, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        let token_ttl = TOKEN_TTL_SECONDS.to_string();
+        let ttl_header = ("X-aws-ec2-metadata-token-ttl-seconds", token_ttl.as_str());
+        let token = match self.request("PUT", "/latest/api/token", &[ttl_header]) {
+            Ok((200, token)) => Some(token),
+            // IMDSv1 only
+            Ok(_) => None,
+            Err(e) => return Err(imds_error(e)),
+        };
+
+        let headers = match &token {
+            Some(token) => vec![("X-aws-ec2-metadata-token", token.as_str())],
+            None => Vec::new(),
+        };
+        match self.request("GET", "/latest/meta-data/local-ipv4", &headers).map_err(imds_error)? {
+            (200, body) => body.trim().parse().map_err(|e| {
+                format!("invalid local-ipv4 {:?} from the EC2 instance metadata service: {}", body, e).into()
+            }),
+            (status, _) => {
+                Err(format!("the EC2 instance metadata service answered local-ipv4 with status {}", status).into())
+            }
+        }
+    }
+
+    /// Sends an HTTP/1.0 request, so the response is neither chunked nor kept alive,
+    /// and returns the status code and the body.
+    fn request(&self, method: &str, path: &str, headers: &[(&str, &str)]) -> io::Result<(u16, String)> {
+        let mut stream = TcpStream::connect_timeout(&self.endpoint, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+
+        let mut request = format!("{} {} HTTP/1.0\r\nHost: {}\r\n", method, path, self.endpoint.ip());
+        for (name, value) in headers {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        if method == "PUT" {
+            request.push_str("Content-Length: 0\r\n");
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes())?;
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed HTTP response");
+        let (head, body) = response.split_once("\r\n\r\n").ok_or_else(invalid)?;
+        let status = head
+            .split_whitespace()
+            .nth(1)
+            .and_then(|status| status.parse().ok())
+            .ok_or_else(invalid)?;
+        Ok((status, body.to_string()))
+    }
+}
+
+fn imds_error(e: io::Error) -> Box<dyn std::error::Error + Send + Sync + 'static> {
+    match e.kind() {
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => {
+            "timed out querying the EC2 instance metadata service".into()
+        }
+        _ => format!("cannot query the EC2 instance metadata service: {}", e).into(),
+    }
+}
+
+impl MachineID for AmazonEC2MachineID {
+    fn machine_id(&mut self) -> Result<u16, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        let octets = self.private_ipv4()?.octets();
+        Ok(((octets[2] as u16) << 8) + (octets[3] as u16))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AmazonEC2MachineID;
+    use crate::{Error, Settings};
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpListener};
+    use std::time::Duration;
+
+    /// Serves one connection per response, returning the address and the requests received.
+    fn mock_imds(responses: Vec<&'static str>) -> (SocketAddr, std::thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            responses
+                .into_iter()
+                .map(|response| {
+                    let (mut stream, _) = listener.accept().unwrap();
+                    let mut request = Vec::new();
+                    let mut buf = [0; 1024];
+                    while !request.ends_with(b"\r\n\r\n") {
+                        let n = stream.read(&mut buf).unwrap();
+                        request.extend_from_slice(&buf[..n]);
+                    }
+                    stream.write_all(response.as_bytes()).unwrap();
+                    String::from_utf8(request).unwrap()
+                })
+                .collect()
+        });
+        (addr, server)
+    }
+
+    #[test]
+    fn test_amazon_ec2_machine_id() {
+        let (addr, server) = mock_imds(vec![
+            "HTTP/1.0 200 OK\r\nContent-Length: 5\r\n\r\ntoken",
+            "HTTP/1.0 200 OK\r\nContent-Length: 10\r\n\r\n10.0.12.34",
+        ]);
+        let sf = Settings::new()
+            .set_machine_id(Box::new(AmazonEC2MachineID::new().with_endpoint(addr)))
+            .into_sonyflake()
+            .unwrap();
+        assert_eq!(sf.machine_id(), (12 << 8) + 34);
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("PUT /latest/api/token HTTP/1.0\r\n"));
+        assert!(requests[0].contains("X-aws-ec2-metadata-token-ttl-seconds: 21600\r\n"));
+        assert!(requests[1].starts_with("GET /latest/meta-data/local-ipv4 HTTP/1.0\r\n"));
+        assert!(requests[1].contains("X-aws-ec2-metadata-token: token\r\n"));
+    }
+
+    #[test]
+    fn test_amazon_ec2_machine_id_imdsv1() {
+        let (addr, server) = mock_imds(vec![
+            "HTTP/1.0 403 Forbidden\r\n\r\n",
+            "HTTP/1.0 200 OK\r\n\r\n172.31.1.2",
+        ]);
+        let ip = AmazonEC2MachineID::new().with_endpoint(addr).private_ipv4().unwrap();
+        assert_eq!(ip, "172.31.1.2".parse::<std::net::Ipv4Addr>().unwrap());
+        assert!(!server.join().unwrap()[1].contains("X-aws-ec2-metadata-token"));
+
+        let (addr, _) = mock_imds(vec!["HTTP/1.0 200 OK\r\n\r\ntoken", "HTTP/1.0 200 OK\r\n\r\nnot an ip"]);
+        let err = AmazonEC2MachineID::new().with_endpoint(addr).private_ipv4().unwrap_err();
+        assert!(err.to_string().contains("not an ip"));
+    }
+
+    #[test]
+    fn test_amazon_ec2_machine_id_timeout() {
+        // accepts, but never answers
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let machine_id = AmazonEC2MachineID::new()
+            .with_endpoint(listener.local_addr().unwrap())
+            .with_timeout(Duration::from_millis(100));
+        let err = Settings::new()
+            .set_machine_id(Box::new(machine_id))
+            .into_sonyflake()
+            .unwrap_err();
+        assert!(matches!(&err, Error::MachineIdFailed(e) if e.to_string().contains("timed out")));
+    }
+}
@@ -0,0 +1,150 @@
+use crate::{check_borrowed_bits, sequence_mask, to_sonyflake_time, BitLayout, Clock, Error, MachineID, FLAKE_TIME_UNIT};
+#[cfg(not(feature = "std"))]
+use alloc::boxed::Box;
+use chrono::{DateTime, Utc};
+
+/// `BareSonyFlake` is a SonyFlake generator for `no_std` targets, available without the `std` feature.
+///
+/// It needs nothing from the platform: the current time comes from the given [`Clock`], e.g. an RTC
+/// or a GPS receiver, and the machine id from the given [`MachineID`]. It uses SonyFlake's default
+/// bit layout and time unit of 10 msec.
+///
+/// There is no thread to put to sleep, so when the sequence of the current time window is used up,
+/// `next_id` returns `Error::RateExceeded` instead of waiting; retry once the clock has moved on.
+///
+/// ```rust
+/// use chrono::{DateTime, TimeZone, Utc};
+/// use infallible_sonyflake::{BareSonyFlake, Clock, IDParts, MachineID};
+///
+/// struct Rtc;
+///
+/// impl Clock for Rtc {
+///     fn now(&self) -> DateTime<Utc> {
+///         Utc.timestamp_opt(1_700_000_000, 0).unwrap()
+///     }
+/// }
+///
+/// struct BoardID;
+///
+/// impl MachineID for BoardID {
+///     fn machine_id(&mut self) -> Result<u16, Box<dyn core::error::Error + Send + Sync + 'static>> {
+///         Ok(12)
+///     }
+/// }
+///
+/// let start_time = Utc.timestamp_opt(1_600_000_000, 0).unwrap();
+/// let mut sf = BareSonyFlake::new(start_time, BoardID, Box::new(Rtc)).unwrap();
+/// let id = sf.next_id().unwrap();
+/// assert_eq!(IDParts::decompose(id).get_machine_id(), 12);
+/// ```
+///
+/// [`Clock`]: trait.Clock.html
+/// [`MachineID`]: trait.MachineID.html
+pub struct BareSonyFlake {
+    start_time: i64,
+    clock: Box<dyn Clock>,
+    machine_id: u16,
+    elapsed_time: i64,
+    sequence: u16,
+}
+
+impl BareSonyFlake {
+    /// Create a new `BareSonyFlake` counting time since `start_time` on `clock`.
+    pub fn new<M: MachineID>(start_time: DateTime<Utc>, mut machine_id: M, clock: Box<dyn Clock>) -> Result<Self, Error> {
+        if start_time > clock.now() {
+            return Err(Error::StartTimeAheadOfCurrentTime(start_time));
+        }
+
+        let machine_id = machine_id.machine_id().map_err(Error::MachineIdFailed)?;
+        check_borrowed_bits(BitLayout::SONYFLAKE, 0, machine_id)?;
+
+        Ok(Self {
+            start_time: to_sonyflake_time(start_time, FLAKE_TIME_UNIT)?,
+            clock,
+            machine_id,
+            // the window before the start time, used up
+            elapsed_time: -1,
+            sequence: sequence_mask(BitLayout::SONYFLAKE.sequence),
+        })
+    }
+
+    /// Generate the next unique id.
+    /// After the time overflows, next_id returns `Error::TimeOverflow`. When the sequence of the current
+    /// time window is used up, it returns `Error::RateExceeded` without consuming anything.
+    pub fn next_id(&mut self) -> Result<u64, Error> {
+        let mask_sequence = sequence_mask(BitLayout::SONYFLAKE.sequence);
+        let current = to_sonyflake_time(self.clock.now(), FLAKE_TIME_UNIT)? - self.start_time;
+
+        if self.elapsed_time < current {
+            self.elapsed_time = current;
+            self.sequence = 0;
+        } else {
+            // self.elapsed_time >= current
+            let sequence = self.sequence.wrapping_add(1) & mask_sequence;
+            if sequence == 0 {
+                return Err(Error::RateExceeded);
+            }
+            self.sequence = sequence;
+        }
+
+        if self.elapsed_time >= 1 << BitLayout::SONYFLAKE.time {
+            return Err(Error::TimeOverflow);
+        }
+
+        Ok(BitLayout::SONYFLAKE.compose(self.elapsed_time, self.sequence, self.machine_id))
+    }
+
+    /// Returns the machine id of the generator.
+    pub fn machine_id(&self) -> u16 {
+        self.machine_id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BareSonyFlake;
+    use crate::{Clock, Error, FixedMachineID, IDParts};
+    use chrono::{DateTime, Duration, TimeZone, Utc};
+    use std::sync::atomic::{AtomicI64, Ordering};
+    use std::sync::Arc;
+
+    /// a clock reading the shared number of milliseconds since the unix epoch
+    struct ManualClock(Arc<AtomicI64>);
+
+    impl Clock for ManualClock {
+        fn now(&self) -> DateTime<Utc> {
+            Utc.timestamp_millis_opt(self.0.load(Ordering::Relaxed)).unwrap()
+        }
+    }
+
+    #[test]
+    fn test_bare_sonyflake() {
+        let start_time = Utc.timestamp_opt(1_600_000_000, 0).unwrap();
+        let millis = Arc::new(AtomicI64::new(start_time.timestamp_millis() + 1_000));
+        let mut sf = BareSonyFlake::new(start_time, FixedMachineID(9), Box::new(ManualClock(millis.clone()))).unwrap();
+        assert_eq!(sf.machine_id(), 9);
+
+        let ids = (0..256).map(|_| sf.next_id().unwrap()).collect::<Vec<_>>();
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+        let parts = IDParts::decompose(ids[255]);
+        assert_eq!((parts.get_time(), parts.get_sequence(), parts.get_machine_id()), (100, 255, 9));
+
+        // the window is used up until the clock moves on
+        assert!(matches!(sf.next_id().unwrap_err(), Error::RateExceeded));
+        assert!(matches!(sf.next_id().unwrap_err(), Error::RateExceeded));
+        millis.fetch_add(10, Ordering::Relaxed);
+        let parts = IDParts::decompose(sf.next_id().unwrap());
+        assert_eq!((parts.get_time(), parts.get_sequence()), (101, 0));
+
+        let err = BareSonyFlake::new(Utc::now() + Duration::days(1), FixedMachineID(9), Box::new(ManualClock(millis.clone())))
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::StartTimeAheadOfCurrentTime(_)));
+
+        let far_past = Utc.with_ymd_and_hms(1600, 1, 1, 0, 0, 0).unwrap();
+        let err = BareSonyFlake::new(far_past, FixedMachineID(9), Box::new(ManualClock(millis)))
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::StartTimeOutOfRange(_)));
+    }
+}
@@ -0,0 +1,348 @@
+#[cfg(feature = "std")]
+use crate::{now_nanos, WaitStrategy, FLAKE_TIME_UNIT, NANOS_PER_DAY};
+use chrono::{DateTime, Utc};
+#[cfg(feature = "std")]
+use std::fmt::{Debug, Formatter};
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// `Clock` is a time source returning the current time, see [`Settings::set_clock`].
+///
+/// Every read of the current time by a generator, from checking the start time to generating ids
+/// and refreshing the start time of an [`InfallibleSonyFlake`], goes through the clock, so a mock
+/// clock makes time-dependent behavior deterministic in tests.
+///
+/// [`Settings::set_clock`]: struct.Settings.html#method.set_clock
+/// [`InfallibleSonyFlake`]: struct.InfallibleSonyFlake.html
+pub trait Clock: Send + Sync {
+    /// `now` returns the current time.
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// `SystemClock` reads the time from `chrono::Utc::now`.
+#[derive(Copy, Clone, Debug, Default)]
+#[cfg(feature = "std")]
+pub struct SystemClock;
+
+#[cfg(feature = "std")]
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A `Clock` read as a `NanoClock`.
+#[cfg(feature = "std")]
+pub(crate) struct ClockNanos(pub(crate) Box<dyn Clock>);
+
+#[cfg(feature = "std")]
+impl NanoClock for ClockNanos {
+    fn now_nanos(&self) -> i64 {
+        saturating_timestamp_nanos(self.0.now())
+    }
+}
+
+/// Nanoseconds since the unix epoch of `time`, saturated to the range of `i64`, about 1677 to 2262.
+/// A saturated time is out of the lifetime of any generator, which then reports it as an error.
+#[cfg(feature = "std")]
+pub(crate) fn saturating_timestamp_nanos(time: DateTime<Utc>) -> i64 {
+    match time.timestamp_nanos_opt() {
+        Some(nanos) => nanos,
+        None if time.timestamp() < 0 => i64::MIN,
+        None => i64::MAX,
+    }
+}
+
+/// `NanoClock` is a time source returning nanoseconds since the unix epoch directly,
+/// see [`Settings::set_nano_clock`].
+///
+/// [`Settings::set_nano_clock`]: struct.Settings.html#method.set_nano_clock
+pub trait NanoClock: Send + Sync {
+    /// `now_nanos` returns the current time in nanoseconds since the unix epoch.
+    fn now_nanos(&self) -> i64;
+}
+
+/// `SystemNanoClock` reads the time from `std::time::SystemTime`.
+#[derive(Copy, Clone, Debug, Default)]
+#[cfg(feature = "std")]
+pub struct SystemNanoClock;
+
+#[cfg(feature = "std")]
+impl NanoClock for SystemNanoClock {
+    fn now_nanos(&self) -> i64 {
+        match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_nanos().min(i64::MAX as u128) as i64,
+            // a system time before the unix epoch
+            Err(e) => -(e.duration().as_nanos().min(i64::MAX as u128) as i64),
+        }
+    }
+}
+
+/// A `NanoClock` standing still at the given time, for tests.
+#[cfg(test)]
+pub(crate) struct FixedClock(pub(crate) i64);
+
+#[cfg(test)]
+impl NanoClock for FixedClock {
+    fn now_nanos(&self) -> i64 {
+        self.0
+    }
+}
+
+/// A `NanoClock` reading the shared time, which tests move by hand.
+#[cfg(test)]
+pub(crate) struct ManualClock(pub(crate) std::sync::Arc<std::sync::atomic::AtomicI64>);
+
+#[cfg(test)]
+impl NanoClock for ManualClock {
+    fn now_nanos(&self) -> i64 {
+        self.0.load(std::sync::atomic::Ordering::SeqCst)
+    }
+}
+
+/// `TimeSource` is the time base of a generator, see [`Settings::set_time_source`].
+///
+/// [`Settings::set_time_source`]: struct.Settings.html#method.set_time_source
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+#[cfg(feature = "std")]
+pub enum TimeSource {
+    /// The wall clock, which follows NTP adjustments, including backward steps. This is the default.
+    #[default]
+    WallClock,
+
+    /// A monotonic clock, `std::time::Instant`, anchored at the wall clock time when it is set.
+    /// It never steps, so the ids are immune to adjustments of the wall clock, but it drifts from the wall
+    /// clock as long as the generator lives, so the time part of the ids no longer tells the wall clock time
+    /// they were generated at: decoding it with `to_datetime` or `timestamp_of` is only approximate.
+    Monotonic,
+}
+
+/// A `NanoClock` reading `Instant`, anchored at the wall clock time it was created at.
+#[cfg(feature = "std")]
+pub(crate) struct MonotonicNanoClock {
+    anchor: Instant,
+    anchor_nanos: i64,
+}
+
+#[cfg(feature = "std")]
+impl MonotonicNanoClock {
+    pub(crate) fn new() -> Self {
+        Self {
+            anchor: Instant::now(),
+            anchor_nanos: now_nanos(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl NanoClock for MonotonicNanoClock {
+    fn now_nanos(&self) -> i64 {
+        self.anchor_nanos + self.anchor.elapsed().as_nanos() as i64
+    }
+}
+
+/// The time source of a generator: a `NanoClock` if one is set, the crate's clock otherwise,
+/// counting time in units of `time_unit` nanoseconds.
+#[cfg(feature = "std")]
+pub(crate) struct UnitClock {
+    clock: Option<Box<dyn NanoClock>>,
+    time_unit: i64,
+}
+
+#[cfg(feature = "std")]
+impl Default for UnitClock {
+    fn default() -> Self {
+        Self::new(None, FLAKE_TIME_UNIT)
+    }
+}
+
+#[cfg(feature = "std")]
+impl UnitClock {
+    pub(crate) fn new(clock: Option<Box<dyn NanoClock>>, time_unit: i64) -> Self {
+        Self { clock, time_unit }
+    }
+
+    pub(crate) fn now_nanos(&self) -> i64 {
+        match &self.clock {
+            Some(clock) => clock.now_nanos(),
+            None => now_nanos(),
+        }
+    }
+
+    pub(crate) fn time_unit(&self) -> i64 {
+        self.time_unit
+    }
+
+    /// the number of time units in a UTC day
+    pub(crate) fn units_per_day(&self) -> i64 {
+        NANOS_PER_DAY / self.time_unit
+    }
+
+    pub(crate) fn elapsed_time(&self, start_time: i64) -> i64 {
+        (self.now_nanos() / self.time_unit).saturating_sub(start_time)
+    }
+
+    pub(crate) fn sleep_time(&self, overtime: i64) -> Duration {
+        Duration::from_nanos((overtime * self.time_unit) as u64)
+            - Duration::from_nanos((self.now_nanos() % self.time_unit) as u64)
+    }
+
+    /// Waits `wait` on this clock, by sleeping or by reading the clock until the time has passed.
+    pub(crate) fn wait(&self, strategy: WaitStrategy, wait: Duration) {
+        match strategy {
+            WaitStrategy::Sleep => std::thread::sleep(wait),
+            WaitStrategy::Spin => {
+                let deadline = self.now_nanos() + wait.as_nanos() as i64;
+                while self.now_nanos() < deadline {
+                    std::hint::spin_loop();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Debug for UnitClock {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UnitClock")
+            .field("nano_clock", &self.clock.is_some())
+            .field("time_unit", &self.time_unit)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Clock, ManualClock, MonotonicNanoClock, NanoClock, SystemClock, SystemNanoClock, TimeSource};
+    use crate::{Error, IDParts, Settings, BIT_LEN_TIME, FLAKE_TIME_UNIT};
+    use chrono::{DateTime, TimeZone, Utc};
+    use std::sync::atomic::{AtomicI64, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_nano_clock() {
+        let start_time = Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap();
+        let start_nanos = start_time.timestamp_nanos_opt().unwrap();
+        let now = Arc::new(AtomicI64::new(start_nanos + 12345 * FLAKE_TIME_UNIT + 42));
+
+        let sf = Settings::new()
+            .set_start_time(start_time)
+            .set_machine_id_value(1)
+            .set_nano_clock(Box::new(ManualClock(now.clone())))
+            .into_sonyflake()
+            .unwrap();
+
+        let parts = IDParts::decompose(sf.next_id().unwrap());
+        assert_eq!((parts.get_time(), parts.get_sequence()), (12345, 0));
+        let parts = IDParts::decompose(sf.next_id().unwrap());
+        assert_eq!((parts.get_time(), parts.get_sequence()), (12345, 1));
+
+        now.fetch_add(3 * FLAKE_TIME_UNIT, Ordering::SeqCst);
+        let parts = IDParts::decompose(sf.next_id().unwrap());
+        assert_eq!((parts.get_time(), parts.get_sequence()), (12348, 0));
+    }
+
+    struct MockDateTimeClock(Arc<AtomicI64>);
+
+    impl Clock for MockDateTimeClock {
+        fn now(&self) -> DateTime<Utc> {
+            Utc.timestamp_nanos(self.0.load(Ordering::SeqCst))
+        }
+    }
+
+    #[test]
+    fn test_clock() {
+        let start_time = Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap();
+        let start_nanos = start_time.timestamp_nanos_opt().unwrap();
+        let now = Arc::new(AtomicI64::new(start_nanos - FLAKE_TIME_UNIT));
+        let settings = || {
+            Settings::new()
+                .set_start_time(start_time)
+                .set_machine_id_value(1)
+                .set_clock(Box::new(MockDateTimeClock(now.clone())))
+        };
+
+        // the start time is checked against the clock, not the system time
+        assert!(matches!(
+            settings().into_sonyflake().unwrap_err(),
+            Error::StartTimeAheadOfCurrentTime(time) if time == start_time
+        ));
+
+        now.store(start_nanos + 7 * FLAKE_TIME_UNIT, Ordering::SeqCst);
+        let sf = settings().into_sonyflake().unwrap();
+        let infallible = settings().into_infallible_sonyflake().unwrap();
+        assert_eq!(IDParts::decompose(sf.next_id().unwrap()).get_time(), 7);
+        assert_eq!(IDParts::decompose(infallible.next_id()).get_time(), 7);
+
+        // the time bits overflow exactly at the end of the lifetime
+        now.store(start_nanos + (1 << BIT_LEN_TIME) * FLAKE_TIME_UNIT, Ordering::SeqCst);
+        assert!(matches!(sf.next_id().unwrap_err(), Error::TimeOverflow));
+
+        // the infallible generator starts over from the current time
+        let parts = IDParts::decompose(infallible.next_id());
+        assert_eq!((parts.get_time(), parts.get_sequence()), (0, 0));
+        assert_eq!(infallible.start_time(), Utc.timestamp_nanos(now.load(Ordering::SeqCst)));
+        let parts = IDParts::decompose(infallible.next_id());
+        assert_eq!((parts.get_time(), parts.get_sequence()), (0, 1));
+    }
+
+    struct SecondsClock(Arc<AtomicI64>);
+
+    impl Clock for SecondsClock {
+        fn now(&self) -> DateTime<Utc> {
+            Utc.timestamp_opt(self.0.load(Ordering::SeqCst), 0).unwrap()
+        }
+    }
+
+    #[test]
+    fn test_clock_out_of_range() {
+        let now = Arc::new(AtomicI64::new(Utc::now().timestamp()));
+        let settings = || {
+            Settings::new()
+                .set_machine_id_value(1)
+                .set_clock(Box::new(SecondsClock(now.clone())))
+        };
+        let sf = settings().into_sonyflake().unwrap();
+        let infallible = settings().into_infallible_sonyflake().unwrap();
+        sf.next_id().unwrap();
+
+        // times outside of 1677 to 2262 are errors, not panics
+        now.store(Utc.with_ymd_and_hms(3000, 1, 1, 0, 0, 0).unwrap().timestamp(), Ordering::SeqCst);
+        assert!(matches!(sf.next_id().unwrap_err(), Error::TimeOverflow));
+        infallible.next_id();
+        now.store(Utc.with_ymd_and_hms(1500, 1, 1, 0, 0, 0).unwrap().timestamp(), Ordering::SeqCst);
+        assert!(matches!(sf.next_id().unwrap_err(), Error::ClockMovedBackwards(_)));
+    }
+
+    #[test]
+    fn test_system_clock() {
+        let before = Utc::now();
+        let now = SystemClock.now();
+        assert!(now >= before && now - before < chrono::Duration::seconds(1));
+    }
+
+    #[test]
+    fn test_system_nano_clock() {
+        let before = Utc::now().timestamp_nanos_opt().unwrap();
+        let now = SystemNanoClock.now_nanos();
+        assert!(now >= before && now - before < 1_000_000_000);
+    }
+
+    #[test]
+    fn test_monotonic_time_source() {
+        let clock = MonotonicNanoClock::new();
+        let reads = (0..1000).map(|_| clock.now_nanos()).collect::<Vec<_>>();
+        assert!(reads.windows(2).all(|w| w[0] <= w[1]));
+        assert!((clock.now_nanos() - Utc::now().timestamp_nanos_opt().unwrap()).abs() < 1_000_000_000);
+
+        let sf = Settings::new()
+            .set_machine_id_value(5)
+            .set_time_source(TimeSource::Monotonic)
+            .into_sonyflake()
+            .unwrap();
+        let ids = (0..1000).map(|_| sf.next_id().unwrap()).collect::<Vec<_>>();
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+        let decoded = sf.timestamp_of(ids[0]);
+        assert!((Utc::now() - decoded).abs() < chrono::Duration::seconds(10));
+    }
+}
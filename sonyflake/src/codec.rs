@@ -0,0 +1,276 @@
+use crate::Error;
+#[cfg(not(feature = "std"))]
+use alloc::{format, string::{String, ToString}, vec::Vec};
+
+/// `IdCodec` converts an ID to and from a string, see [`SonyFlake::next_encoded`].
+///
+/// The crate ships [`DecimalCodec`], [`HexCodec`], [`Base62Codec`] and [`Base32Codec`];
+/// implement the trait to plug in any other encoding.
+///
+/// [`SonyFlake::next_encoded`]: struct.SonyFlake.html#method.next_encoded
+/// [`DecimalCodec`]: struct.DecimalCodec.html
+/// [`HexCodec`]: struct.HexCodec.html
+/// [`Base62Codec`]: struct.Base62Codec.html
+/// [`Base32Codec`]: struct.Base32Codec.html
+pub trait IdCodec {
+    /// `encode` returns the string form of the ID.
+    fn encode(&self, id: u64) -> String;
+
+    /// `decode` parses the string form of an ID back to the ID.
+    /// Returns `Error::InvalidEncodedID` if the string is not a valid encoding.
+    fn decode(&self, s: &str) -> Result<u64, Error>;
+}
+
+/// `DecimalCodec` encodes an ID as a base 10 number.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct DecimalCodec;
+
+impl IdCodec for DecimalCodec {
+    fn encode(&self, id: u64) -> String {
+        id.to_string()
+    }
+
+    fn decode(&self, s: &str) -> Result<u64, Error> {
+        decode_radix(s, 10, |c| c.to_digit(10))
+    }
+}
+
+/// `HexCodec` encodes an ID as a lowercase base 16 number.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct HexCodec;
+
+impl IdCodec for HexCodec {
+    fn encode(&self, id: u64) -> String {
+        format!("{:x}", id)
+    }
+
+    fn decode(&self, s: &str) -> Result<u64, Error> {
+        decode_radix(s, 16, |c| c.to_digit(16))
+    }
+}
+
+const BASE62_ALPHABET: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// `Base62Codec` encodes an ID with the digits `0-9`, `A-Z` and `a-z`, in that order.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Base62Codec;
+
+impl IdCodec for Base62Codec {
+    fn encode(&self, id: u64) -> String {
+        encode_radix(id, BASE62_ALPHABET)
+    }
+
+    fn decode(&self, s: &str) -> Result<u64, Error> {
+        decode_radix(s, 62, |c| match c {
+            '0'..='9' => Some(c as u32 - '0' as u32),
+            'A'..='Z' => Some(c as u32 - 'A' as u32 + 10),
+            'a'..='z' => Some(c as u32 - 'a' as u32 + 36),
+            _ => None,
+        })
+    }
+}
+
+/// `encode_base62` encodes an ID as a short, URL-safe base62 string, see [`Base62Codec`].
+///
+/// [`Base62Codec`]: struct.Base62Codec.html
+pub fn encode_base62(id: u64) -> String {
+    Base62Codec.encode(id)
+}
+
+/// `decode_base62` parses a base62 string produced by [`encode_base62`] back to the ID.
+/// Returns `Error::InvalidEncodedID` if the string is empty, holds a character outside of the
+/// base62 alphabet, or overflows 64 bits.
+///
+/// [`encode_base62`]: fn.encode_base62.html
+pub fn decode_base62(s: &str) -> Result<u64, Error> {
+    Base62Codec.decode(s)
+}
+
+const CROCKFORD_ALPHABET: &[u8] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// `Base32Codec` encodes an ID with [Crockford's base 32](https://www.crockford.com/base32.html).
+/// Decoding is case-insensitive, and reads `I`/`L` as `1` and `O` as `0`.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Base32Codec;
+
+impl IdCodec for Base32Codec {
+    fn encode(&self, id: u64) -> String {
+        encode_radix(id, CROCKFORD_ALPHABET)
+    }
+
+    fn decode(&self, s: &str) -> Result<u64, Error> {
+        decode_radix(s, 32, |c| match c.to_ascii_uppercase() {
+            'O' => Some(0),
+            'I' | 'L' => Some(1),
+            c => CROCKFORD_ALPHABET.iter().position(|d| *d as char == c).map(|d| d as u32),
+        })
+    }
+}
+
+/// `encode_with_checksum` encodes an ID with [`Base32Codec`] followed by two check symbols of the same
+/// alphabet holding a CRC-8 of the ID, for IDs typed by humans or read from lossy channels, e.g. in support
+/// tickets or QR codes. [`decode_with_checksum`] catches any single mistyped symbol.
+///
+/// ```rust
+/// use infallible_sonyflake::{decode_with_checksum, encode_with_checksum};
+///
+/// let encoded = encode_with_checksum(123456789);
+/// assert_eq!(decode_with_checksum(&encoded).unwrap(), 123456789);
+///
+/// // a typo in the first symbol
+/// let mistyped = format!("{}{}", if encoded.starts_with('4') { '5' } else { '4' }, &encoded[1..]);
+/// assert!(decode_with_checksum(&mistyped).is_err());
+/// ```
+///
+/// [`Base32Codec`]: struct.Base32Codec.html
+/// [`decode_with_checksum`]: fn.decode_with_checksum.html
+pub fn encode_with_checksum(id: u64) -> String {
+    let crc = crc8(id);
+    let mut encoded = Base32Codec.encode(id);
+    encoded.push(CROCKFORD_ALPHABET[(crc >> 5) as usize] as char);
+    encoded.push(CROCKFORD_ALPHABET[(crc & 0x1f) as usize] as char);
+    encoded
+}
+
+/// `decode_with_checksum` parses a string produced by [`encode_with_checksum`] back to the ID.
+/// Returns `Error::InvalidEncodedID` if the string is not valid base 32 with two check symbols,
+/// and `Error::ChecksumMismatch` if the check symbols do not match the ID, e.g. after a typo.
+///
+/// [`encode_with_checksum`]: fn.encode_with_checksum.html
+pub fn decode_with_checksum(s: &str) -> Result<u64, Error> {
+    let split = s.char_indices().rev().nth(1).map(|(i, _)| i).unwrap_or(0);
+    if split == 0 {
+        return Err(Error::InvalidEncodedID(s.to_string()));
+    }
+    let (id, check) = s.split_at(split);
+    let id = Base32Codec.decode(id).map_err(|_| Error::InvalidEncodedID(s.to_string()))?;
+    let check = Base32Codec.decode(check).map_err(|_| Error::InvalidEncodedID(s.to_string()))?;
+    if check != crc8(id) as u64 {
+        return Err(Error::ChecksumMismatch(s.to_string()));
+    }
+    Ok(id)
+}
+
+/// CRC-8 with the polynomial `x^8 + x^2 + x + 1` of the big-endian bytes of the ID
+fn crc8(id: u64) -> u8 {
+    id.to_be_bytes().iter().fold(0, |crc, byte| {
+        (0..8).fold(crc ^ byte, |crc, _| if crc & 0x80 != 0 { crc << 1 ^ 0x07 } else { crc << 1 })
+    })
+}
+
+fn encode_radix(mut id: u64, alphabet: &[u8]) -> String {
+    let radix = alphabet.len() as u64;
+    let mut digits = Vec::new();
+    loop {
+        digits.push(alphabet[(id % radix) as usize]);
+        id /= radix;
+        if id == 0 {
+            break;
+        }
+    }
+    digits.reverse();
+    String::from_utf8(digits).unwrap()
+}
+
+fn decode_radix(s: &str, radix: u64, digit: impl Fn(char) -> Option<u32>) -> Result<u64, Error> {
+    if s.is_empty() {
+        return Err(Error::InvalidEncodedID(s.to_string()));
+    }
+
+    s.chars().try_fold(0u64, |id, c| {
+        digit(c)
+            .and_then(|d| id.checked_mul(radix)?.checked_add(d as u64))
+            .ok_or_else(|| Error::InvalidEncodedID(s.to_string()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        decode_base62, decode_with_checksum, encode_base62, encode_with_checksum, Base32Codec, Base62Codec, DecimalCodec,
+        HexCodec, IdCodec,
+    };
+    use crate::{Error, Settings};
+
+    /// a custom codec: the decimal form, reversed
+    struct ReversedCodec;
+
+    impl IdCodec for ReversedCodec {
+        fn encode(&self, id: u64) -> String {
+            id.to_string().chars().rev().collect()
+        }
+
+        fn decode(&self, s: &str) -> Result<u64, Error> {
+            s.chars()
+                .rev()
+                .collect::<String>()
+                .parse()
+                .map_err(|_| Error::InvalidEncodedID(s.to_string()))
+        }
+    }
+
+    #[test]
+    fn test_codecs() {
+        let codecs: Vec<&dyn IdCodec> = vec![&DecimalCodec, &HexCodec, &Base62Codec, &Base32Codec, &ReversedCodec];
+        for codec in codecs {
+            for id in [0, 1, 61, 62, 1 << 40, u64::MAX] {
+                assert_eq!(codec.decode(&codec.encode(id)).unwrap(), id);
+            }
+            assert!(matches!(codec.decode("").unwrap_err(), Error::InvalidEncodedID(_)));
+            assert!(matches!(codec.decode("#").unwrap_err(), Error::InvalidEncodedID(_)));
+        }
+
+        assert_eq!(HexCodec.encode(255), "ff");
+        assert_eq!(Base62Codec.encode(61), "z");
+        assert_eq!(Base32Codec.encode(31), "Z");
+        assert_eq!(Base32Codec.decode("oIl").unwrap(), 0b00001_00001);
+        // one digit more than u64::MAX
+        assert!(Base62Codec.decode(&format!("{}0", Base62Codec.encode(u64::MAX))).is_err());
+    }
+
+    #[test]
+    fn test_base62() {
+        let id = 1 << 50 | 12345;
+        let encoded = encode_base62(id);
+        assert!(encoded.chars().all(|c| c.is_ascii_alphanumeric()));
+        assert_eq!(decode_base62(&encoded).unwrap(), id);
+        assert!(matches!(decode_base62("abc-").unwrap_err(), Error::InvalidEncodedID(_)));
+        assert!(matches!(decode_base62("zzzzzzzzzzzz").unwrap_err(), Error::InvalidEncodedID(_)));
+    }
+
+    #[test]
+    fn test_checksum() {
+        for id in [0, 1, 31, 32, 1 << 40, u64::MAX] {
+            let encoded = encode_with_checksum(id);
+            assert_eq!(decode_with_checksum(&encoded).unwrap(), id);
+            assert_eq!(decode_with_checksum(&encoded.to_lowercase()).unwrap(), id);
+        }
+
+        // every single mistyped symbol is caught
+        let encoded = encode_with_checksum(1 << 50 | 12345);
+        for (i, c) in encoded.char_indices() {
+            for typo in "0123456789ABCDEFGHJKMNPQRSTVWXYZ".chars().filter(|typo| *typo != c) {
+                let mut mistyped = encoded.clone();
+                mistyped.replace_range(i..i + 1, &typo.to_string());
+                assert!(decode_with_checksum(&mistyped).is_err(), "{} was accepted", mistyped);
+            }
+        }
+        let mistyped = format!("2{}", &encoded[1..]);
+        assert!(matches!(decode_with_checksum(&mistyped).unwrap_err(), Error::ChecksumMismatch(s) if s == mistyped));
+
+        for invalid in ["", "0", "00", "#00", "0#0"] {
+            assert!(matches!(decode_with_checksum(invalid).unwrap_err(), Error::InvalidEncodedID(_)));
+        }
+    }
+
+    #[test]
+    fn test_next_encoded() {
+        let sf = Settings::new()
+            .set_machine_id_value(3)
+            .into_sonyflake()
+            .unwrap();
+        let encoded = sf.next_encoded(&ReversedCodec).unwrap();
+        let id = ReversedCodec.decode(&encoded).unwrap();
+        assert!(sf.next_id().unwrap() > id);
+    }
+}
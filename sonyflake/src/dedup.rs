@@ -0,0 +1,108 @@
+use crate::{Error, SonyFlake};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+/// `DedupSonyFlake` makes id generation idempotent per request, for at-least-once pipelines where
+/// the same request may ask for an id more than once.
+///
+/// It remembers the ids handed out for the most recent `capacity` request ids in an LRU cache, and
+/// returns the cached id when a request id repeats. Once a request id is evicted, a repeat of it
+/// gets a fresh id, so size the cache for the window in which retries are expected.
+#[derive(Debug)]
+pub struct DedupSonyFlake {
+    sf: SonyFlake,
+    capacity: usize,
+    /// the id and the last use of each cached request id
+    ids: HashMap<Arc<str>, (u64, u64)>,
+    /// request ids by their last use, from least to most recently used
+    recent: BTreeMap<u64, Arc<str>>,
+    /// the stamp of the next use, increasing with every request
+    next_stamp: u64,
+}
+
+impl DedupSonyFlake {
+    /// Create a new `DedupSonyFlake` generating ids with `sf`, remembering up to `capacity` request ids.
+    pub fn new(sf: SonyFlake, capacity: usize) -> Self {
+        Self {
+            sf,
+            capacity: capacity.max(1),
+            ids: HashMap::with_capacity(capacity),
+            recent: BTreeMap::new(),
+            next_stamp: 0,
+        }
+    }
+
+    /// Returns the id generated for `request_id`, generating a new one unless the request id is cached.
+    pub fn next_id_for_request(&mut self, request_id: &str) -> Result<u64, Error> {
+        let stamp = self.next_stamp;
+
+        if let Some((id, last_used)) = self.ids.get_mut(request_id) {
+            if let Some(key) = self.recent.remove(last_used) {
+                self.recent.insert(stamp, key);
+            }
+            *last_used = stamp;
+            self.next_stamp += 1;
+            return Ok(*id);
+        }
+
+        let id = self.sf.next_id()?;
+        if self.ids.len() == self.capacity {
+            if let Some((_, evicted)) = self.recent.pop_first() {
+                self.ids.remove(&evicted);
+            }
+        }
+        let key: Arc<str> = Arc::from(request_id);
+        self.recent.insert(stamp, key.clone());
+        self.ids.insert(key, (id, stamp));
+        self.next_stamp += 1;
+        Ok(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DedupSonyFlake;
+    use crate::Settings;
+
+    #[test]
+    fn test_dedup_sonyflake() {
+        let sf = Settings::new()
+            .set_machine_id_value(1)
+            .into_sonyflake()
+            .unwrap();
+        let mut dedup = DedupSonyFlake::new(sf, 2);
+
+        let a = dedup.next_id_for_request("req-a").unwrap();
+        assert_eq!(dedup.next_id_for_request("req-a").unwrap(), a);
+        let b = dedup.next_id_for_request("req-b").unwrap();
+        assert_ne!(a, b);
+
+        // req-a was used more recently than req-b, so req-b is evicted first
+        assert_eq!(dedup.next_id_for_request("req-a").unwrap(), a);
+        let c = dedup.next_id_for_request("req-c").unwrap();
+        assert_eq!(dedup.next_id_for_request("req-a").unwrap(), a);
+        assert_eq!(dedup.next_id_for_request("req-c").unwrap(), c);
+        assert!(dedup.next_id_for_request("req-b").unwrap() > c);
+        assert_eq!((dedup.ids.len(), dedup.recent.len()), (2, 2));
+    }
+
+    #[test]
+    fn test_dedup_sonyflake_lru_order() {
+        let sf = Settings::new().set_machine_id_value(1).into_sonyflake().unwrap();
+        let mut dedup = DedupSonyFlake::new(sf, 100);
+
+        let ids = (0..100).map(|i| dedup.next_id_for_request(&format!("req-{}", i)).unwrap()).collect::<Vec<_>>();
+        // touch the even request ids, so the odd ones are the least recently used
+        for i in (0..100).step_by(2) {
+            assert_eq!(dedup.next_id_for_request(&format!("req-{}", i)).unwrap(), ids[i]);
+        }
+        for i in 100..150 {
+            dedup.next_id_for_request(&format!("req-{}", i)).unwrap();
+        }
+        for i in (0..100).step_by(2) {
+            assert_eq!(dedup.next_id_for_request(&format!("req-{}", i)).unwrap(), ids[i]);
+        }
+        assert!(dedup.next_id_for_request("req-1").unwrap() > ids[99]);
+        assert_eq!((dedup.ids.len(), dedup.recent.len()), (100, 100));
+    }
+}
@@ -0,0 +1,75 @@
+use crate::MachineID;
+use std::env::{self, VarError};
+
+/// `EnvMachineID` is a [`MachineID`] reading the machine id from an environment variable, e.g. a stable
+/// node index injected by an orchestrator such as Kubernetes or Nomad.
+///
+/// The variable must hold a decimal `u16`. If it is missing or does not parse, the generator is not created
+/// and `Error::MachineIdFailed` tells which variable is at fault.
+///
+/// ```rust
+/// use infallible_sonyflake::{EnvMachineID, Settings};
+///
+/// std::env::set_var("NODE_INDEX", "42");
+/// let sf = Settings::new()
+///     .set_machine_id(Box::new(EnvMachineID::new("NODE_INDEX")))
+///     .into_sonyflake()
+///     .unwrap();
+/// assert_eq!(sf.machine_id(), 42);
+/// ```
+///
+/// [`MachineID`]: trait.MachineID.html
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct EnvMachineID {
+    var_name: String,
+}
+
+impl EnvMachineID {
+    /// Create a new `EnvMachineID` reading the environment variable `var_name`.
+    pub fn new(var_name: &str) -> Self {
+        Self {
+            var_name: var_name.to_string(),
+        }
+    }
+}
+
+impl MachineID for EnvMachineID {
+    fn machine_id(&mut self) -> Result<u16, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        let value = env::var(&self.var_name).map_err(|e| match e {
+            VarError::NotPresent => format!("the environment variable {} is not set", self.var_name),
+            VarError::NotUnicode(_) => format!("the environment variable {} is not valid unicode", self.var_name),
+        })?;
+        value.trim().parse().map_err(|e| {
+            format!("cannot parse the environment variable {}={:?} as a machine id: {}", self.var_name, value, e).into()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EnvMachineID;
+    use crate::{Error, Settings};
+
+    #[test]
+    fn test_env_machine_id() {
+        std::env::set_var("SONYFLAKE_TEST_MACHINE_ID", "1234");
+        let sf = Settings::new()
+            .set_machine_id(Box::new(EnvMachineID::new("SONYFLAKE_TEST_MACHINE_ID")))
+            .into_infallible_sonyflake()
+            .unwrap();
+        assert_eq!(sf.machine_id(), 1234);
+
+        let err = Settings::new()
+            .set_machine_id(Box::new(EnvMachineID::new("SONYFLAKE_TEST_MISSING_MACHINE_ID")))
+            .into_sonyflake()
+            .unwrap_err();
+        assert!(matches!(&err, Error::MachineIdFailed(e) if e.to_string().contains("SONYFLAKE_TEST_MISSING_MACHINE_ID")));
+
+        std::env::set_var("SONYFLAKE_TEST_BAD_MACHINE_ID", "70000");
+        let err = Settings::new()
+            .set_machine_id(Box::new(EnvMachineID::new("SONYFLAKE_TEST_BAD_MACHINE_ID")))
+            .into_sonyflake()
+            .unwrap_err();
+        assert!(matches!(&err, Error::MachineIdFailed(e) if e.to_string().contains("\"70000\"")));
+    }
+}
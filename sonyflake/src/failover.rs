@@ -0,0 +1,86 @@
+use crate::{Error, SonyFlake};
+
+/// `FailoverSonyFlake` serves ids from a primary generator, and switches over to a secondary one
+/// for good once the primary's time overflows.
+///
+/// The secondary must use a different machine id, and would usually have a later start time, so it
+/// serves ids from a fresh epoch. Unlike `InfallibleSonyFlake`, which rebases its own start time, the
+/// ids of the two generators can never collide.
+#[derive(Debug, Clone)]
+pub struct FailoverSonyFlake {
+    primary: SonyFlake,
+    secondary: SonyFlake,
+    failed_over: bool,
+}
+
+impl FailoverSonyFlake {
+    /// Create a new `FailoverSonyFlake`. Returns `Error::InvalidMachineID` if the secondary
+    /// uses the same machine id as the primary.
+    pub fn new(primary: SonyFlake, secondary: SonyFlake) -> Result<Self, Error> {
+        if primary.machine_id == secondary.machine_id {
+            return Err(Error::InvalidMachineID(secondary.machine_id));
+        }
+
+        Ok(Self {
+            primary,
+            secondary,
+            failed_over: false,
+        })
+    }
+
+    /// Generate the next unique id from whichever generator is healthy.
+    pub fn next_id(&mut self) -> Result<u64, Error> {
+        if !self.failed_over {
+            match self.primary.next_id() {
+                Err(Error::TimeOverflow) => self.failed_over = true,
+                result => return result,
+            }
+        }
+        self.secondary.next_id()
+    }
+
+    /// Returns whether the primary has overflowed and ids are served by the secondary.
+    pub fn is_failed_over(&self) -> bool {
+        self.failed_over
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FailoverSonyFlake;
+    use crate::{Error, IDParts, Settings, BIT_LEN_TIME};
+    use std::time::Duration;
+
+    #[test]
+    fn test_failover_sonyflake() {
+        let primary = Settings::new()
+            .set_machine_id_value(1)
+            // the overflow is simulated by moving the state, not the clock
+            .set_clock_regression_tolerance(Duration::MAX)
+            .into_sonyflake()
+            .unwrap();
+        let secondary = Settings::new()
+            .set_machine_id_value(2)
+            .into_sonyflake()
+            .unwrap();
+        let mut sf = FailoverSonyFlake::new(primary.clone(), secondary).unwrap();
+
+        assert_eq!(IDParts::decompose(sf.next_id().unwrap()).get_machine_id(), 1);
+        assert!(!sf.is_failed_over());
+
+        primary.inner.lock().elapsed_time = 1 << BIT_LEN_TIME;
+        for _ in 0..10 {
+            assert_eq!(IDParts::decompose(sf.next_id().unwrap()).get_machine_id(), 2);
+        }
+        assert!(sf.is_failed_over());
+
+        let same = Settings::new()
+            .set_machine_id_value(1)
+            .into_sonyflake()
+            .unwrap();
+        assert!(matches!(
+            FailoverSonyFlake::new(primary, same).unwrap_err(),
+            Error::InvalidMachineID(1)
+        ));
+    }
+}
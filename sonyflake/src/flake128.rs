@@ -0,0 +1,224 @@
+#[cfg(feature = "std")]
+use crate::{Error, MachineID, MachineIDChecker, Settings, UnitClock};
+#[cfg(feature = "std")]
+use parking_lot::Mutex;
+#[cfg(feature = "std")]
+use std::sync::Arc;
+
+/// bit length of time of a 128-bit id
+const BIT_LEN_TIME_128: u32 = 55;
+
+/// bit length of sequence number of a 128-bit id
+const BIT_LEN_SEQUENCE_128: u32 = 24;
+
+/// bit length of machine id of a 128-bit id
+const BIT_LEN_MACHINE_ID_128: u32 = 127 - BIT_LEN_TIME_128 - BIT_LEN_SEQUENCE_128;
+
+/// 1 msec
+#[cfg(feature = "std")]
+const FLAKE_TIME_UNIT_128: i64 = 1_000_000;
+
+/// SonyFlake128 is a unique ID generator producing 128-bit ids, for users who can afford UUID-sized
+/// ids and need far more machines or per-window throughput than 63 bits allow.
+///
+/// A SonyFlake128 ID is composed of
+///
+/// - 55 bits for time in units of 1 msec
+/// - 24 bits for a sequence number
+/// - 48 bits for a machine id
+///
+/// and the most significant bit is always zero, so the ids also fit an `i128` and sort by time like SonyFlake's:
+///
+/// - The lifetime is over a million years from the start time
+/// - It can work in 2^48 machines, e.g. with a MAC address as the machine id, see [`with_machine_id`]
+/// - It can generate 2^24 IDs per msec at most in a single machine
+///
+/// [`with_machine_id`]: struct.SonyFlake128.html#method.with_machine_id
+#[derive(Debug, Clone)]
+#[cfg(feature = "std")]
+pub struct SonyFlake128 {
+    start_time: i64,
+    machine_id: u64,
+    inner: Arc<Mutex<Inner128>>,
+}
+
+/// the state of a `SonyFlake128`, whose sequence is wider than the `u16` of `Inner`
+#[derive(Debug)]
+#[cfg(feature = "std")]
+struct Inner128 {
+    clock: UnitClock,
+    elapsed_time: i64,
+    sequence: u32,
+}
+
+#[cfg(feature = "std")]
+impl SonyFlake128 {
+    /// Create a new SonyFlake128 with the 16-bit machine id resolved from the settings.
+    pub fn new<M: MachineID, C: MachineIDChecker>(mut st: Settings<M, C>) -> Result<Self, Error> {
+        let start_time = st.get_start_time_in(FLAKE_TIME_UNIT_128)?;
+        let clock = UnitClock::new(st.nano_clock.take(), FLAKE_TIME_UNIT_128);
+        let machine_id = st.get_and_check_machine_id()?;
+        Ok(Self::with_state(start_time, clock, machine_id as u64))
+    }
+
+    /// Create a new SonyFlake128 with a machine id of up to 48 bits, ignoring the machine id and
+    /// checker of the settings. Fails with `Error::MachineIdFailed` if `machine_id` does not fit.
+    pub fn with_machine_id<M: MachineID, C: MachineIDChecker>(mut st: Settings<M, C>, machine_id: u64) -> Result<Self, Error> {
+        if machine_id >> BIT_LEN_MACHINE_ID_128 != 0 {
+            return Err(Error::MachineIdFailed(
+                format!("the machine id {} does not fit in {} bits", machine_id, BIT_LEN_MACHINE_ID_128).into(),
+            ));
+        }
+        let start_time = st.get_start_time_in(FLAKE_TIME_UNIT_128)?;
+        let clock = UnitClock::new(st.nano_clock.take(), FLAKE_TIME_UNIT_128);
+        Ok(Self::with_state(start_time, clock, machine_id))
+    }
+
+    fn with_state(start_time: i64, clock: UnitClock, machine_id: u64) -> Self {
+        Self {
+            start_time,
+            machine_id,
+            inner: Arc::new(Mutex::new(Inner128 {
+                clock,
+                elapsed_time: 0,
+                sequence: 0,
+            })),
+        }
+    }
+
+    /// Generate the next unique 128-bit id.
+    /// After the SonyFlake128 time overflows, next_id returns an error.
+    pub fn next_id(&self) -> Result<u128, Error> {
+        let mask_sequence = (1 << BIT_LEN_SEQUENCE_128) - 1;
+
+        let mut inner = self.inner.lock();
+
+        let current = inner.clock.elapsed_time(self.start_time);
+
+        if inner.elapsed_time < current {
+            inner.elapsed_time = current;
+            inner.sequence = 0;
+        } else {
+            // self.elapsed_time >= current
+            inner.sequence = (inner.sequence + 1) & mask_sequence;
+            if inner.sequence == 0 {
+                inner.elapsed_time += 1;
+                let overtime = inner.elapsed_time - current;
+                std::thread::sleep(inner.clock.sleep_time(overtime));
+            }
+        }
+
+        if inner.elapsed_time >= 1 << BIT_LEN_TIME_128 {
+            return Err(Error::TimeOverflow);
+        }
+
+        Ok((inner.elapsed_time as u128) << (BIT_LEN_SEQUENCE_128 + BIT_LEN_MACHINE_ID_128)
+            | (inner.sequence as u128) << BIT_LEN_MACHINE_ID_128
+            | self.machine_id as u128)
+    }
+
+    /// Returns the machine id of the generator.
+    pub fn machine_id(&self) -> u64 {
+        self.machine_id
+    }
+}
+
+/// `IDParts128` contains the bit parts for a 128-bit ID.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct IDParts128 {
+    id: u128,
+    time: u64,
+    sequence: u32,
+    machine_id: u64,
+}
+
+impl IDParts128 {
+    /// `decompose` returns a set of SonyFlake128 ID parts.
+    pub fn decompose(id: u128) -> Self {
+        decompose128(id)
+    }
+
+    /// `get_id` returns the original ID
+    pub fn get_id(&self) -> u128 {
+        self.id
+    }
+
+    /// `get_time` returns a timestamp in msec since the start time
+    pub fn get_time(&self) -> u64 {
+        self.time
+    }
+
+    /// `get_sequence` returns sequence
+    pub fn get_sequence(&self) -> u32 {
+        self.sequence
+    }
+
+    /// `get_machine_id` returns the machine id
+    pub fn get_machine_id(&self) -> u64 {
+        self.machine_id
+    }
+}
+
+/// `decompose128` returns a set of SonyFlake128 ID parts.
+pub fn decompose128(id: u128) -> IDParts128 {
+    let mask_seq = ((1u128 << BIT_LEN_SEQUENCE_128) - 1) << BIT_LEN_MACHINE_ID_128;
+    let mask_machine_id = (1u128 << BIT_LEN_MACHINE_ID_128) - 1;
+
+    IDParts128 {
+        id,
+        time: (id >> (BIT_LEN_SEQUENCE_128 + BIT_LEN_MACHINE_ID_128)) as u64,
+        sequence: ((id & mask_seq) >> BIT_LEN_MACHINE_ID_128) as u32,
+        machine_id: (id & mask_machine_id) as u64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decompose128, SonyFlake128, BIT_LEN_TIME_128};
+    use crate::{Error, Settings};
+    use chrono::{Duration, Utc};
+
+    #[test]
+    fn test_sonyflake128() {
+        let start_time = Utc::now() - Duration::seconds(10);
+        let sf = Settings::new()
+            .set_start_time(start_time)
+            .set_machine_id_value(42)
+            .into_sonyflake128()
+            .unwrap();
+        assert_eq!(sf.machine_id(), 42);
+
+        let mut last_id = 0;
+        let mut max_sequence = 0;
+        for _ in 0..100_000 {
+            let id = sf.next_id().unwrap();
+            assert!(id > last_id);
+            last_id = id;
+
+            let parts = decompose128(id);
+            assert_eq!(parts.get_id(), id);
+            assert_eq!(parts.get_machine_id(), 42);
+            assert!(parts.get_time() >= 10_000);
+            max_sequence = max_sequence.max(parts.get_sequence());
+        }
+        // far more than the 256 ids of a SonyFlake window
+        assert!(max_sequence > 256);
+        assert_eq!(last_id >> 127, 0);
+    }
+
+    #[test]
+    fn test_sonyflake128_wide_machine_id() {
+        let mac = 0x0242_ac11_0002;
+        let sf = SonyFlake128::with_machine_id(Settings::new(), mac).unwrap();
+        let parts = decompose128(sf.next_id().unwrap());
+        assert_eq!(parts.get_machine_id(), mac);
+
+        assert!(matches!(
+            SonyFlake128::with_machine_id(Settings::new(), 1 << 48).unwrap_err(),
+            Error::MachineIdFailed(_)
+        ));
+
+        sf.inner.lock().elapsed_time = 1 << BIT_LEN_TIME_128;
+        assert!(matches!(sf.next_id().unwrap_err(), Error::TimeOverflow));
+    }
+}
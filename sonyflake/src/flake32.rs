@@ -0,0 +1,203 @@
+#[cfg(feature = "std")]
+use crate::{config_fingerprint, Error, Inner, MachineID, MachineIDChecker, Settings, UnitClock};
+#[cfg(feature = "std")]
+use parking_lot::Mutex;
+#[cfg(feature = "std")]
+use std::sync::Arc;
+
+/// bit length of time of a 32-bit id
+const BIT_LEN_TIME_32: u32 = 22;
+
+/// bit length of sequence number of a 32-bit id
+const BIT_LEN_SEQUENCE_32: u32 = 4;
+
+/// bit length of machine id of a 32-bit id
+const BIT_LEN_MACHINE_ID_32: u32 = 32 - BIT_LEN_TIME_32 - BIT_LEN_SEQUENCE_32;
+
+/// 1 sec
+#[cfg(feature = "std")]
+const FLAKE_TIME_UNIT_32: i64 = 1_000_000_000;
+
+/// SonyFlake32 is a unique ID generator producing 32-bit ids, for contexts which cannot afford 64-bit ids.
+///
+/// A SonyFlake32 ID is composed of
+///
+/// - 22 bits for time in units of 1 sec
+/// - 4 bits for a sequence number
+/// - 6 bits for a machine id
+///
+/// This is a much smaller id space than SonyFlake's, and it is only meant for short-lived or
+/// single-datacenter use:
+///
+/// - The lifetime is about 48 days from the start time, after which `next_id` returns `Error::TimeOverflow`.
+///   The default start time is long past that, so set a recent one with `Settings::set_start_time`
+/// - It can work in at most 2^6 machines, so the machine id must be below 64
+/// - It can generate 2^4 IDs per second at most in a single machine
+#[derive(Debug, Clone)]
+#[cfg(feature = "std")]
+pub struct SonyFlake32 {
+    start_time: i64,
+    machine_id: u16,
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[cfg(feature = "std")]
+impl SonyFlake32 {
+    /// Create a new SonyFlake32. The machine id resolved from the settings must fit in 6 bits.
+    pub fn new<M: MachineID, C: MachineIDChecker>(mut st: Settings<M, C>) -> Result<Self, Error> {
+        let start_time = st.get_start_time_in(FLAKE_TIME_UNIT_32)?;
+        let inner = Inner {
+            clock: Arc::new(UnitClock::new(st.nano_clock.take(), FLAKE_TIME_UNIT_32)),
+            ..Inner::default()
+        };
+
+        let machine_id = st.get_and_check_machine_id()?;
+        if machine_id >= 1 << BIT_LEN_MACHINE_ID_32 {
+            return Err(Error::InvalidMachineID(machine_id));
+        }
+
+        Ok(Self {
+            start_time,
+            machine_id,
+            inner: Arc::new(Mutex::new(inner)),
+        })
+    }
+
+    /// Generate the next unique 32-bit id.
+    /// After the SonyFlake32 time overflows, next_id returns an error.
+    pub fn next_id(&mut self) -> Result<u32, Error> {
+        let mask_sequence = (1 << BIT_LEN_SEQUENCE_32) - 1;
+
+        let mut inner = self.inner.lock();
+
+        let current = inner.clock.elapsed_time(self.start_time);
+
+        if inner.elapsed_time < current {
+            inner.elapsed_time = current;
+            inner.sequence = 0;
+        } else {
+            // self.elapsed_time >= current
+            inner.sequence = (inner.sequence + 1) & mask_sequence;
+            if inner.sequence == 0 {
+                inner.elapsed_time += 1;
+                let overtime = inner.elapsed_time - current;
+                std::thread::sleep(inner.clock.sleep_time(overtime));
+            }
+        }
+
+        if inner.elapsed_time >= 1 << BIT_LEN_TIME_32 {
+            return Err(Error::TimeOverflow);
+        }
+
+        Ok((inner.elapsed_time as u32) << (BIT_LEN_SEQUENCE_32 + BIT_LEN_MACHINE_ID_32)
+            | (inner.sequence as u32) << BIT_LEN_MACHINE_ID_32
+            | self.machine_id as u32)
+    }
+
+    /// Returns a fingerprint of the generator's stable configuration, see `SonyFlake::config_fingerprint`.
+    pub fn config_fingerprint(&self) -> u64 {
+        config_fingerprint(
+            self.start_time * FLAKE_TIME_UNIT_32,
+            BIT_LEN_TIME_32 as i64,
+            BIT_LEN_SEQUENCE_32 as i64,
+            BIT_LEN_MACHINE_ID_32 as i64,
+            FLAKE_TIME_UNIT_32,
+        )
+    }
+}
+
+/// `IDParts32` contains the bit parts for a 32-bit ID.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct IDParts32 {
+    id: u32,
+    time: u32,
+    sequence: u32,
+    machine_id: u32,
+}
+
+impl IDParts32 {
+    /// `decompose` returns a set of SonyFlake32 ID parts.
+    pub fn decompose(id: u32) -> Self {
+        decompose32(id)
+    }
+
+    /// `get_id` returns the original ID
+    pub fn get_id(&self) -> u32 {
+        self.id
+    }
+
+    /// `get_time` returns a timestamp in seconds since the start time
+    pub fn get_time(&self) -> u32 {
+        self.time
+    }
+
+    /// `get_sequence` returns sequence
+    pub fn get_sequence(&self) -> u32 {
+        self.sequence
+    }
+
+    /// `get_machine_id` returns the machine id
+    pub fn get_machine_id(&self) -> u32 {
+        self.machine_id
+    }
+}
+
+/// `decompose32` returns a set of SonyFlake32 ID parts.
+pub fn decompose32(id: u32) -> IDParts32 {
+    let mask_seq = ((1u32 << BIT_LEN_SEQUENCE_32) - 1) << BIT_LEN_MACHINE_ID_32;
+    let mask_machine_id = (1u32 << BIT_LEN_MACHINE_ID_32) - 1;
+
+    IDParts32 {
+        id,
+        time: id >> (BIT_LEN_SEQUENCE_32 + BIT_LEN_MACHINE_ID_32),
+        sequence: (id & mask_seq) >> BIT_LEN_MACHINE_ID_32,
+        machine_id: id & mask_machine_id,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decompose32, BIT_LEN_TIME_32};
+    use crate::{Error, Settings};
+    use chrono::{Duration, Utc};
+
+    #[test]
+    fn test_sonyflake32() {
+        let start_time = Utc::now() - Duration::seconds(10);
+        let mut sf = Settings::new()
+            .set_start_time(start_time)
+            .set_machine_id_value(42)
+            .into_sonyflake32()
+            .unwrap();
+
+        let mut last_id = 0;
+        for _ in 0..20 {
+            let id = sf.next_id().unwrap();
+            assert!(id > last_id);
+            last_id = id;
+
+            let parts = decompose32(id);
+            assert_eq!(parts.get_id(), id);
+            assert_eq!(parts.get_machine_id(), 42);
+            assert!(parts.get_time() >= 10);
+        }
+
+        assert!(matches!(
+            Settings::new()
+                .set_machine_id_value(64)
+                .into_sonyflake32()
+                .unwrap_err(),
+            Error::InvalidMachineID(64)
+        ));
+    }
+
+    #[test]
+    fn test_sonyflake32_overflow() {
+        let mut sf = Settings::new()
+            .set_machine_id_value(1)
+            .into_sonyflake32()
+            .unwrap();
+        sf.inner.lock().elapsed_time = 1 << BIT_LEN_TIME_32;
+        assert!(matches!(sf.next_id().unwrap_err(), Error::TimeOverflow));
+    }
+}
@@ -0,0 +1,156 @@
+use crate::{InfallibleSonyFlake, SonyFlake};
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+/// `Health` is a snapshot of a generator's state, taken by `SonyFlake::health` or `InfallibleSonyFlake::health`.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Health {
+    machine_id: u16,
+    remaining_lifetime: Duration,
+    ids_generated: u64,
+    future_drift: Duration,
+}
+
+impl Health {
+    pub(crate) fn new(
+        machine_id: u16,
+        elapsed_time: i64,
+        time_bits: i64,
+        time_unit: i64,
+        ids_generated: u64,
+        future_drift: Duration,
+    ) -> Self {
+        let remaining = ((1 << time_bits) - elapsed_time).max(0);
+        Self {
+            machine_id,
+            remaining_lifetime: Duration::from_nanos(remaining as u64 * time_unit as u64),
+            ids_generated,
+            future_drift,
+        }
+    }
+
+    /// `get_machine_id` returns the machine id of the generator
+    pub fn get_machine_id(&self) -> u16 {
+        self.machine_id
+    }
+
+    /// `get_remaining_lifetime` returns the time left until the time part of an ID overflows
+    pub fn get_remaining_lifetime(&self) -> Duration {
+        self.remaining_lifetime
+    }
+
+    /// `get_ids_generated` returns the number of IDs handed out since the generator was created
+    pub fn get_ids_generated(&self) -> u64 {
+        self.ids_generated
+    }
+
+    /// `get_future_drift` returns how far the generator's time is ahead of the clock
+    pub fn get_future_drift(&self) -> Duration {
+        self.future_drift
+    }
+}
+
+#[derive(Debug)]
+enum Member {
+    Fallible(SonyFlake),
+    Infallible(InfallibleSonyFlake),
+}
+
+impl Member {
+    fn health(&self) -> Health {
+        match self {
+            Member::Fallible(sf) => sf.health(),
+            Member::Infallible(sf) => sf.health(),
+        }
+    }
+}
+
+/// `FleetView` aggregates the [`Health`] of a set of generators, for a control plane monitoring many of them.
+///
+/// Registered generators share their state with the view, so every aggregate reflects the ids
+/// generated up to the moment it is computed.
+///
+/// [`Health`]: struct.Health.html
+#[derive(Debug, Default)]
+pub struct FleetView {
+    members: Vec<Member>,
+}
+
+impl FleetView {
+    /// Create an empty `FleetView`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a `SonyFlake` to the view.
+    pub fn register(&mut self, sf: &SonyFlake) -> &mut Self {
+        self.members.push(Member::Fallible(sf.clone()));
+        self
+    }
+
+    /// Add an `InfallibleSonyFlake` to the view.
+    pub fn register_infallible(&mut self, sf: &InfallibleSonyFlake) -> &mut Self {
+        self.members.push(Member::Infallible(sf.clone()));
+        self
+    }
+
+    /// Returns a `Health` snapshot of every registered generator, in registration order.
+    pub fn health(&self) -> Vec<Health> {
+        self.members.iter().map(Member::health).collect()
+    }
+
+    /// Returns the shortest remaining lifetime across the fleet, or `None` if no generator is registered.
+    pub fn min_remaining_lifetime(&self) -> Option<Duration> {
+        self.health().iter().map(Health::get_remaining_lifetime).min()
+    }
+
+    /// Returns the number of IDs generated by the whole fleet.
+    pub fn total_ids_generated(&self) -> u64 {
+        self.health().iter().map(Health::get_ids_generated).sum()
+    }
+
+    /// Returns the distinct machine ids used by the fleet.
+    pub fn machines_in_use(&self) -> BTreeSet<u16> {
+        self.health().iter().map(Health::get_machine_id).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FleetView;
+    use crate::Settings;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn test_fleet_view() {
+        let mut fleet = FleetView::new();
+        assert_eq!(fleet.min_remaining_lifetime(), None);
+
+        let old = Settings::new()
+            .set_start_time(Utc.with_ymd_and_hms(2000, 1, 1, 0, 0, 0).unwrap())
+            .set_machine_id_value(1)
+            .into_sonyflake()
+            .unwrap();
+        let recent = Settings::new()
+            .set_machine_id_value(2)
+            .into_sonyflake()
+            .unwrap();
+        let infallible = Settings::new()
+            .set_machine_id_value(2)
+            .into_infallible_sonyflake()
+            .unwrap();
+        fleet.register(&old).register(&recent).register_infallible(&infallible);
+
+        for _ in 0..10 {
+            old.next_id().unwrap();
+            recent.next_id().unwrap();
+            infallible.next_id();
+        }
+        recent.reserve_block(5).unwrap();
+
+        assert_eq!(fleet.machines_in_use().into_iter().collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(fleet.total_ids_generated(), 35);
+        assert_eq!(fleet.min_remaining_lifetime(), Some(old.health().get_remaining_lifetime()));
+        assert!(fleet.min_remaining_lifetime() < Some(recent.health().get_remaining_lifetime()));
+    }
+}
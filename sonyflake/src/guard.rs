@@ -0,0 +1,62 @@
+use crate::Error;
+use parking_lot::{const_mutex, Mutex};
+use std::collections::BTreeSet;
+
+/// the `(machine_id, start_time)` pairs of the live guarded generators
+static REGISTRY: Mutex<BTreeSet<(u16, i64)>> = const_mutex(BTreeSet::new());
+
+/// A machine id and start time claimed by a generator and its clones, released on drop.
+#[derive(Debug)]
+pub(crate) struct Registration {
+    machine_id: u16,
+    start_time: i64,
+}
+
+impl Registration {
+    pub(crate) fn new(machine_id: u16, start_time: i64) -> Result<Self, Error> {
+        if REGISTRY.lock().insert((machine_id, start_time)) {
+            Ok(Self { machine_id, start_time })
+        } else {
+            Err(Error::DuplicateMachineId(machine_id))
+        }
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        REGISTRY.lock().remove(&(self.machine_id, self.start_time));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Error, Settings};
+    use chrono::{TimeZone, Utc};
+
+    fn settings() -> Settings {
+        Settings::new()
+            .set_start_time(Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap())
+            .set_machine_id_value(4242)
+    }
+
+    #[test]
+    fn test_duplicate_machine_id() {
+        let sf = settings().guard_duplicate_machine_id().into_sonyflake().unwrap();
+        let clone = sf.clone();
+
+        let err = settings().guard_duplicate_machine_id().into_infallible_sonyflake().unwrap_err();
+        assert!(matches!(err, Error::DuplicateMachineId(4242)));
+        // generators which do not opt in are not checked
+        settings().into_sonyflake().unwrap();
+
+        drop(sf);
+        assert!(matches!(
+            settings().guard_duplicate_machine_id().into_sonyflake().unwrap_err(),
+            Error::DuplicateMachineId(4242)
+        ));
+
+        // the last clone releases the machine id
+        drop(clone);
+        settings().guard_duplicate_machine_id().into_sonyflake().unwrap();
+    }
+}
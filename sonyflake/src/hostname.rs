@@ -0,0 +1,82 @@
+use crate::{hash_to_machine_id_with_seed, MachineID};
+use gethostname::gethostname;
+
+/// `HostnameMachineID` is a [`MachineID`] hashing the system hostname into a 16-bit machine id,
+/// for hosts where the default, the lower 16 bits of the private IPv4 address, is unavailable or
+/// ambiguous, e.g. IPv6-only networks or containers behind a NAT.
+///
+/// Distinct hostnames may hash to the same machine id. If two hosts of a fleet collide, pick another
+/// seed with [`with_seed`]; every host of the fleet must use the same seed.
+///
+/// The hash spans all 16 bits, so with a [`BitLayout`] of fewer machine id bits, narrow it down with
+/// `Settings::set_machine_id_transform`.
+///
+/// ```rust
+/// use infallible_sonyflake::{HostnameMachineID, Settings};
+///
+/// let sf = Settings::new()
+///     .set_machine_id(Box::new(HostnameMachineID::new()))
+///     .into_sonyflake()
+///     .unwrap();
+/// ```
+///
+/// [`MachineID`]: trait.MachineID.html
+/// [`with_seed`]: struct.HostnameMachineID.html#method.with_seed
+/// [`BitLayout`]: struct.BitLayout.html
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct HostnameMachineID {
+    seed: u64,
+}
+
+impl HostnameMachineID {
+    /// Create a new `HostnameMachineID` with the seed 0.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a new `HostnameMachineID` mixing `seed` into the hash of the hostname.
+    pub fn with_seed(seed: u64) -> Self {
+        Self { seed }
+    }
+
+    /// Returns the machine id `hostname` hashes to.
+    pub fn machine_id_of(&self, hostname: &str) -> u16 {
+        hash_to_machine_id_with_seed(hostname.as_bytes(), self.seed)
+    }
+}
+
+impl MachineID for HostnameMachineID {
+    fn machine_id(&mut self) -> Result<u16, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        let hostname = gethostname();
+        let hostname = hostname.to_str().ok_or("the hostname is not valid UTF-8")?;
+        if hostname.is_empty() {
+            return Err("the hostname is empty".into());
+        }
+        Ok(self.machine_id_of(hostname))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HostnameMachineID;
+    use crate::{IDParts, Settings};
+    use gethostname::gethostname;
+
+    #[test]
+    fn test_hostname_machine_id() {
+        let machine_id = HostnameMachineID::with_seed(42);
+        assert_eq!(machine_id.machine_id_of("web-1"), machine_id.machine_id_of("web-1"));
+        assert_ne!(machine_id.machine_id_of("web-1"), machine_id.machine_id_of("web-2"));
+        assert_ne!(machine_id.machine_id_of("web-1"), HostnameMachineID::new().machine_id_of("web-1"));
+
+        let sf = Settings::new()
+            .set_machine_id(Box::new(machine_id))
+            .into_sonyflake()
+            .unwrap();
+        let hostname = gethostname();
+        assert_eq!(
+            IDParts::decompose(sf.next_id().unwrap()).get_machine_id(),
+            machine_id.machine_id_of(hostname.to_str().unwrap()) as u64
+        );
+    }
+}
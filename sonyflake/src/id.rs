@@ -0,0 +1,125 @@
+use crate::{DecimalCodec, Error, IdCodec};
+#[cfg(not(feature = "std"))]
+use alloc::string::ToString;
+use core::convert::TryFrom;
+use core::fmt::{Display, Formatter};
+use core::str::FromStr;
+
+/// `Id` is a SonyFlake ID, a `u64` whose most significant bit is 0, parsed from and printed as
+/// a decimal number, e.g. from the arguments of a CLI tool or a config file.
+///
+/// It keeps IDs apart from unrelated `u64`s in APIs and as map keys: it orders, hashes and serializes
+/// like the `u64`, and deserializing a `u64` with the most significant bit set fails.
+/// `SonyFlake::next_flake_id` generates IDs of this type.
+///
+/// ```rust
+/// use infallible_sonyflake::Id;
+///
+/// let id: Id = "123456789".parse().unwrap();
+/// assert_eq!(id.get(), 123456789);
+/// assert_eq!(id.to_string(), "123456789");
+///
+/// // the most significant bit is set
+/// assert!("9223372036854775808".parse::<Id>().is_err());
+/// ```
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Serialize, Deserialize)]
+#[serde(try_from = "u64", into = "u64")]
+pub struct Id(pub(crate) u64);
+
+impl Id {
+    /// Create a new `Id`. Returns `Error::InvalidEncodedID` if the most significant bit of `id` is set.
+    pub fn new(id: u64) -> Result<Self, Error> {
+        if id >> 63 != 0 {
+            return Err(Error::InvalidEncodedID(id.to_string()));
+        }
+        Ok(Self(id))
+    }
+
+    /// Returns the ID as a `u64`.
+    pub fn get(&self) -> u64 {
+        self.0
+    }
+}
+
+impl From<Id> for u64 {
+    fn from(id: Id) -> Self {
+        id.0
+    }
+}
+
+impl TryFrom<u64> for Id {
+    type Error = Error;
+
+    /// Returns `Error::InvalidEncodedID` if the most significant bit of `id` is set.
+    fn try_from(id: u64) -> Result<Self, Self::Error> {
+        Self::new(id)
+    }
+}
+
+impl FromStr for Id {
+    type Err = Error;
+
+    /// Parses a decimal ID. Returns `Error::InvalidEncodedID` if `s` is not a decimal `u64`
+    /// or its most significant bit is set.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::new(DecimalCodec.decode(s)?)
+    }
+}
+
+impl Display for Id {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Id;
+    use crate::{Error, Settings};
+    use std::collections::HashMap;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn test_id() {
+        let sf = Settings::new()
+            .set_machine_id_value(3)
+            .into_sonyflake()
+            .unwrap();
+        let raw = sf.next_id().unwrap();
+        let id: Id = raw.to_string().parse().unwrap();
+        assert_eq!(u64::from(id), raw);
+        assert_eq!(id.to_string(), raw.to_string());
+        assert_eq!(Id::new(raw).unwrap(), id);
+
+        assert!(matches!("".parse::<Id>().unwrap_err(), Error::InvalidEncodedID(_)));
+        assert!(matches!("12a".parse::<Id>().unwrap_err(), Error::InvalidEncodedID(_)));
+        assert!(matches!("18446744073709551616".parse::<Id>().unwrap_err(), Error::InvalidEncodedID(_)));
+        assert!(matches!(
+            (1u64 << 63).to_string().parse::<Id>().unwrap_err(),
+            Error::InvalidEncodedID(s) if s == (1u64 << 63).to_string()
+        ));
+    }
+
+    #[test]
+    fn test_flake_id() {
+        let sf = Settings::new()
+            .set_machine_id_value(3)
+            .into_sonyflake()
+            .unwrap();
+        let a = sf.next_flake_id().unwrap();
+        let b = sf.next_flake_id().unwrap();
+        assert!(a < b);
+        assert_eq!(Id::try_from(u64::from(a)).unwrap(), a);
+        assert!(matches!(Id::try_from(1u64 << 63).unwrap_err(), Error::InvalidEncodedID(_)));
+
+        let mut names = HashMap::new();
+        names.insert(a, "a");
+        names.insert(b, "b");
+        assert_eq!(names[&a], "a");
+
+        let json = serde_json::to_string(&a).unwrap();
+        assert_eq!(json, a.get().to_string());
+        assert_eq!(serde_json::from_str::<Id>(&json).unwrap(), a);
+        assert!(serde_json::from_str::<Id>(&(1u64 << 63).to_string()).is_err());
+    }
+}
@@ -0,0 +1,81 @@
+use crate::{Error, InfallibleSonyFlake, SonyFlake};
+
+/// `SonyFlakeIter` yields ids from a [`SonyFlake`] indefinitely, see [`SonyFlake::iter`].
+///
+/// It holds a clone of the generator, so its ids never collide with the ids of the generator or its other clones.
+/// Once the time overflows, every item is `Err(Error::TimeOverflow)`.
+///
+/// [`SonyFlake`]: struct.SonyFlake.html
+/// [`SonyFlake::iter`]: struct.SonyFlake.html#method.iter
+#[derive(Debug, Clone)]
+pub struct SonyFlakeIter {
+    sf: SonyFlake,
+}
+
+impl SonyFlakeIter {
+    pub(crate) fn new(sf: SonyFlake) -> Self {
+        Self { sf }
+    }
+}
+
+impl Iterator for SonyFlakeIter {
+    type Item = Result<u64, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.sf.next_id())
+    }
+}
+
+/// `InfallibleSonyFlakeIter` yields ids from an [`InfallibleSonyFlake`] indefinitely, see [`InfallibleSonyFlake::iter`].
+///
+/// It holds a clone of the generator, so its ids never collide with the ids of the generator or its other clones.
+///
+/// [`InfallibleSonyFlake`]: struct.InfallibleSonyFlake.html
+/// [`InfallibleSonyFlake::iter`]: struct.InfallibleSonyFlake.html#method.iter
+#[derive(Debug, Clone)]
+pub struct InfallibleSonyFlakeIter {
+    sf: InfallibleSonyFlake,
+}
+
+impl InfallibleSonyFlakeIter {
+    pub(crate) fn new(sf: InfallibleSonyFlake) -> Self {
+        Self { sf }
+    }
+}
+
+impl Iterator for InfallibleSonyFlakeIter {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.sf.next_id())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Error, Settings};
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_sonyflake_iter() {
+        let sf = Settings::new()
+            .set_machine_id_value(3)
+            .into_sonyflake()
+            .unwrap();
+        let ids = sf.iter().take(100).collect::<Result<Vec<_>, Error>>().unwrap();
+        assert_eq!(ids.len(), 100);
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+        // the iterator shares the generator's state
+        assert!(sf.next_id().unwrap() > ids[99]);
+    }
+
+    #[test]
+    fn test_infallible_sonyflake_iter() {
+        let sf = Settings::new()
+            .set_machine_id_value(3)
+            .into_infallible_sonyflake()
+            .unwrap();
+        let ids = sf.iter().take(100).chain(sf.iter().take(100)).collect::<HashSet<_>>();
+        assert_eq!(ids.len(), 200);
+    }
+}
@@ -0,0 +1,245 @@
+use crate::{Error, IDParts, BIT_LEN_MACHINE_ID, BIT_LEN_SEQUENCE, BIT_LEN_TIME};
+
+/// `BitLayout` is the allocation of the 63 low bits of an ID to its time, sequence and machine id parts,
+/// see [`Settings::set_bit_layout`].
+///
+/// The default is SonyFlake's 39 bits of time, 8 bits of sequence and 16 bits of machine id. Trading
+/// machine id bits for sequence bits raises the throughput of each machine for a smaller fleet,
+/// and trading time bits shortens or extends the lifetime of the ids.
+///
+/// SonyFlake puts the sequence above the machine id; [`BitLayout::SNOWFLAKE`] is Twitter Snowflake's layout,
+/// which puts the machine id above the sequence.
+///
+/// ```rust
+/// use infallible_sonyflake::BitLayout;
+///
+/// // 1024 machines, each generating up to 2^12 IDs per time unit
+/// let layout = BitLayout::new(41, 12, 10).unwrap();
+/// let id = layout.compose(1234, 5, 6);
+/// let parts = layout.decompose(id);
+/// assert_eq!((parts.get_time(), parts.get_sequence(), parts.get_machine_id()), (1234, 5, 6));
+/// ```
+///
+/// [`Settings::set_bit_layout`]: struct.Settings.html#method.set_bit_layout
+/// [`BitLayout::SNOWFLAKE`]: struct.BitLayout.html#associatedconstant.SNOWFLAKE
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct BitLayout {
+    pub(crate) time: i64,
+    pub(crate) sequence: i64,
+    pub(crate) machine_id: i64,
+    /// whether the sequence is in the lowest bits, below the machine id
+    sequence_low: bool,
+}
+
+impl Default for BitLayout {
+    fn default() -> Self {
+        Self::SONYFLAKE
+    }
+}
+
+impl BitLayout {
+    /// SonyFlake's layout: 39 bits of time, 8 bits of sequence and 16 bits of machine id.
+    pub const SONYFLAKE: BitLayout = BitLayout {
+        time: BIT_LEN_TIME,
+        sequence: BIT_LEN_SEQUENCE,
+        machine_id: BIT_LEN_MACHINE_ID,
+        sequence_low: false,
+    };
+
+    /// Twitter Snowflake's layout: 41 bits of time, 10 bits of machine id and 12 bits of sequence, in that order.
+    /// See `Settings::snowflake` for the matching time unit and epoch.
+    pub const SNOWFLAKE: BitLayout = BitLayout {
+        time: 41,
+        sequence: 12,
+        machine_id: 10,
+        sequence_low: true,
+    };
+
+    /// Create a new `BitLayout`. The three lengths must add up to 63, the sequence must be 1 to 16 bits long
+    /// and the machine id at most 16 bits long, otherwise `Error::InvalidBitLayout` is returned.
+    pub fn new(time_bits: u8, sequence_bits: u8, machine_id_bits: u8) -> Result<Self, Error> {
+        if time_bits as u32 + sequence_bits as u32 + machine_id_bits as u32 != 63
+            || !(1..=16).contains(&sequence_bits)
+            || machine_id_bits > 16
+        {
+            return Err(Error::InvalidBitLayout(time_bits, sequence_bits, machine_id_bits));
+        }
+
+        Ok(Self {
+            time: time_bits as i64,
+            sequence: sequence_bits as i64,
+            machine_id: machine_id_bits as i64,
+            sequence_low: false,
+        })
+    }
+
+    /// Returns the bit length of the time part.
+    pub fn time_bits(&self) -> u8 {
+        self.time as u8
+    }
+
+    /// Returns the bit length of the sequence part.
+    pub fn sequence_bits(&self) -> u8 {
+        self.sequence as u8
+    }
+
+    /// Returns the bit length of the machine id part.
+    pub fn machine_id_bits(&self) -> u8 {
+        self.machine_id as u8
+    }
+
+    /// the offsets of the sequence and the machine id in an ID
+    fn shifts(&self) -> (i64, i64) {
+        if self.sequence_low {
+            (0, self.sequence)
+        } else {
+            (self.machine_id, 0)
+        }
+    }
+
+    /// `compose` packs the elapsed time, sequence and machine id into an ID of this layout.
+    /// The fields are expected to fit their bit lengths.
+    pub fn compose(&self, elapsed_time: i64, sequence: u16, machine_id: u16) -> u64 {
+        let (sequence_shift, machine_id_shift) = self.shifts();
+        (elapsed_time as u64) << (self.sequence + self.machine_id)
+            | (sequence as u64) << sequence_shift
+            | (machine_id as u64) << machine_id_shift
+    }
+
+    /// `decompose` returns the parts of an ID of this layout.
+    pub fn decompose(&self, id: u64) -> IDParts {
+        let (sequence_shift, machine_id_shift) = self.shifts();
+
+        IDParts {
+            id,
+            msb: id >> 63,
+            time: id >> (self.sequence + self.machine_id),
+            sequence: (id >> sequence_shift) & ((1 << self.sequence) - 1),
+            machine_id: (id >> machine_id_shift) & ((1 << self.machine_id) - 1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BitLayout;
+    use crate::{
+        decompose, decompose_obfuscated, decompose_obfuscated_with_layout, decompose_with_layout, Error, IDParts,
+        Settings,
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn test_bit_layout() {
+        let id = 0x1234_5678_9abc_def0;
+        assert_eq!(BitLayout::default().decompose(id), decompose(id));
+
+        assert!(matches!(BitLayout::new(40, 8, 16).unwrap_err(), Error::InvalidBitLayout(40, 8, 16)));
+        assert!(BitLayout::new(63, 0, 0).is_err());
+        assert!(BitLayout::new(30, 16, 17).is_err());
+        assert!(BitLayout::new(47, 16, 0).is_ok());
+    }
+
+    #[test]
+    fn test_snowflake_layout() {
+        let layout = BitLayout::SNOWFLAKE;
+        let id = layout.compose(123_456_789, 42, 1000);
+        assert_eq!(id, 123_456_789 << 22 | 1000 << 12 | 42);
+        let parts = layout.decompose(id);
+        assert_eq!((parts.get_time(), parts.get_sequence(), parts.get_machine_id()), (123_456_789, 42, 1000));
+    }
+
+    #[test]
+    fn test_decompose_with_layout() {
+        let layouts = [
+            BitLayout::SONYFLAKE,
+            BitLayout::SNOWFLAKE,
+            BitLayout::new(41, 12, 10).unwrap(),
+            BitLayout::new(35, 16, 12).unwrap(),
+            BitLayout::new(47, 16, 0).unwrap(),
+            BitLayout::new(46, 1, 16).unwrap(),
+        ];
+        for layout in layouts {
+            let max_time = (1i64 << layout.time_bits()) - 1;
+            let max_sequence = ((1u32 << layout.sequence_bits()) - 1) as u16;
+            let max_machine_id = ((1u32 << layout.machine_id_bits()) - 1) as u16;
+            for (time, sequence, machine_id) in [(0, 0, 0), (1234, 1, max_machine_id), (max_time, max_sequence, 0)] {
+                let id = layout.compose(time, sequence, machine_id);
+                let parts = decompose_with_layout(id, &layout);
+                assert_eq!(
+                    (parts.get_id(), parts.get_msb(), parts.get_time(), parts.get_sequence(), parts.get_machine_id()),
+                    (id, 0, time as u64, sequence as u64, machine_id as u64),
+                    "{:?}",
+                    layout
+                );
+            }
+        }
+
+        let id = 0x1234_5678_9abc_def0;
+        assert_eq!(decompose_with_layout(id, &BitLayout::SONYFLAKE), decompose(id));
+    }
+
+    #[test]
+    fn test_custom_bit_layout() {
+        let layout = BitLayout::new(35, 16, 12).unwrap();
+        let sf = Settings::new()
+            .set_machine_id_value(4000)
+            .set_bit_layout(layout)
+            .into_sonyflake()
+            .unwrap();
+        assert_eq!(sf.bit_layout(), layout);
+
+        let mut last = 0;
+        let mut max_sequence = 0;
+        for _ in 0..2000 {
+            let id = sf.next_id().unwrap();
+            assert!(id > last);
+            last = id;
+            let parts: IDParts = layout.decompose(id);
+            assert_eq!(parts.get_machine_id(), 4000);
+            max_sequence = max_sequence.max(parts.get_sequence());
+        }
+        // the sequence goes past 8 bits within a window
+        assert!(max_sequence > 255);
+
+        // the machine id must fit in 12 bits
+        assert!(matches!(
+            Settings::new()
+                .set_machine_id_value(4096)
+                .set_bit_layout(layout)
+                .into_infallible_sonyflake()
+                .unwrap_err(),
+            Error::MachineIDOutOfRange(4096)
+        ));
+    }
+
+    #[test]
+    fn test_decompose_obfuscated_with_layout() {
+        let key = 0xfeed_beef;
+        for (layout, time_unit) in [
+            (BitLayout::SNOWFLAKE, Duration::from_millis(1)),
+            (BitLayout::new(35, 16, 12).unwrap(), Duration::from_millis(10)),
+        ] {
+            let sf = Settings::new()
+                .set_machine_id_value(1000)
+                .set_bit_layout(layout)
+                .set_time_unit(time_unit)
+                .set_machine_id_obfuscation(key)
+                .into_sonyflake()
+                .unwrap();
+            let ids = sf.next_ids(500).unwrap();
+            // sorted by time, but not within a time unit, as the machine id is above the sequence in some layouts
+            assert!(ids.windows(2).all(|w| layout.decompose(w[0]).get_time() <= layout.decompose(w[1]).get_time()));
+            for id in &ids {
+                let parts = decompose_obfuscated_with_layout(*id, key, &layout);
+                assert_eq!(parts.get_machine_id(), 1000);
+                assert_eq!(parts.get_time(), layout.decompose(*id).get_time());
+                assert_eq!(parts.get_sequence(), layout.decompose(*id).get_sequence());
+            }
+            assert!(ids.iter().any(|id| layout.decompose(*id).get_machine_id() != 1000));
+        }
+
+        let id = 0x1234_5678_9abc_def0;
+        assert_eq!(decompose_obfuscated_with_layout(id, key, &BitLayout::SONYFLAKE), decompose_obfuscated(id, key));
+    }
+}
@@ -54,6 +54,38 @@
 //! infallible-sonyflake = "0.1"
 //! ```
 //!
+//! ## Features
+//! - `aws`: enable [`AmazonEC2MachineID`](struct.AmazonEC2MachineID.html), which reads the private IPv4
+//!   address of an EC2 instance from the instance metadata service, like the Go `awsutil` package.
+//! - `coarse-clock`: read the current time from the [coarsetime](https://crates.io/crates/coarsetime) crate
+//!   instead of `chrono`. A coarse clock read avoids a full system call on every `next_id`, which
+//!   dominates the cost when the generator does not need to sleep. The trade-off is accuracy: the coarse
+//!   clock only advances once per kernel tick (typically 1-4 msec), so the time part of an ID may lag the
+//!   real time by up to a tick. IDs stay unique and monotonic.
+//! - `duplicate-guard`: enable `Settings::guard_duplicate_machine_id`, which keeps an in-process registry
+//!   of the machine ids and start times in use and refuses to create a second independent generator
+//!   with the same pair, catching misconfigurations that would emit colliding IDs.
+//! - `rand`: enable [`random_valid_id`](fn.random_valid_id.html), which draws arbitrary well-formed
+//!   IDs from a [rand](https://crates.io/crates/rand) RNG for fuzzing and property tests of ID handlers.
+//! - `tokio`: enable `SonyFlake::next_id_async` and `InfallibleSonyFlake::next_id_async`, which wait for
+//!   the next 10 msec window with `tokio::time::sleep` instead of blocking the thread when the sequence is used up,
+//!   and `InfallibleSonyFlake::subscribe_rebase`, a `tokio::sync::watch` channel of the start time after rebases.
+//!
+//! - `std` (default): everything relying on the standard library: the system clock, the private
+//!   IP address lookup, the lock of `SonyFlake` and the generators built on top of it.
+//!
+//! ## Embedded / `no_std`
+//! Without the `std` feature the crate only needs `alloc`:
+//! ```toml
+//! [dependencies]
+//! infallible-sonyflake = { version = "0.1", default-features = false }
+//! ```
+//! The platform has no clock or network interfaces to read, so supply a [`Clock`](trait.Clock.html),
+//! e.g. backed by an RTC, and a [`MachineID`](trait.MachineID.html) to a
+//! [`BareSonyFlake`](struct.BareSonyFlake.html). It never sleeps: once the sequence of the current
+//! time window is used up, `next_id` returns `Error::RateExceeded` until the clock moves on.
+//! The codecs, `BitLayout`, `IDParts` and the other pure helpers are available as well.
+//!
 //! ## Quickstart
 //! 1. **Fallible SonyFlake**
 //!    `Sonyflake` may fail to generate a unique ID when we call `next_id` if time overflows.
@@ -61,12 +93,10 @@
 //!    use infallible_sonyflake::{SonyFlake, Settings};
 //!    use chrono::Utc;
 //!
-//!    fn main() {
-//!        let now = Utc::now();
-//!        let mut sf = Settings::new().set_start_time(now).into_sonyflake().unwrap();
-//!        let next_id = sf.next_id().unwrap();
-//!        println!("{}", next_id);
-//!    }
+//!    let now = Utc::now();
+//!    let sf = Settings::new().set_start_time(now).into_sonyflake().unwrap();
+//!    let next_id = sf.next_id().unwrap();
+//!    println!("{}", next_id);
 //!    ```
 //! 2. **Infallible SonyFlake**
 //!    `InfallibleSonyFlake` will always generate a unique ID when we call `next_id` if time overflow happens, it will refresh the `start_time` to the current time.
@@ -74,12 +104,10 @@
 //!    use infallible_sonyflake::{InfallibleSonyFlake, Settings};
 //!    use chrono::Utc;
 //!
-//!    fn main() {
-//!        let now = Utc::now();
-//!        let mut sf = Settings::new().set_start_time(now).into_infallible_sonyflake().unwrap();
-//!        let next_id = sf.next_id();
-//!        println!("{}", next_id);
-//!    }
+//!    let now = Utc::now();
+//!    let sf = Settings::new().set_start_time(now).into_infallible_sonyflake().unwrap();
+//!    let next_id = sf.next_id();
+//!    println!("{}", next_id);
 //!    ```
 //! 3. **Custom machine ID and machine ID checker**
 //!    ```rust
@@ -94,7 +122,7 @@
 //!    impl MachineID for CustomMachineID {
 //!        fn machine_id(&mut self) -> Result<u16, Box<dyn std::error::Error + Send + Sync + 'static>> {
 //!            self.counter += 1;
-//!            if self.counter % 2 != 0 {
+//!            if self.counter % 2 == 1 {
 //!                Ok(self.id)
 //!            } else {
 //!                Err(Box::new("NaN".parse::<u32>().unwrap_err()))
@@ -106,30 +134,24 @@
 //!
 //!    impl MachineIDChecker for CustomMachineIDChecker {
 //!        fn check_machine_id(&self, id: u16) -> bool {
-//!            if id % 2 != 0 {
-//!                true
-//!            } else {
-//!                false
-//!            }
+//!            id % 2 == 1
 //!        }
 //!    }
 //!
-//!    fn main() {
-//!        let mut sf = Settings::new()
-//!            .set_machine_id(Box::new(CustomMachineID { counter: 0, id: 1 }))
-//!            .set_check_machine_id(Box::new(CustomMachineIDChecker {}))
-//!            .into_infallible_sonyflake().unwrap();
-//!        let id = sf.next_id();
-//!        let parts = IDParts::decompose(id);
-//!        assert_eq!(parts.get_machine_id(), 1);
-//!
-//!        let err = Settings::new()
-//!            .set_machine_id(Box::new(CustomMachineID { counter: 0, id: 2 }))
-//!            .set_check_machine_id(Box::new(CustomMachineIDChecker {}))
-//!            .into_infallible_sonyflake().unwrap_err();
-//!
-//!        assert_eq!(format!("{}", err), Error::InvalidMachineID(2).to_string());
-//!    }
+//!    let sf = Settings::new()
+//!        .set_machine_id(Box::new(CustomMachineID { counter: 0, id: 1 }))
+//!        .set_check_machine_id(Box::new(CustomMachineIDChecker {}))
+//!        .into_infallible_sonyflake().unwrap();
+//!    let id = sf.next_id();
+//!    let parts = IDParts::decompose(id);
+//!    assert_eq!(parts.get_machine_id(), 1);
+//!
+//!    let err = Settings::new()
+//!        .set_machine_id(Box::new(CustomMachineID { counter: 0, id: 2 }))
+//!        .set_check_machine_id(Box::new(CustomMachineIDChecker {}))
+//!        .into_infallible_sonyflake().unwrap_err();
+//!
+//!    assert_eq!(format!("{}", err), Error::InvalidMachineID(2).to_string());
 //!    ```
 //!
 //!
@@ -140,7 +162,8 @@
 //! - MachineID returns the unique ID of the SonyFlake instance.
 //!   If MachineID returns an error, SonyFlake is not created.
 //!   If MachineID is nil, default MachineID is used.
-//!   Default MachineID returns the lower 16 bits of the private IP address.
+//!   Default MachineID returns the lower 16 bits of the private IP address,
+//!   or failing that a hash of the MAC address of the first non-loopback network interface.
 //!
 //! - CheckMachineID validates the uniqueness of the machine ID.
 //!   If CheckMachineID returns false, SonyFlake is not created.
@@ -152,16 +175,154 @@
 //! NextID can continue to generate IDs for about 174 years from StartTime.
 //! But after the SonyFlake time is over the limit, NextID returns an error. Or, you can use `InfallibleSonyFlake`, `InfallibleSonyFlake` will always generate a unique ID when we call `next_id` if time overflow happens, it will refresh the `start_time` to the current time.
 
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
 #[macro_use]
 extern crate serde;
 
-use chrono::{DateTime, TimeZone, Utc};
-use pnet::datalink::interfaces;
-use std::fmt::{Debug, Formatter};
-use std::net::{IpAddr, Ipv4Addr};
+#[cfg(not(feature = "std"))]
+use alloc::{boxed::Box, format, string::{String, ToString}, vec::Vec};
+use alloc::collections::BTreeSet;
+use chrono::{DateTime, NaiveDate, SecondsFormat, TimeZone, Utc};
+use core::cmp::Ordering;
+#[cfg(feature = "std")]
+use core::convert::TryFrom;
+use core::fmt::{Debug, Formatter};
+use core::time::Duration;
+#[cfg(feature = "std")]
+use parking_lot::{Mutex, MutexGuard};
+#[cfg(feature = "std")]
+use pnet::datalink::{interfaces, NetworkInterface};
+#[cfg(feature = "std")]
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+#[cfg(feature = "std")]
 use std::sync::Arc;
-use std::time::Duration;
-use parking_lot::Mutex;
+
+#[cfg(feature = "std")]
+mod allocator;
+#[cfg(feature = "std")]
+pub use allocator::{RangeAllocator, RangeClient};
+
+#[cfg(feature = "std")]
+mod atomic;
+#[cfg(feature = "std")]
+pub use atomic::AtomicSonyFlake;
+
+#[cfg(feature = "aws")]
+mod aws;
+#[cfg(feature = "aws")]
+pub use aws::AmazonEC2MachineID;
+
+mod bare;
+pub use bare::BareSonyFlake;
+
+mod clock;
+#[cfg(feature = "std")]
+use clock::{ClockNanos, MonotonicNanoClock, UnitClock};
+pub use clock::{Clock, NanoClock};
+#[cfg(feature = "std")]
+pub use clock::{SystemClock, SystemNanoClock, TimeSource};
+
+mod codec;
+pub use codec::{
+    decode_base62, decode_with_checksum, encode_base62, encode_with_checksum, Base32Codec, Base62Codec, DecimalCodec,
+    HexCodec, IdCodec,
+};
+
+#[cfg(feature = "std")]
+mod dedup;
+#[cfg(feature = "std")]
+pub use dedup::DedupSonyFlake;
+
+#[cfg(feature = "std")]
+mod env;
+#[cfg(feature = "std")]
+pub use env::EnvMachineID;
+
+#[cfg(feature = "std")]
+mod failover;
+#[cfg(feature = "std")]
+pub use failover::FailoverSonyFlake;
+
+mod flake128;
+pub use flake128::{decompose128, IDParts128};
+#[cfg(feature = "std")]
+pub use flake128::SonyFlake128;
+
+mod flake32;
+pub use flake32::{decompose32, IDParts32};
+#[cfg(feature = "std")]
+pub use flake32::SonyFlake32;
+
+#[cfg(feature = "std")]
+mod fleet;
+#[cfg(feature = "std")]
+pub use fleet::{FleetView, Health};
+
+#[cfg(feature = "duplicate-guard")]
+mod guard;
+
+#[cfg(feature = "std")]
+mod hostname;
+#[cfg(feature = "std")]
+pub use hostname::HostnameMachineID;
+
+mod id;
+pub use id::Id;
+
+#[cfg(feature = "std")]
+mod iter;
+#[cfg(feature = "std")]
+pub use iter::{InfallibleSonyFlakeIter, SonyFlakeIter};
+
+mod layout;
+pub use layout::BitLayout;
+
+#[cfg(feature = "std")]
+mod metrics;
+#[cfg(feature = "std")]
+pub use metrics::Metrics;
+
+#[cfg(feature = "std")]
+mod partitioned;
+#[cfg(feature = "std")]
+pub use partitioned::ThreadPartitionedSonyFlake;
+
+#[cfg(feature = "std")]
+mod pod;
+#[cfg(feature = "std")]
+pub use pod::PodOrdinalMachineID;
+
+#[cfg(feature = "std")]
+mod pool;
+#[cfg(feature = "std")]
+pub use pool::SonyFlakePool;
+
+#[cfg(feature = "std")]
+mod prefetch;
+#[cfg(feature = "std")]
+pub use prefetch::PrefetchSonyFlake;
+
+mod region;
+pub use region::{RegionChecker, RegionMap};
+
+#[cfg(feature = "std")]
+mod sampler;
+#[cfg(feature = "std")]
+use sampler::ClockSampler;
+#[cfg(feature = "std")]
+pub use sampler::ClockStallHook;
+
+#[cfg(feature = "std")]
+mod snapshot;
+#[cfg(feature = "std")]
+pub use snapshot::Snapshot;
+
+#[cfg(feature = "std")]
+mod thread_slots;
+#[cfg(feature = "std")]
+use thread_slots::ThreadSlots;
 
 /// bit length of time
 const BIT_LEN_TIME: i64 = 39;
@@ -175,6 +336,18 @@ const BIT_LEN_MACHINE_ID: i64 = 63 - BIT_LEN_TIME - BIT_LEN_SEQUENCE;
 /// 10 msec
 const FLAKE_TIME_UNIT: i64 = 10_000_000;
 
+/// the number of nanoseconds in a UTC day
+#[cfg(feature = "std")]
+const NANOS_PER_DAY: i64 = 24 * 60 * 60 * 1_000_000_000;
+
+/// the Twitter Snowflake epoch, 2010-11-04 01:42:54.657 UTC
+#[cfg(feature = "std")]
+const SNOWFLAKE_EPOCH_MILLIS: i64 = 1_288_834_974_657;
+
+/// separates the prefix from the id in `SonyFlake::next_prefixed`
+#[cfg(feature = "std")]
+const PREFIX_SEPARATOR: char = '_';
+
 /// The [`Error`] type for this crate.
 ///
 /// [`Error`]: enum.Error.html
@@ -183,8 +356,12 @@ pub enum Error {
     /// `Error::StartTimeAheadOfCurrentTime` means that start time is ahead of current time
     StartTimeAheadOfCurrentTime(DateTime<Utc>),
 
+    /// `Error::StartTimeOutOfRange` means that start time cannot be represented in nanoseconds
+    /// since the unix epoch, i.e. it is outside of roughly the years 1677 to 2262
+    StartTimeOutOfRange(DateTime<Utc>),
+
     /// `Error::MachineIdFailed` returned by `MachineID`
-    MachineIdFailed(Box<dyn std::error::Error + 'static + Send + Sync>),
+    MachineIdFailed(Box<dyn core::error::Error + 'static + Send + Sync>),
 
     /// `Error::InvalidMachineID` returned by `MachineIDChecker`
     InvalidMachineID(u16),
@@ -192,36 +369,183 @@ pub enum Error {
     /// `Error::TimeOverflow` means that we over the sonyflake time limit
     TimeOverflow,
 
-    /// `Error::NoPrivateIPv4Address` means that there is no private ip address on this machine
+    /// `Error::NoPrivateIPv4Address` means that there is neither a private ip address nor a MAC address
+    /// on this machine to derive the default machine id from
     NoPrivateIPv4Address,
+
+    /// `Error::NoPrivateIPAddress` means that there is neither a private IPv4 address nor a unique local
+    /// or link-local IPv6 address nor a MAC address on this machine, see `Settings::fallback_to_ipv6`
+    NoPrivateIPAddress,
+
+    /// `Error::NoNetworkInterfaces` means that no network interfaces could be listed on this machine at all,
+    /// as in some sandboxes, so the default machine id cannot be derived from a private ip address
+    NoNetworkInterfaces,
+
+    /// `Error::CounterExhausted` means that a sequence-only generator has used up all of its time and sequence bits
+    CounterExhausted,
+
+    /// `Error::InvalidPartitionCount` means that the number of sequence partitions is not a power of two within the sequence space,
+    /// or the number of generators of a `SonyFlakePool` is not a power of two within the machine id space
+    InvalidPartitionCount(u16),
+
+    /// `Error::NoFreePartition` means that every sequence partition, or every generator of a `SonyFlakePool`,
+    /// is already assigned to another thread
+    NoFreePartition,
+
+    /// `Error::MultipleMachineIds` means that a set of ids expected to come from one machine carries these machine ids
+    MultipleMachineIds(BTreeSet<u16>),
+
+    /// `Error::AllocatorClosed` means that the `RangeAllocator` serving lease requests has stopped
+    AllocatorClosed,
+
+    /// `Error::InvalidRegion` means that a region's machine id range is empty or overlaps another region's
+    InvalidRegion(String),
+
+    /// `Error::InvalidBorrowedBits` means that more machine id bits are borrowed for the sequence than allowed
+    InvalidBorrowedBits(u8),
+
+    /// `Error::InvalidEncodedID` means that a string cannot be decoded to an ID by an `IdCodec`
+    InvalidEncodedID(String),
+
+    /// `Error::ChecksumMismatch` means that the check symbols of a string do not match the ID it encodes,
+    /// see `decode_with_checksum`
+    ChecksumMismatch(String),
+
+    /// `Error::DuplicateMachineId` means that another independent generator with the same machine id
+    /// and start time is alive in this process, see `Settings::guard_duplicate_machine_id`
+    DuplicateMachineId(u16),
+
+    /// `Error::CreatedBeforeStartTime` means that a synthetic ID was requested for a time before the start time
+    CreatedBeforeStartTime(DateTime<Utc>),
+
+    /// `Error::SequenceOutOfRange` means that a sequence number does not fit in the sequence bits
+    SequenceOutOfRange(u16),
+
+    /// `Error::InvalidPrefix` means that an ID prefix is empty or contains the `_` separator
+    InvalidPrefix(String),
+
+    /// `Error::InvalidTagBits` means that the tag bits leave no room for a sequence number
+    InvalidTagBits(u8),
+
+    /// `Error::InvalidTag` means that a tag does not fit in the reserved tag bits
+    InvalidTag(u8),
+
+    /// `Error::RateExceeded` means that the generator has been saturated for more consecutive
+    /// 10 msec windows than allowed by `Settings::set_max_consecutive_sleeps`
+    RateExceeded,
+
+    /// `Error::InvalidTimeUnit` means that the time unit set by `Settings::set_time_unit` is zero, longer than a day
+    /// or too long for the time range of the bit layout to fit in nanoseconds
+    InvalidTimeUnit(Duration),
+
+    /// `Error::InvalidBitLayout` means that the time, sequence and machine id bit lengths passed to
+    /// `BitLayout::new` do not make a valid layout
+    InvalidBitLayout(u8, u8, u8),
+
+    /// `Error::MachineIDOutOfRange` means that the machine id returned by `MachineID` does not fit in the
+    /// machine id bits of the bit layout, so it would overlap the other parts of the ids
+    MachineIDOutOfRange(u16),
+
+    /// `Error::ClockMovedBackwards` means that the clock is behind the time of the last id by more than
+    /// the tolerance set by `Settings::set_clock_regression_tolerance`, by the given duration
+    ClockMovedBackwards(Duration),
 }
 
 unsafe impl Send for Error {}
 unsafe impl Sync for Error {}
 
-impl std::fmt::Display for Error {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         match self {
             Error::StartTimeAheadOfCurrentTime(time) => {
                 write!(f, "start_time {} is ahead of current time", time)
             }
+            Error::StartTimeOutOfRange(time) => write!(f, "start_time {} is out of range", time),
             Error::MachineIdFailed(e) => write!(f, "cannot get a machine id: {}", e),
             Error::InvalidMachineID(id) => write!(f, "invalid machine id: {}", id),
             Error::TimeOverflow => write!(f, "over the sonyflake time limit"),
             Error::NoPrivateIPv4Address => write!(f, "no private IPv4 address"),
+            Error::NoPrivateIPAddress => write!(f, "no private IPv4 or IPv6 address"),
+            Error::NoNetworkInterfaces => write!(f, "no network interfaces"),
+            Error::CounterExhausted => write!(f, "sequence-only counter exhausted"),
+            Error::InvalidPartitionCount(n) => write!(f, "invalid number of partitions: {}", n),
+            Error::NoFreePartition => write!(f, "no free partition"),
+            Error::MultipleMachineIds(ids) => write!(f, "expected ids from a single machine, found machine ids: {:?}", ids),
+            Error::AllocatorClosed => write!(f, "range allocator closed"),
+            Error::InvalidRegion(name) => write!(f, "invalid machine id range for region {}", name),
+            Error::InvalidBorrowedBits(n) => write!(f, "cannot borrow {} machine id bits for the sequence", n),
+            Error::InvalidEncodedID(s) => write!(f, "invalid encoded id: {}", s),
+            Error::ChecksumMismatch(s) => write!(f, "checksum mismatch in encoded id: {}", s),
+            Error::DuplicateMachineId(id) => write!(f, "machine id {} is already used by another generator with the same start time", id),
+            Error::CreatedBeforeStartTime(time) => write!(f, "{} is before the start time", time),
+            Error::SequenceOutOfRange(sequence) => write!(f, "sequence {} does not fit in {} bits", sequence, BIT_LEN_SEQUENCE),
+            Error::InvalidPrefix(prefix) => write!(f, "invalid id prefix: {:?}", prefix),
+            Error::InvalidTagBits(n) => write!(f, "cannot reserve {} sequence bits for tags", n),
+            Error::InvalidTag(tag) => write!(f, "tag {} does not fit in the reserved tag bits", tag),
+            Error::RateExceeded => write!(f, "id generation rate exceeded"),
+            Error::InvalidTimeUnit(unit) => write!(f, "invalid time unit: {:?}", unit),
+            Error::InvalidBitLayout(time, sequence, machine_id) => write!(
+                f,
+                "invalid bit layout: {} time bits, {} sequence bits and {} machine id bits",
+                time, sequence, machine_id
+            ),
+            Error::MachineIDOutOfRange(id) => write!(f, "machine id {} does not fit in the machine id bits", id),
+            Error::ClockMovedBackwards(by) => write!(f, "the clock moved backwards by {:?}", by),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+/// `Backpressure` is returned by [`SonyFlake::next_id_checked`] when the generator cannot hand out
+/// an id right now, telling the caller whether and when to retry.
+///
+/// [`SonyFlake::next_id_checked`]: struct.SonyFlake.html#method.next_id_checked
+#[derive(Debug)]
+pub enum Backpressure {
+    /// `Backpressure::Saturated` means that the sequence of the current 10 msec window is used up,
+    /// retry after `retry_after`, when the next window starts.
+    Saturated {
+        /// the time left until the next window
+        retry_after: Duration,
+    },
+
+    /// `Backpressure::OverflowImminent` means that the time bits are used up, or would be by the next window,
+    /// so retrying does not help.
+    OverflowImminent,
+
+    /// `Backpressure::Failed` means that generating the id failed for another reason than the capacity
+    /// of the generator, with the given error.
+    Failed(Error),
+}
+
+impl core::fmt::Display for Backpressure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Backpressure::Saturated { retry_after } => write!(f, "window saturated, retry after {:?}", retry_after),
+            Backpressure::OverflowImminent => write!(f, "sonyflake time overflow imminent"),
+            Backpressure::Failed(e) => write!(f, "{}", e),
         }
     }
 }
 
-impl std::error::Error for Error {}
+impl core::error::Error for Backpressure {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Backpressure::Failed(e) => Some(e),
+            _ => None,
+        }
+    }
+}
 
 /// `MachineID` is for custom machine id generator.
 pub trait MachineID {
     /// `machine_id` returns the unique ID of the `Sonyflake` instance.
     /// If `machine_id` returns an error, `Sonyflake` is not created.
     /// If `machine_id` is nil, default `machine_id` is used.
-    /// Default `machine_id` returns the lower 16 bits of the private IP address.
-    fn machine_id(&mut self) -> Result<u16, Box<dyn std::error::Error + Send + Sync + 'static>>;
+    /// Default `machine_id` returns the lower 16 bits of the private IP address,
+    /// or failing that a hash of the MAC address of the first non-loopback network interface.
+    fn machine_id(&mut self) -> Result<u16, Box<dyn core::error::Error + Send + Sync + 'static>>;
 }
 
 /// `MachineIDChecker` is for custom machine id checker.
@@ -232,71 +556,245 @@ pub trait MachineIDChecker {
     fn check_machine_id(&self, id: u16) -> bool;
 }
 
+/// `FirstIdStrategy` decides the state a generator starts from, and so the very first id it hands out.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum FirstIdStrategy {
+    /// The sequence starts at 128. If the first `next_id` falls in the same 10 msec window as the
+    /// start time, the first id has sequence 129, otherwise 0. This is the default.
+    #[default]
+    Legacy,
+
+    /// The first id always has sequence 0, whatever the start time.
+    Sequential,
+}
+
+/// `WaitStrategy` decides how a generator waits for the next time window once the sequence of the
+/// current one is used up, see [`Settings::set_wait_strategy`].
+///
+/// [`Settings::set_wait_strategy`]: struct.Settings.html#method.set_wait_strategy
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum WaitStrategy {
+    /// Put the thread to sleep with `std::thread::sleep`. This is the default.
+    #[default]
+    Sleep,
+
+    /// Busy-wait, reading the clock in a loop until the next window starts. This burns a core for up to
+    /// a time unit but avoids the wakeup latency of the scheduler, which can be several times the remaining
+    /// wait, so it suits latency-critical callers with a core to spare.
+    Spin,
+}
+
+impl<T: MachineID + ?Sized> MachineID for Box<T> {
+    fn machine_id(&mut self) -> Result<u16, Box<dyn core::error::Error + Send + Sync + 'static>> {
+        (**self).machine_id()
+    }
+}
+
+impl<T: MachineIDChecker + ?Sized> MachineIDChecker for Box<T> {
+    fn check_machine_id(&self, id: u16) -> bool {
+        (**self).check_machine_id(id)
+    }
+}
+
 /// A builder to build a [`SonyFlake`] generator.
 ///
+/// By default the machine id source `M` and checker `C` are boxed trait objects. Name concrete types
+/// instead, e.g. `Settings::<MyMachineID, MyChecker>::default()`, to have them called without dynamic dispatch
+/// or allocation; the generators built are the same either way.
+///
 /// [`SonyFlake`]: struct.SonyFlake.html
-pub struct Settings {
+#[cfg(feature = "std")]
+pub struct Settings<M = Box<dyn MachineID>, C = Box<dyn MachineIDChecker>> {
     start_time: Option<DateTime<Utc>>,
-    machine_id: Option<Box<dyn MachineID>>,
-    check_machine_id: Option<Box<dyn MachineIDChecker>>,
+    machine_id: Option<M>,
+    machine_id_transform: Option<Box<dyn Fn(u16) -> u16>>,
+    check_machine_id: Option<C>,
+    ipv6_fallback: bool,
+    sequence_only: bool,
+    borrowed_bits: u8,
+    first_id_strategy: FirstIdStrategy,
+    sequence_start: Option<u16>,
+    wait_strategy: WaitStrategy,
+    id_prefix: Option<String>,
+    tag_bits: u8,
+    max_consecutive_sleeps: Option<u32>,
+    clock_regression_tolerance: Duration,
+    daily_rolling: bool,
+    bit_reversal: bool,
+    machine_id_obfuscation: Option<u64>,
+    clock_sampler: Option<ClockSampler>,
+    nano_clock: Option<Box<dyn NanoClock>>,
+    on_exhaustion: Option<ExhaustionHook>,
+    metrics: Option<Box<dyn Metrics>>,
+    overflow_threshold: f64,
+    on_overflow_approaching: Option<Box<dyn FnMut(i64, i64) + Send>>,
+    time_unit: Duration,
+    bit_layout: BitLayout,
+    snapshot_state: Option<(i64, u16)>,
+    #[cfg(feature = "duplicate-guard")]
+    guard_duplicates: bool,
 }
 
-impl Default for Settings {
+#[cfg(feature = "std")]
+impl<M, C> Default for Settings<M, C> {
     fn default() -> Self {
-        Settings::new()
+        Self {
+            start_time: None,
+            machine_id: None,
+            machine_id_transform: None,
+            check_machine_id: None,
+            ipv6_fallback: false,
+            sequence_only: false,
+            borrowed_bits: 0,
+            first_id_strategy: FirstIdStrategy::Legacy,
+            sequence_start: None,
+            wait_strategy: WaitStrategy::Sleep,
+            id_prefix: None,
+            tag_bits: 0,
+            max_consecutive_sleeps: None,
+            clock_regression_tolerance: Duration::from_secs(1),
+            daily_rolling: false,
+            bit_reversal: false,
+            machine_id_obfuscation: None,
+            clock_sampler: None,
+            nano_clock: None,
+            on_exhaustion: None,
+            metrics: None,
+            overflow_threshold: 0.95,
+            on_overflow_approaching: None,
+            time_unit: Duration::from_nanos(FLAKE_TIME_UNIT as u64),
+            bit_layout: BitLayout::SONYFLAKE,
+            snapshot_state: None,
+            #[cfg(feature = "duplicate-guard")]
+            guard_duplicates: false,
+        }
     }
 }
 
+#[cfg(feature = "std")]
 impl Settings {
     /// Construct a new builder to call methods on for the [`SonyFlake`] or [`InfallibleSonyFlake`] construction.
     ///
     /// [`SonyFlake`]: struct.SonyFlake.html
     /// [`InfallibleSonyFlake`]: struct.InfallibleSonyFlake.html
     pub fn new() -> Self {
-        Self {
-            start_time: None,
-            machine_id: None,
-            check_machine_id: None,
+        Self::default()
+    }
+
+    /// Construct a builder preconfigured for ids bit-compatible with Twitter Snowflake: 41 bits of time
+    /// in milliseconds since the Twitter epoch (2010-11-04 01:42:54.657 UTC), 10 bits of machine id
+    /// and 12 bits of sequence, see [`BitLayout::SNOWFLAKE`].
+    ///
+    /// The machine id must be below 1024, so the default one derived from the private IP address
+    /// usually does not fit; set one with `set_machine_id`.
+    ///
+    /// [`BitLayout::SNOWFLAKE`]: struct.BitLayout.html#associatedconstant.SNOWFLAKE
+    pub fn snowflake() -> Self {
+        Self::new()
+            .set_bit_layout(BitLayout::SNOWFLAKE)
+            .set_time_unit(Duration::from_millis(1))
+            .set_start_time(Utc.timestamp_millis_opt(SNOWFLAKE_EPOCH_MILLIS).unwrap())
+    }
+
+    /// Construct a builder resuming a [`SonyFlake`] from a [`Snapshot`]: the start time, time unit and
+    /// machine id are taken from the snapshot, and the generator built continues right after the last id
+    /// generated before the snapshot was taken. Other settings must be set again as they were.
+    ///
+    /// Building the generator fails with `Error::TimeOverflow` or `Error::SequenceOutOfRange` if the position
+    /// of the snapshot does not fit the generator, see `SonyFlake::restore_state`.
+    ///
+    /// [`SonyFlake`]: struct.SonyFlake.html
+    /// [`Snapshot`]: struct.Snapshot.html
+    pub fn from_snapshot(snapshot: &Snapshot) -> Self {
+        let mut st = Self::new()
+            .set_start_time(snapshot.get_start_time())
+            .set_time_unit(snapshot.get_time_unit())
+            .set_machine_id_value(snapshot.get_machine_id());
+        st.snapshot_state = Some((snapshot.get_elapsed_time(), snapshot.get_sequence()));
+        st
+    }
+}
+
+#[cfg(feature = "std")]
+impl<C: MachineIDChecker> Settings<Box<dyn MachineID>, C> {
+    /// Sets a fixed machine id, e.g. a node index known at startup, without implementing `MachineID`.
+    /// The checker, if any, still validates it.
+    pub fn set_machine_id_value(self, machine_id: u16) -> Self {
+        self.set_machine_id(Box::new(FixedMachineID(machine_id)))
+    }
+}
+
+#[cfg(feature = "std")]
+impl<M: MachineID, C: MachineIDChecker> Settings<M, C> {
+
+    /// Returns the time unit in nanoseconds.
+    fn get_time_unit(&self) -> Result<i64, Error> {
+        match self.time_unit.as_nanos() as i64 {
+            time_unit
+                if time_unit > 0
+                    && time_unit <= NANOS_PER_DAY
+                    && (1i64 << self.bit_layout.time).checked_mul(time_unit).is_some() =>
+            {
+                Ok(time_unit)
+            }
+            _ => Err(Error::InvalidTimeUnit(self.time_unit)),
         }
     }
 
+    /// Returns the start time in the configured time unit.
     fn get_start_time(&self) -> Result<i64, Error> {
-        return if let Some(start_time) = self.start_time {
-            if start_time > Utc::now() {
+        self.get_start_time_in(self.get_time_unit()?)
+    }
+
+    /// Returns the start time in units of `time_unit` nanoseconds.
+    fn get_start_time_in(&self, time_unit: i64) -> Result<i64, Error> {
+        if let Some(start_time) = self.start_time {
+            let now = match &self.nano_clock {
+                Some(clock) => clock.now_nanos(),
+                None => now_nanos(),
+            };
+            let start_nanos = start_time.timestamp_nanos_opt().ok_or(Error::StartTimeOutOfRange(start_time))?;
+            if start_nanos > now {
                 return Err(Error::StartTimeAheadOfCurrentTime(start_time));
             }
-            Ok(to_sonyflake_time(start_time))
+            Ok(start_nanos / time_unit)
         } else {
-            Ok(to_sonyflake_time(default_start_time()))
-        }
-    }
-
-    fn get_and_check_machine_id(self) -> Result<u16, Error> {
-        return if let Some(mut machine_id) = self.machine_id {
-            match machine_id.machine_id() {
-                Ok(machine_id) => {
-                    if let Some(checker) = self.check_machine_id {
-                        if !checker.check_machine_id(machine_id) {
-                            return Err(Error::InvalidMachineID(machine_id));
-                        }
-                    }
-                    Ok(machine_id)
-                },
-                Err(e) => Err(Error::MachineIdFailed(e)),
-            }
-        } else {
-            match lower_16_bit_private_ip() {
-                Ok(machine_id) => {
-                    if let Some(checker) = self.check_machine_id {
-                        if !checker.check_machine_id(machine_id) {
-                            return Err(Error::InvalidMachineID(machine_id));
-                        }
-                    }
-                    Ok(machine_id)
-                },
-                Err(e) => Err(e),
-            }
+            to_sonyflake_time(default_start_time(), time_unit)
+        }
+    }
+
+    fn check_sequence_start(&self, sequence_bits: i64) -> Result<(), Error> {
+        match self.sequence_start {
+            Some(sequence) if sequence as u64 >> sequence_bits != 0 => Err(Error::SequenceOutOfRange(sequence)),
+            _ => Ok(()),
+        }
+    }
+
+    fn get_and_check_machine_id(mut self) -> Result<u16, Error> {
+        let machine_id = match &mut self.machine_id {
+            Some(machine_id) => machine_id.machine_id().map_err(Error::MachineIdFailed)?,
+            None => default_machine_id(self.ipv6_fallback)?,
+        };
+        self.check_machine_id_value(machine_id)
+    }
+
+    /// Applies the transform to a resolved machine id, then checks it fits the layout and passes the checker.
+    fn check_machine_id_value(&self, machine_id: u16) -> Result<u16, Error> {
+        let machine_id = match &self.machine_id_transform {
+            Some(transform) => transform(machine_id),
+            None => machine_id,
         };
+
+        if machine_id as u64 >> self.bit_layout.machine_id != 0 {
+            return Err(Error::MachineIDOutOfRange(machine_id));
+        }
+
+        if let Some(checker) = &self.check_machine_id {
+            if !checker.check_machine_id(machine_id) {
+                return Err(Error::InvalidMachineID(machine_id));
+            }
+        }
+        Ok(machine_id)
     }
 
     /// Sets the start time.
@@ -308,568 +806,4381 @@ impl Settings {
 
     /// Sets the machine id.
     /// If the fn returns an error, finalize will fail.
-    pub fn set_machine_id(mut self, machine_id: Box<dyn MachineID>) -> Self {
+    pub fn set_machine_id(mut self, machine_id: M) -> Self {
         self.machine_id = Some(machine_id);
         self
     }
 
+    /// Set a function applied once to the resolved machine id at construction, e.g. a keyed permutation,
+    /// so the machine id bits of the ids do not reveal the private IP address they were derived from.
+    /// The checker sees the transformed machine id.
+    ///
+    /// The transform must be a bijection, or two hosts may end up with the same machine id.
+    pub fn set_machine_id_transform(mut self, transform: Box<dyn Fn(u16) -> u16>) -> Self {
+        self.machine_id_transform = Some(transform);
+        self
+    }
+
     /// Set a function to check the machine id.
     /// If the fn returns false, finalize will fail.
-    pub fn set_check_machine_id(mut self, check_machine_id: Box<dyn MachineIDChecker>) -> Self {
+    pub fn set_check_machine_id(mut self, check_machine_id: C) -> Self {
         self.check_machine_id = Some(check_machine_id);
         self
     }
 
-    pub fn into_sonyflake(self) -> Result<SonyFlake, Error> {
-        SonyFlake::new(self)
+    /// When no machine id is set and there is no private IPv4 address, derive the default machine id
+    /// from the lower 16 bits of a unique local (`fc00::/7`) IPv6 address, or failing that a link-local
+    /// (`fe80::/10`) one, before falling back to the MAC address, e.g. on IPv6-only networks.
+    /// Fails with `Error::NoPrivateIPAddress` if there is none of them.
+    pub fn fallback_to_ipv6(mut self) -> Self {
+        self.ipv6_fallback = true;
+        self
     }
 
-    pub fn into_infallible_sonyflake(self) -> Result<InfallibleSonyFlake, Error> {
-        InfallibleSonyFlake::new(self)
+    /// Ignore the clock and use the time and sequence bits as a single counter.
+    ///
+    /// In this mode `next_id` hands out ids whose time and sequence bits form a
+    /// monotonically increasing counter starting at zero, so consecutive ids differ
+    /// by exactly one count and generation never sleeps. The ids carry **no timestamp**:
+    /// the time bits are part of the counter and cannot be decoded to a point in time.
+    ///
+    /// Once the counter is used up, [`SonyFlake`] returns [`Error::CounterExhausted`]
+    /// and [`InfallibleSonyFlake`] starts counting from zero again.
+    ///
+    /// [`SonyFlake`]: struct.SonyFlake.html
+    /// [`InfallibleSonyFlake`]: struct.InfallibleSonyFlake.html
+    /// [`Error::CounterExhausted`]: enum.Error.html#variant.CounterExhausted
+    pub fn sequence_only_mode(mut self) -> Self {
+        self.sequence_only = true;
+        self
     }
-}
-
-/// SonyFlake is a distributed unique ID generator, may fail to generate unique id if time overflows.
-#[derive(Debug)]
-pub struct SonyFlake {
-    start_time: i64,
-    machine_id: u16,
-    inner: Arc<Mutex<Inner>>,
-}
 
-impl SonyFlake {
-    /// Create a new SonyFlake with the default configuration.
-    /// For custom configuration see [`builder`].
+    /// Extend the sequence into the top `n` bits of the machine id, multiplying the number of
+    /// IDs per 10 msec by `2^n` without changing the layout of an ID.
     ///
-    /// [`builder`]: struct.SonyFlake.html#method.builder
-    pub fn new(st: Settings) -> Result<Self, Error> {
-        let sequence = 1 << (BIT_LEN_SEQUENCE - 1);
-
-        let start_time = st.get_start_time()?;
+    /// This is for deployments which use far fewer machine ids than 2^16: the top `n` bits of this
+    /// node's machine id must be zero, otherwise finalize will fail. `n` can be at most 8.
+    /// IDs generated this way decompose with [`decompose_borrowed`].
+    ///
+    /// [`decompose_borrowed`]: fn.decompose_borrowed.html
+    pub fn borrow_machine_bits_for_sequence(mut self, n: u8) -> Self {
+        self.borrowed_bits = n;
+        self
+    }
 
-        let machine_id = st.get_and_check_machine_id()?;
+    /// Set the strategy for the very first id, see [`FirstIdStrategy`].
+    /// Use `FirstIdStrategy::Sequential` for predictable first ids, e.g. in tests comparing against golden ids.
+    ///
+    /// [`FirstIdStrategy`]: enum.FirstIdStrategy.html
+    pub fn set_first_id_strategy(mut self, strategy: FirstIdStrategy) -> Self {
+        self.first_id_strategy = strategy;
+        self
+    }
 
-        Ok(SonyFlake {
-            start_time,
-            machine_id,
-            inner: Arc::new(Mutex::new(Inner {
-                sequence,
-                elapsed_time: 0,
-            })),
-        })
+    /// Set the sequence of the very first id, overriding the [`FirstIdStrategy`]:
+    /// the first id has sequence `sequence` whatever the start time, and the ids after it count up from there.
+    /// `set_sequence_start(0)` is the same as `FirstIdStrategy::Sequential`.
+    /// Building the generator fails with `Error::SequenceOutOfRange` if `sequence` does not fit the sequence bits.
+    ///
+    /// [`FirstIdStrategy`]: enum.FirstIdStrategy.html
+    pub fn set_sequence_start(mut self, sequence: u16) -> Self {
+        self.sequence_start = Some(sequence);
+        self
     }
 
-    /// Generate the next unique id.
-    /// After the SonyFlake time overflows, next_id returns an error.
-    pub fn next_id(&mut self) -> Result<u64, Error> {
-        let mask_sequence = (1 << BIT_LEN_SEQUENCE) - 1;
-        
-        let mut inner = self.inner.lock();
+    /// Set how `next_id` waits for the next time window when the sequence of the current one is used up,
+    /// see [`WaitStrategy`]. `SonyFlake::next_id_async` always awaits a `tokio` timer instead.
+    ///
+    /// [`WaitStrategy`]: enum.WaitStrategy.html
+    pub fn set_wait_strategy(mut self, strategy: WaitStrategy) -> Self {
+        self.wait_strategy = strategy;
+        self
+    }
 
-        let current = current_elapsed_time(self.start_time);
+    /// Set the namespace of the string IDs returned by `SonyFlake::next_prefixed`, e.g. `ord` for `ord_3F8aZ1`.
+    /// The prefix must be non-empty and must not contain the `_` separator, otherwise finalize will fail.
+    pub fn set_id_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.id_prefix = Some(prefix.into());
+        self
+    }
 
-        if inner.elapsed_time < current {
-            inner.elapsed_time = current;
-            inner.sequence = 0;
-        } else {
-            // self.elapsed_time >= current
-            inner.sequence = (inner.sequence + 1) & mask_sequence;
-            if inner.sequence == 0 {
-                inner.elapsed_time += 1;
-                let overtime = inner.elapsed_time - current;
-                std::thread::sleep(sleep_time(overtime));
-            }
-        }
+    /// Reserve the top `n` bits of the sequence for a caller-supplied tag, e.g. a 4-bit record type,
+    /// passed to `SonyFlake::next_id_tagged` and read back with `IDParts::get_tag`.
+    ///
+    /// The sequence keeps only `8 - n` bits for each tag, so a generator can produce at most
+    /// 2^(8 - n) IDs per 10 msec. `n` can be at most 7, otherwise finalize will fail.
+    pub fn reserve_tag_bits(mut self, n: u8) -> Self {
+        self.tag_bits = n;
+        self
+    }
 
-        if inner.elapsed_time >= 1 << BIT_LEN_TIME {
-            return Err(Error::TimeOverflow);
-        }
+    /// Make `SonyFlake::next_id` return `Error::RateExceeded` instead of sleeping once the sequence
+    /// has wrapped `n` times in a row without the generator catching up with the clock.
+    ///
+    /// Every wrap of the sequence makes `next_id` sleep until the next 10 msec window, so under
+    /// sustained overload callers keep stalling. This surfaces the overload instead; the count is
+    /// reset whenever a call finds the clock past the generator's window. A call returning the
+    /// error leaves the generator untouched.
+    pub fn set_max_consecutive_sleeps(mut self, n: u32) -> Self {
+        self.max_consecutive_sleeps = Some(n);
+        self
+    }
 
-        Ok(to_id(inner.elapsed_time, inner.sequence, self.machine_id))
+    /// Sets how far the clock may fall behind the time of the last id, 1 sec by default, before
+    /// `SonyFlake::next_id` returns `Error::ClockMovedBackwards` instead of waiting for the clock to catch up.
+    ///
+    /// Within the tolerance, e.g. after a small NTP adjustment, the generator carries on from the time of
+    /// the last id, sleeping as the sequence wraps. A larger backward step, e.g. on a VM migration, is
+    /// surfaced instead of stalling callers for as long as the step.
+    pub fn set_clock_regression_tolerance(mut self, tolerance: Duration) -> Self {
+        self.clock_regression_tolerance = tolerance;
+        self
     }
-}
 
-/// Returns a new `SonyFlake` referencing the same state as `self`.
-impl Clone for SonyFlake {
-    fn clone(&self) -> Self {
-        Self {
-            start_time: self.start_time,
-            machine_id: self.machine_id,
-            inner: self.inner.clone(),
-        }
+    /// Measure the time part of IDs from the start of the current UTC day instead of the start time,
+    /// for systems which only need IDs to be unique within a day and want small IDs: the time part
+    /// stays below 2^23.
+    ///
+    /// The generator restarts its sequence at every UTC midnight, so IDs are only unique and
+    /// comparable within a day, and the date is needed to interpret them in full, see [`rolling_timestamp`].
+    /// The start time is ignored in this mode.
+    ///
+    /// [`rolling_timestamp`]: fn.rolling_timestamp.html
+    pub fn daily_rolling_epoch(mut self) -> Self {
+        self.daily_rolling = true;
+        self
     }
-}
 
-/// InfallibleSonyFlake is a distributed unique ID generator, which will always generate a unique id.
-/// If time overflows, it will refresh the start time to current time.
-#[derive(Debug)]
-pub struct InfallibleSonyFlake {
-    start_time: i64,
-    machine_id: u16,
-    inner: Arc<Mutex<Inner>>,
-}
+    /// Emit IDs with their bits reversed by [`bit_reverse_id`], so the fast-changing sequence and time
+    /// bits move to the high half of the ID and consecutive IDs differ in their high bits, spreading
+    /// them across the buckets of hash tables which bucket by the high bits.
+    ///
+    /// Reversed IDs are **not** time-sortable and do not decompose; apply `bit_reverse_id` again
+    /// to recover the original ID.
+    ///
+    /// [`bit_reverse_id`]: fn.bit_reverse_id.html
+    pub fn enable_bit_reversal(mut self) -> Self {
+        self.bit_reversal = true;
+        self
+    }
 
-impl InfallibleSonyFlake {
-    /// Create a new SonyFlake with the default configuration.
-    /// For custom configuration see [`builder`].
+    /// XOR the machine id bits of every ID with a mask derived from `key` and the time and sequence
+    /// of the ID, so the machine id bits vary from ID to ID and IDs of the same machine are harder to
+    /// correlate. IDs stay unique, as all machines sharing the key apply the same mask to an ID's time
+    /// and sequence, and time-sortable, as the time and sequence bits are left as they are. With a layout
+    /// putting the machine id above the sequence, such as [`BitLayout::SNOWFLAKE`], the IDs of a time unit
+    /// are no longer in increasing order though.
     ///
-    /// [`builder`]: struct.SonyFlake.html#method.builder
-    pub fn new(st: Settings) -> Result<Self, Error> {
-        let sequence = 1 << (BIT_LEN_SEQUENCE - 1);
+    /// This is obfuscation, not encryption: anyone with the key, or enough IDs to guess it, recovers
+    /// the machine ids. Decode the IDs with [`decompose_obfuscated`], or `decompose_obfuscated_with_layout`
+    /// for a custom layout, and the same key; `next_id_fields` returns the plain fields.
+    ///
+    /// [`decompose_obfuscated`]: fn.decompose_obfuscated.html
+    /// [`BitLayout::SNOWFLAKE`]: struct.BitLayout.html#associatedconstant.SNOWFLAKE
+    pub fn set_machine_id_obfuscation(mut self, key: u64) -> Self {
+        self.machine_id_obfuscation = Some(key);
+        self
+    }
 
-        let start_time = st.get_start_time()?;
+    /// Watch the clock for degraded resolution at runtime: every `every` ids, measure the fraction of
+    /// clock reads which were identical to the previous read, and call `hook` if it is at least `threshold`.
+    ///
+    /// A clock which silently stops advancing leaves the generator relying entirely on the sequence
+    /// and sleeping, until the ids borrow so much time from the future that the time bits overflow.
+    /// Note that under a sustained load of more than one id per 10 msec identical reads are expected,
+    /// so pick the threshold for the expected load.
+    pub fn set_clock_stall_hook(mut self, every: u32, threshold: f64, hook: Box<dyn ClockStallHook>) -> Self {
+        self.clock_sampler = Some(ClockSampler::new(every, threshold, hook));
+        self
+    }
 
-        let machine_id = st.get_and_check_machine_id()?;
+    /// Read the current time from `clock` instead of the crate's clock. A `NanoClock` returns
+    /// nanoseconds directly, so reading it involves no `DateTime` conversion.
+    pub fn set_nano_clock(mut self, clock: Box<dyn NanoClock>) -> Self {
+        self.nano_clock = Some(clock);
+        self
+    }
 
-        Ok(Self {
-            start_time,
-            machine_id,
-            inner: Arc::new(Mutex::new(Inner {
-                sequence,
-                elapsed_time: 0,
-            })),
-        })
+    /// Read the current time from `clock` instead of the crate's clock, everywhere a generator needs it:
+    /// to check the start time, to generate ids and to refresh the start time of an `InfallibleSonyFlake`.
+    /// It replaces a clock set by `set_nano_clock`, and the other way around.
+    pub fn set_clock(mut self, clock: Box<dyn Clock>) -> Self {
+        self.nano_clock = Some(Box::new(ClockNanos(clock)));
+        self
     }
 
-    /// Generate the next unique id.
-    /// After the SonyFlake time overflows, next_id returns an error.
-    pub fn next_id(&mut self) -> u64 {
-        let mask_sequence = (1 << BIT_LEN_SEQUENCE) - 1;
+    /// Sets the time base of the generator, the wall clock by default, see [`TimeSource`].
+    /// It replaces a clock set by `set_clock` or `set_nano_clock`.
+    ///
+    /// With `TimeSource::Monotonic` the ids are immune to steps of the wall clock, at the cost of the
+    /// meaning of their time part: it counts time since the start time as measured by a monotonic clock
+    /// anchored when the generator is built, which drifts from the wall clock, so decoding the time
+    /// of the ids only gives an approximation of when they were generated.
+    ///
+    /// [`TimeSource`]: enum.TimeSource.html
+    pub fn set_time_source(mut self, time_source: TimeSource) -> Self {
+        self.nano_clock = match time_source {
+            TimeSource::WallClock => None,
+            TimeSource::Monotonic => Some(Box::new(MonotonicNanoClock::new())),
+        };
+        self
+    }
 
-        let mut inner = self.inner.lock();
+    /// Sets the time unit of the time part of the ids, 10 msec by default.
+    ///
+    /// The time part has 39 bits, so the unit trades the lifetime of the ids for throughput:
+    /// a generator hands out at most 2^8 ids per unit, and runs out of time 2^39 units after the start time.
+    /// With 1 msec, that is 256000 ids per second for about 17 years; with the default 10 msec,
+    /// 25600 ids per second for about 174 years. If the unit is zero, longer than a day, or so long that the
+    /// time part of the bit layout overflows nanoseconds (e.g. 1 sec with 39 bits), finalize will fail.
+    ///
+    /// The time part of the ids is then counted in this unit; decode it with `SonyFlake::timestamp_of`.
+    pub fn set_time_unit(mut self, time_unit: Duration) -> Self {
+        self.time_unit = time_unit;
+        self
+    }
+
+    /// Sets the allocation of the bits of the ids to time, sequence and machine id, SonyFlake's 39, 8 and 16 bits
+    /// by default. The machine id must fit the machine id bits and the time unit the time bits, see
+    /// `set_time_unit`, or finalize will fail.
+    ///
+    /// Decode the ids of a custom layout with [`BitLayout::decompose`]; the crate's free functions,
+    /// such as [`decompose`], assume the default layout.
+    ///
+    /// [`BitLayout::decompose`]: struct.BitLayout.html#method.decompose
+    /// [`decompose`]: fn.decompose.html
+    pub fn set_bit_layout(mut self, layout: BitLayout) -> Self {
+        self.bit_layout = layout;
+        self
+    }
 
-        let current = current_elapsed_time(self.start_time);
+    /// Call `on_exhaustion` when the sequence of a 10 msec window is used up, instead of sleeping until
+    /// the next window right away. If it returns a spare machine id, the generator carries on in the same
+    /// window with that machine id and the sequence starting over at 0; if it returns `None`, it sleeps.
+    ///
+    /// This keeps throughput up under bursts, at the cost of spending spare machine ids: the caller must
+    /// make sure no other generator uses them. A spare machine id is used until the window ends.
+    pub fn set_on_exhaustion(mut self, on_exhaustion: Box<dyn FnMut() -> Option<u16> + Send>) -> Self {
+        self.on_exhaustion = Some(ExhaustionHook(on_exhaustion));
+        self
+    }
 
-        if inner.elapsed_time < current {
-            inner.elapsed_time = current;
-            inner.sequence = 0;
-        } else {
-            // self.elapsed_time >= current
-            inner.sequence = (inner.sequence + 1) & mask_sequence;
-            if inner.sequence == 0 {
-                inner.elapsed_time += 1;
-                let overtime = inner.elapsed_time - current;
-                std::thread::sleep(sleep_time(overtime));
+    /// Report the generation events of the generator to `metrics`, e.g. to count the ids generated,
+    /// the waits for the next window and overflows. Without metrics, no events are reported.
+    pub fn set_metrics(mut self, metrics: Box<dyn Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Sets the fraction of the time bits' range past which `SonyFlake::next_id` calls the callback set by
+    /// `set_on_overflow_approaching`, 0.95 by default.
+    pub fn set_overflow_threshold(mut self, threshold: f64) -> Self {
+        self.overflow_threshold = threshold;
+        self
+    }
+
+    /// Call `on_overflow_approaching` once, from `SonyFlake::next_id`, when the time part of the ids crosses
+    /// the overflow threshold, see `set_overflow_threshold`. It receives the elapsed time of the id and the limit
+    /// of the time part, `1 << time_bits`, at which `next_id` starts returning `Error::TimeOverflow`.
+    ///
+    /// This gives operators time to move to a new start time or layout before the generator stops.
+    pub fn set_on_overflow_approaching(mut self, on_overflow_approaching: Box<dyn FnMut(i64, i64) + Send>) -> Self {
+        self.on_overflow_approaching = Some(on_overflow_approaching);
+        self
+    }
+
+    /// Refuse to create the generator, with `Error::DuplicateMachineId`, while another generator
+    /// with the same machine id and start time is alive in this process, as the two would emit
+    /// colliding ids. Clones of a generator share its state, so they are not duplicates.
+    #[cfg(feature = "duplicate-guard")]
+    pub fn guard_duplicate_machine_id(mut self) -> Self {
+        self.guard_duplicates = true;
+        self
+    }
+
+    /// Check the settings for every problem `into_sonyflake` would fail on, without building the generator,
+    /// e.g. to report all misconfigurations at once in a setup wizard. Returns no errors if the settings are valid.
+    ///
+    /// The machine id source set by `set_machine_id` is not queried, as that may have side effects, so errors
+    /// of the machine id are only found with the default machine id. Problems only found at construction,
+    /// such as `Error::DuplicateMachineId`, are not reported either.
+    pub fn validate(&self) -> Vec<Error> {
+        let mut errors = Vec::new();
+
+        let time_unit = self.get_time_unit().unwrap_or_else(|e| {
+            errors.push(e);
+            FLAKE_TIME_UNIT
+        });
+        if let Err(e) = self.get_start_time_in(time_unit) {
+            errors.push(e);
+        }
+
+        let layout = self.bit_layout;
+        if self.tag_bits as i64 >= layout.sequence {
+            errors.push(Error::InvalidTagBits(self.tag_bits));
+        } else if let Err(e) = self.check_sequence_start(sequence_bits(layout, self.tag_bits, self.borrowed_bits)) {
+            errors.push(e);
+        }
+        if let Some(prefix) = &self.id_prefix {
+            if prefix.is_empty() || prefix.contains(PREFIX_SEPARATOR) {
+                errors.push(Error::InvalidPrefix(prefix.clone()));
+            }
+        }
+        if let Some((elapsed_time, sequence)) = self.snapshot_state {
+            if !(0..1 << layout.time).contains(&elapsed_time) {
+                errors.push(Error::TimeOverflow);
+            }
+            let sequence_bits = sequence_bits(layout, self.tag_bits, self.borrowed_bits);
+            if (self.tag_bits as i64) < layout.sequence && sequence as u64 >= 1 << sequence_bits {
+                errors.push(Error::SequenceOutOfRange(sequence));
             }
         }
 
-        if inner.elapsed_time >= 1 << BIT_LEN_TIME {
-            let now = Utc::now();
-            // let today = Utc::today().and_hms(now.hour(), now.minute(), now.second());
-            self.start_time = to_sonyflake_time(now, );
-            inner.elapsed_time = 0;
-            inner.sequence = 0;
-            return to_id(inner.elapsed_time, inner.sequence, self.machine_id);
+        if self.machine_id.is_none() {
+            if let Err(e) = default_machine_id(self.ipv6_fallback)
+                .and_then(|machine_id| self.check_machine_id_value(machine_id))
+                .and_then(|machine_id| check_borrowed_bits(layout, self.borrowed_bits, machine_id))
+            {
+                errors.push(e);
+            }
+        } else if self.borrowed_bits as i64 > layout.machine_id.min(16 - layout.sequence) {
+            errors.push(Error::InvalidBorrowedBits(self.borrowed_bits));
         }
+        errors
+    }
+
+    pub fn into_sonyflake(self) -> Result<SonyFlake, Error> {
+        SonyFlake::new(self)
+    }
+
+    pub fn into_infallible_sonyflake(self) -> Result<InfallibleSonyFlake, Error> {
+        InfallibleSonyFlake::new(self)
+    }
+
+    pub fn into_sonyflake32(self) -> Result<SonyFlake32, Error> {
+        SonyFlake32::new(self)
+    }
 
-        to_id(inner.elapsed_time, inner.sequence, self.machine_id)
+    pub fn into_sonyflake128(self) -> Result<SonyFlake128, Error> {
+        SonyFlake128::new(self)
     }
 }
 
-/// Returns a new `InfallibleSonyFlake` referencing the same state as `self`.
-impl Clone for InfallibleSonyFlake {
-    fn clone(&self) -> Self {
-        Self {
-            start_time: self.start_time,
-            machine_id: self.machine_id,
-            inner: self.inner.clone(),
+/// SonyFlake is a distributed unique ID generator, may fail to generate unique id if time overflows.
+#[derive(Debug)]
+#[cfg(feature = "std")]
+pub struct SonyFlake {
+    time_unit: i64,
+    layout: BitLayout,
+    machine_id: u16,
+    sequence_only: bool,
+    daily_rolling: bool,
+    bit_reversal: bool,
+    obfuscation_key: Option<u64>,
+    borrowed_bits: u8,
+    tag_bits: u8,
+    max_consecutive_sleeps: Option<u32>,
+    /// in time units
+    clock_regression_tolerance: i64,
+    id_prefix: Option<String>,
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[cfg(feature = "std")]
+impl SonyFlake {
+    /// Create a new SonyFlake with the default configuration.
+    /// For custom configuration see [`builder`].
+    ///
+    /// [`builder`]: struct.SonyFlake.html#method.builder
+    pub fn new<M: MachineID, C: MachineIDChecker>(mut st: Settings<M, C>) -> Result<Self, Error> {
+        let sequence_only = st.sequence_only;
+        let daily_rolling = st.daily_rolling;
+        let bit_reversal = st.bit_reversal;
+        let obfuscation_key = st.machine_id_obfuscation;
+        let time_unit = st.get_time_unit()?;
+        // before the clock moves into the state
+        let start_time = st.get_start_time()?;
+        let layout = st.bit_layout;
+        let inner = Inner {
+            start_time,
+            ..Inner::new(&mut st, time_unit)
+        };
+        let borrowed_bits = st.borrowed_bits;
+        let id_prefix = st.id_prefix.take();
+        let tag_bits = st.tag_bits;
+        let max_consecutive_sleeps = st.max_consecutive_sleeps;
+        let clock_regression_tolerance =
+            (st.clock_regression_tolerance.as_nanos() / time_unit as u128).min(i64::MAX as u128) as i64;
+        let snapshot_state = st.snapshot_state;
+        if tag_bits as i64 >= layout.sequence {
+            return Err(Error::InvalidTagBits(tag_bits));
+        }
+        st.check_sequence_start(sequence_bits(layout, tag_bits, borrowed_bits))?;
+        if let Some(prefix) = &id_prefix {
+            if prefix.is_empty() || prefix.contains(PREFIX_SEPARATOR) {
+                return Err(Error::InvalidPrefix(prefix.clone()));
+            }
+        }
+        #[cfg(feature = "duplicate-guard")]
+        let guard_duplicates = st.guard_duplicates;
+
+        let machine_id = st.get_and_check_machine_id()?;
+        check_borrowed_bits(layout, borrowed_bits, machine_id)?;
+
+        #[cfg(feature = "duplicate-guard")]
+        let inner = Inner {
+            registration: if guard_duplicates {
+                Some(guard::Registration::new(machine_id, start_time)?)
+            } else {
+                None
+            },
+            ..inner
+        };
+
+        let sf = SonyFlake {
+            time_unit,
+            layout,
+            machine_id,
+            sequence_only,
+            daily_rolling,
+            bit_reversal,
+            obfuscation_key,
+            borrowed_bits,
+            tag_bits,
+            max_consecutive_sleeps,
+            clock_regression_tolerance,
+            id_prefix,
+            inner: Arc::new(Mutex::new(inner)),
+        };
+        if let Some((elapsed_time, sequence)) = snapshot_state {
+            sf.restore_state(elapsed_time, sequence)?;
         }
+        Ok(sf)
     }
-}
 
-fn private_ipv4() -> Option<Ipv4Addr> {
-    interfaces()
-        .iter()
-        .filter(|interface| interface.is_up() && !interface.is_loopback())
-        .map(|interface| {
-            interface
-                .ips
-                .iter()
-                .map(|ip_addr| ip_addr.ip()) // convert to std
-                .find(|ip_addr| match ip_addr {
-                    IpAddr::V4(ipv4) => is_private_ipv4(*ipv4),
-                    IpAddr::V6(_) => false,
-                })
-                .and_then(|ip_addr| match ip_addr {
-                    IpAddr::V4(ipv4) => Some(ipv4), // make sure the return type is Ipv4Addr
-                    _ => None,
-                })
+    /// Create a new SonyFlake resuming from an explicit state, e.g. recovered after a crash or for a
+    /// deterministic replay in tests: the next id is the one right after the id with time `elapsed_time`
+    /// and sequence `sequence`, see [`restore_state`]. All other settings take their defaults, so a state
+    /// more than a second ahead of the clock makes `next_id` return `Error::ClockMovedBackwards` until it catches up.
+    ///
+    /// Fails with `Error::TimeOverflow` or `Error::SequenceOutOfRange` if the state does not fit the bits
+    /// of the ids, and like `Settings::set_start_time` for an invalid start time.
+    ///
+    /// [`restore_state`]: struct.SonyFlake.html#method.restore_state
+    pub fn from_state(start_time: DateTime<Utc>, machine_id: u16, elapsed_time: i64, sequence: u16) -> Result<Self, Error> {
+        let mut st = Settings::new().set_start_time(start_time).set_machine_id_value(machine_id);
+        st.snapshot_state = Some((elapsed_time, sequence));
+        Self::new(st)
+    }
+
+    /// Create a new SonyFlake with a machine id which is already known to be valid, e.g. on a hot restart.
+    ///
+    /// Neither the machine id source, the IP lookup nor the checker runs, so construction is as cheap as
+    /// possible; only the start time is validated. Any `u16` fits the 16 machine id bits.
+    /// All other settings take their defaults.
+    pub fn with_verified_machine_id(machine_id: u16, start_time: DateTime<Utc>) -> Result<Self, Error> {
+        Self::new(Settings::new().set_start_time(start_time).set_machine_id_value(machine_id))
+    }
+
+    /// Generate the next unique id.
+    /// After the SonyFlake time overflows, next_id returns an error.
+    pub fn next_id(&self) -> Result<u64, Error> {
+        self.next_id_with_wait().map(|(id, _)| id)
+    }
+
+    /// Generate the next unique id like `next_id`, as an [`Id`] rather than a bare `u64`.
+    ///
+    /// [`Id`]: struct.Id.html
+    pub fn next_flake_id(&self) -> Result<Id, Error> {
+        self.next_id().map(Id)
+    }
+
+    /// Generate the next unique id like `next_id`, also returning how long it slept for the next
+    /// 10 msec window because the sequence of the current one was used up, zero if it did not.
+    /// A caller seeing nonzero waits is saturating the generator and may spread the load over more of them.
+    ///
+    /// The wait happens after the lock on the state is released, so other threads keep generating ids,
+    /// from the next window, in the meantime.
+    pub fn next_id_with_wait(&self) -> Result<(u64, Duration), Error> {
+        let mut inner = self.inner.lock();
+        let ((elapsed_time, sequence, machine_id), wait) = self.reserve_id_fields(&mut inner)?;
+        wait_unlocked(inner, wait);
+        Ok((self.finish_id(self.layout.compose(elapsed_time, sequence, machine_id)), wait))
+    }
+
+    /// Generate the next unique id like `next_id`, but when the sequence of the current 10 msec window is
+    /// used up, wait for the next window with `tokio::time::sleep` instead of blocking the thread.
+    ///
+    /// The id is reserved under the lock before waiting, and the lock is not held across the await,
+    /// so the generator can be shared by the tasks of an async server without stalling the executor.
+    /// It fails like `next_id` when the time overflows; `InfallibleSonyFlake::next_id_async` rebases instead.
+    #[cfg(feature = "tokio")]
+    pub async fn next_id_async(&self) -> Result<u64, Error> {
+        let ((elapsed_time, sequence, machine_id), wait) = {
+            let mut inner = self.inner.lock();
+            self.reserve_id_fields(&mut inner)?
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+        Ok(self.finish_id(self.layout.compose(elapsed_time, sequence, machine_id)))
+    }
+
+    /// Generate the next unique id, encoded to a string by `codec`.
+    pub fn next_encoded(&self, codec: &dyn IdCodec) -> Result<String, Error> {
+        self.next_id().map(|id| codec.encode(id))
+    }
+
+    /// Generate the next unique id carrying `tag` in the tag bits reserved by `Settings::reserve_tag_bits`.
+    /// Returns `Error::InvalidTag` if the tag does not fit in the reserved bits.
+    /// `next_id` generates ids with tag 0.
+    pub fn next_id_tagged(&self, tag: u8) -> Result<u64, Error> {
+        if tag as u16 >= 1 << self.tag_bits {
+            return Err(Error::InvalidTag(tag));
+        }
+
+        let (elapsed_time, sequence, machine_id) = self.next_id_fields()?;
+        let sequence = sequence | (tag as u16) << (self.layout.sequence - self.tag_bits as i64);
+        Ok(self.finish_id(self.layout.compose(elapsed_time, sequence, machine_id)))
+    }
+
+    fn finish_id(&self, id: u64) -> u64 {
+        let id = match self.obfuscation_key {
+            Some(key) => obfuscate_machine_id(self.layout, id, key),
+            None => id,
+        };
+        if self.bit_reversal {
+            bit_reverse_id(id)
+        } else {
+            id
+        }
+    }
+
+    /// Generate the next unique id as a base62 string in the namespace set by `Settings::set_id_prefix`,
+    /// e.g. `ord_3F8aZ1`. Without a prefix, the bare base62 id is returned.
+    pub fn next_prefixed(&self) -> Result<String, Error> {
+        let id = self.next_encoded(&Base62Codec)?;
+        Ok(match &self.id_prefix {
+            Some(prefix) => format!("{}{}{}", prefix, PREFIX_SEPARATOR, id),
+            None => id,
         })
-        .find(|ip| ip.is_some())
-        .flatten()
-}
+    }
 
-fn is_private_ipv4(ip: Ipv4Addr) -> bool {
-    let octets = ip.octets();
-    octets[0] == 10
-        || octets[0] == 172 && (octets[1] >= 16 && octets[1] < 32)
-        || octets[0] == 192 && octets[1] == 168
-}
+    /// Parse an id returned by `next_prefixed` back to the numeric id.
+    /// Returns `Error::InvalidEncodedID` if `s` is not in this generator's namespace or is not a valid id.
+    pub fn strip_prefix_and_decode(&self, s: &str) -> Result<u64, Error> {
+        let id = match &self.id_prefix {
+            Some(prefix) => s
+                .strip_prefix(prefix.as_str())
+                .and_then(|rest| rest.strip_prefix(PREFIX_SEPARATOR))
+                .ok_or_else(|| Error::InvalidEncodedID(s.to_string()))?,
+            None => s,
+        };
+        Base62Codec.decode(id)
+    }
 
-fn lower_16_bit_private_ip() -> Result<u16, Error> {
-    match private_ipv4() {
-        Some(ip) => {
-            let octets = ip.octets();
-            Ok(((octets[2] as u16) << 8) + (octets[3] as u16))
+    /// Generate the next unique id as its `(elapsed_time, sequence, machine_id)` fields instead of the packed id,
+    /// e.g. for tables storing them in separate columns. [`compose`] packs the fields into the id `next_id` would return.
+    ///
+    /// [`compose`]: fn.compose.html
+    pub fn next_id_fields(&self) -> Result<(i64, u16, u16), Error> {
+        let mut inner = self.inner.lock();
+        let (fields, wait) = self.reserve_id_fields(&mut inner)?;
+        wait_unlocked(inner, wait);
+        Ok(fields)
+    }
+
+    /// Generate `n` unique ids at once, locking the state only once instead of once per id,
+    /// and sleeping across 10 msec windows while holding the lock. The ids are in increasing order,
+    /// unless bit reversal is enabled. If generation fails partway, the error is returned and
+    /// the ids generated so far are dropped.
+    pub fn next_ids(&self, n: usize) -> Result<Vec<u64>, Error> {
+        let mut inner = self.inner.lock();
+        (0..n)
+            .map(|_| {
+                let (elapsed_time, sequence, machine_id) = self.next_id_fields_locked(&mut inner)?;
+                Ok(self.finish_id(self.layout.compose(elapsed_time, sequence, machine_id)))
+            })
+            .collect()
+    }
+
+    /// Generate `n` unique ids at once like `next_ids`, but if generation fails partway, e.g. with
+    /// `Error::TimeOverflow` when the time bits run out mid-batch, return the ids generated so far with the error,
+    /// so the caller knows exactly how many succeeded. The error is `None` if all `n` ids were generated.
+    pub fn take_ids(&self, n: usize) -> (Vec<u64>, Option<Error>) {
+        let mut inner = self.inner.lock();
+        let mut ids = Vec::with_capacity(n);
+        for _ in 0..n {
+            match self.next_id_fields_locked(&mut inner) {
+                Ok((elapsed_time, sequence, machine_id)) => {
+                    ids.push(self.finish_id(self.layout.compose(elapsed_time, sequence, machine_id)))
+                }
+                Err(e) => return (ids, Some(e)),
+            }
         }
-        None => Err(Error::NoPrivateIPv4Address),
+        (ids, None)
     }
-}
 
-#[derive(Debug)]
-struct Inner {
-    elapsed_time: i64,
-    sequence: u16,
-}
+    /// Generate the next unique id without ever sleeping: if the current 10 msec window is saturated,
+    /// or the time bits are about to overflow, return a [`Backpressure`] telling the caller when to retry,
+    /// so a scheduler can apply backpressure instead of blocking a thread. A window is only saturated
+    /// once the hook set by `Settings::set_on_exhaustion` has no spare machine id left.
+    ///
+    /// [`Backpressure`]: enum.Backpressure.html
+    pub fn next_id_checked(&self) -> Result<u64, Backpressure> {
+        let mut inner = self.inner.lock();
 
-fn to_id(elapsed_time: i64, seq: u16, machine_id: u16) -> u64 {
-    (elapsed_time as u64) << (BIT_LEN_SEQUENCE + BIT_LEN_MACHINE_ID)
-        | (seq as u64) << BIT_LEN_MACHINE_ID
-        | (machine_id as u64)
-}
+        if !self.sequence_only {
+            let start_time = inner.start_time;
+            let current = inner.current(start_time, self.daily_rolling);
+            if inner.must_wait(current, sequence_bits(self.layout, self.tag_bits, self.borrowed_bits)) {
+                if inner.elapsed_time + 1 >= 1 << self.layout.time {
+                    return Err(Backpressure::OverflowImminent);
+                }
+                let retry_after = inner.clock.sleep_time(inner.elapsed_time + 1 - current);
+                return Err(Backpressure::Saturated { retry_after });
+            }
+        }
 
-fn to_sonyflake_time(time: DateTime<Utc>) -> i64 {
-    time.timestamp_nanos() / FLAKE_TIME_UNIT
-}
+        match self.next_id_fields_locked(&mut inner) {
+            Ok((elapsed_time, sequence, machine_id)) => {
+                Ok(self.finish_id(self.layout.compose(elapsed_time, sequence, machine_id)))
+            }
+            Err(Error::RateExceeded) => Err(Backpressure::Saturated {
+                retry_after: Duration::from_nanos(self.time_unit as u64),
+            }),
+            Err(Error::ClockMovedBackwards(by)) => Err(Backpressure::Saturated { retry_after: by }),
+            Err(Error::TimeOverflow) => Err(Backpressure::OverflowImminent),
+            Err(e) => Err(Backpressure::Failed(e)),
+        }
+    }
 
-fn current_elapsed_time(start_time: i64) -> i64 {
-    to_sonyflake_time(Utc::now()) - start_time
-}
+    fn next_id_fields_locked(&self, inner: &mut Inner) -> Result<(i64, u16, u16), Error> {
+        let (fields, wait) = self.reserve_id_fields(inner)?;
+        inner.wait(wait);
+        Ok(fields)
+    }
 
-fn sleep_time(overtime: i64) -> Duration {
-    Duration::from_millis(overtime as u64 * 10)
-        - Duration::from_nanos((Utc::now().timestamp_nanos() % FLAKE_TIME_UNIT) as u64)
-}
+    /// Advances the state to the next id, returning its fields and how long to wait before handing it out.
+    fn reserve_id_fields(&self, inner: &mut Inner) -> Result<((i64, u16, u16), Duration), Error> {
+        let sequence_bits = sequence_bits(self.layout, self.tag_bits, self.borrowed_bits);
 
-/// `IDParts` contains the bit parts for an ID.
-#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
-pub struct IDParts {
-    id: u64,
-    msb: u64,
-    time: u64,
-    sequence: u64,
-    machine_id: u64,
-}
+        if self.sequence_only {
+            let (elapsed_time, sequence) = match inner.next_count(sequence_bits, self.layout.time) {
+                Some(count) => count,
+                None => {
+                    inner.record_overflow();
+                    return Err(Error::CounterExhausted);
+                }
+            };
+            inner.generated += 1;
+            inner.record_id(Duration::ZERO);
+            let (sequence, machine_id) = split_borrowed(self.layout, sequence, self.machine_id, self.borrowed_bits);
+            return Ok(((elapsed_time, sequence, machine_id), Duration::ZERO));
+        }
 
-impl IDParts {
-    /// `decompose` returns a set of SonyFlake ID parts.
-    pub fn decompose(id: u64) -> Self {
-        decompose(id)
+        let current = inner.current(inner.start_time, self.daily_rolling);
+        if current >= inner.borrowed_until {
+            inner.borrowed = 0;
+        }
+        // the last id is normally at most one window ahead of the clock, after sleeping for it,
+        // plus the windows a reserved block borrowed from the future
+        let behind = inner.elapsed_time - 1 - inner.borrowed - current;
+        if behind > self.clock_regression_tolerance {
+            return Err(Error::ClockMovedBackwards(Duration::from_nanos(behind.saturating_mul(self.time_unit) as u64)));
+        }
+
+        if let Some(max_consecutive_sleeps) = self.max_consecutive_sleeps {
+            if inner.consecutive_sleeps >= max_consecutive_sleeps && inner.would_sleep(current, sequence_bits) {
+                return Err(Error::RateExceeded);
+            }
+        }
+
+        let wait = inner.advance(current, sequence_bits);
+        inner.check_overflow_approaching();
+
+        if inner.elapsed_time >= 1 << self.layout.time {
+            inner.record_overflow();
+            return Err(Error::TimeOverflow);
+        }
+
+        inner.generated += 1;
+        inner.record_id(wait);
+        let machine_id = inner.spare_machine_id.unwrap_or(self.machine_id);
+        let (sequence, machine_id) = split_borrowed(self.layout, inner.sequence, machine_id, self.borrowed_bits);
+        Ok(((inner.elapsed_time, sequence, machine_id), wait))
     }
 
-    /// `get_id` returns the original ID
-    pub fn get_id(&self) -> u64 {
-        self.id
+    /// Returns whether the generator is ahead of the clock, i.e. it has used up the sequence of
+    /// the current 10 msec window and is handing out ids from a future window.
+    /// Being in this state for long is a sign of sustained overload.
+    pub fn is_borrowing_future(&self) -> bool {
+        self.future_drift() > Duration::from_secs(0)
     }
 
-    /// `get_msb` returns msb for the id
-    pub fn get_msb(&self) -> u64 {
-        self.msb
+    /// Returns how far the generator's time is ahead of the clock, or zero if it is not borrowing from the future.
+    pub fn future_drift(&self) -> Duration {
+        if self.sequence_only {
+            return Duration::from_secs(0);
+        }
+        let inner = self.inner.lock();
+        inner.future_drift(inner.start_time)
     }
 
-    /// `get_time` returns a timestamp
-    pub fn get_time(&self) -> u64 {
-        self.time
+    /// Returns the fraction of the ID lifetime elapsed since the start time:
+    /// 0.0 at the start time, approaching 1.0 as the time bits near overflow.
+    pub fn lifetime_elapsed_fraction(&self) -> f64 {
+        let inner = self.inner.lock();
+        inner.clock.elapsed_time(inner.start_time) as f64 / (1u64 << self.layout.time) as f64
+    }
+
+    /// Returns the time left until the time part of the ids overflows and `next_id` starts returning
+    /// `Error::TimeOverflow`, counted from the time of the clock or, if the generator is borrowing from
+    /// the future, from the time of its last id. It accounts for the bit layout and the time unit.
+    pub fn remaining_lifetime(&self) -> Duration {
+        let elapsed_time = {
+            let inner = self.inner.lock();
+            inner.elapsed_time.max(inner.clock.elapsed_time(inner.start_time))
+        };
+        let nanos = ((1i64 << self.layout.time) - elapsed_time).max(0) as u128 * self.time_unit as u128;
+        Duration::new((nanos / 1_000_000_000) as u64, (nanos % 1_000_000_000) as u32)
+    }
+
+    /// Returns the start time the ids are generated from, truncated to the time unit, to decode the time of the ids,
+    /// see [`IDParts::to_datetime`].
+    ///
+    /// [`IDParts::to_datetime`]: struct.IDParts.html#method.to_datetime
+    pub fn start_time(&self) -> DateTime<Utc> {
+        from_sonyflake_time(self.inner.lock().start_time, self.time_unit)
+    }
+
+    /// Returns the time unit of the time part of the ids, see `Settings::set_time_unit`.
+    pub fn time_unit(&self) -> Duration {
+        Duration::from_nanos(self.time_unit as u64)
+    }
+
+    /// Returns the allocation of the bits of the ids, see `Settings::set_bit_layout`.
+    pub fn bit_layout(&self) -> BitLayout {
+        self.layout
+    }
+
+    /// Returns the time an id from this generator was generated at, decoding its time part
+    /// with the generator's start time and time unit.
+    pub fn timestamp_of(&self, id: u64) -> DateTime<Utc> {
+        from_sonyflake_time(self.inner.lock().start_time + self.layout.decompose(id).time as i64, self.time_unit)
+    }
+
+    /// Returns the machine id the generator was constructed with, e.g. the one derived from the private IP address.
+    pub fn machine_id(&self) -> u16 {
+        self.machine_id
+    }
+
+    /// Returns whether `id` carries the machine id of this generator, i.e. whether it was minted by this node,
+    /// e.g. to route the ids of a mixed stream to their shards. Ids minted with a spare machine id handed out
+    /// by `Settings::set_on_exhaustion` are not recognized.
+    pub fn owns(&self, id: u64) -> bool {
+        let id = if self.bit_reversal { bit_reverse_id(id) } else { id };
+        let id = match self.obfuscation_key {
+            Some(key) => obfuscate_machine_id(self.layout, id, key),
+            None => id,
+        };
+        let machine_id_bits = self.layout.machine_id - self.borrowed_bits as i64;
+        self.layout.decompose(id).machine_id & ((1 << machine_id_bits) - 1) == self.machine_id as u64
+    }
+
+    /// Returns an iterator yielding ids from a clone of the generator indefinitely, e.g. `sf.iter().take(100)`.
+    pub fn iter(&self) -> SonyFlakeIter {
+        SonyFlakeIter::new(self.clone())
+    }
+
+    /// Returns the fraction of the current 10 msec window's sequence space already used,
+    /// or 0.0 if no id has been generated in the current window yet. A momentary gauge of the
+    /// pressure on the generator.
+    pub fn current_window_fill(&self) -> f64 {
+        let mut inner = self.inner.lock();
+        let start_time = inner.start_time;
+        inner.window_fill(start_time, self.daily_rolling, sequence_bits(self.layout, self.tag_bits, self.borrowed_bits))
+    }
+
+    /// Returns a fingerprint of the generator's stable configuration: the start time and the widths
+    /// of the ID fields and the time unit, but not the machine id or any state.
+    /// Generators with the same layout and start time share a fingerprint, so a control plane
+    /// can compare fingerprints to detect configuration drift across a fleet.
+    pub fn config_fingerprint(&self) -> u64 {
+        config_fingerprint(
+            self.inner.lock().start_time * self.time_unit,
+            self.layout.time,
+            self.layout.sequence + self.borrowed_bits as i64,
+            self.layout.machine_id - self.borrowed_bits as i64,
+            self.time_unit,
+        )
+    }
+
+    /// Returns a snapshot of the generator's [`Health`], for monitoring.
+    ///
+    /// [`Health`]: struct.Health.html
+    pub fn health(&self) -> Health {
+        let (generated, elapsed_time) = {
+            let inner = self.inner.lock();
+            (inner.generated, inner.clock.elapsed_time(inner.start_time))
+        };
+        Health::new(self.machine_id, elapsed_time, self.layout.time, self.time_unit, generated, self.future_drift())
+    }
+
+    /// Returns a consistent snapshot of the generator's `(elapsed_time, sequence)` state,
+    /// e.g. to persist it and later [`restore_state`] it.
+    ///
+    /// [`restore_state`]: struct.SonyFlake.html#method.restore_state
+    pub fn state(&self) -> (i64, u16) {
+        let inner = self.inner.lock();
+        (inner.elapsed_time, inner.sequence)
+    }
+
+    /// Returns the time part of the last id generated, in time units since the start time.
+    /// Graphed against `1 << bit_layout().time_bits()`, it shows how close the generator is to the time overflow.
+    pub fn current_elapsed(&self) -> i64 {
+        self.inner.lock().elapsed_time
+    }
+
+    /// Returns the sequence of the last id generated. Sampled over time, it shows how much of each
+    /// window's sequence space the load uses up.
+    pub fn current_sequence(&self) -> u16 {
+        self.inner.lock().sequence
+    }
+
+    /// Returns a serializable [`Snapshot`] of the generator's configuration and state, to persist it
+    /// and later resume with [`Settings::from_snapshot`].
+    ///
+    /// [`Snapshot`]: struct.Snapshot.html
+    /// [`Settings::from_snapshot`]: struct.Settings.html#method.from_snapshot
+    pub fn snapshot(&self) -> Snapshot {
+        let inner = self.inner.lock();
+        Snapshot::new(inner.start_time, self.time_unit, self.machine_id, inner.elapsed_time, inner.sequence)
+    }
+
+    /// Replace the generator's state with a snapshot taken by [`state`].
+    /// Returns `Error::TimeOverflow` if the elapsed time is out of range and
+    /// `Error::SequenceOutOfRange` if the sequence does not fit the generator's sequence bits.
+    ///
+    /// [`state`]: struct.SonyFlake.html#method.state
+    pub fn restore_state(&self, elapsed_time: i64, sequence: u16) -> Result<(), Error> {
+        if !(0..1 << self.layout.time).contains(&elapsed_time) {
+            return Err(Error::TimeOverflow);
+        }
+        if sequence as u64 >= 1 << sequence_bits(self.layout, self.tag_bits, self.borrowed_bits) {
+            return Err(Error::SequenceOutOfRange(sequence));
+        }
+
+        let mut inner = self.inner.lock();
+        inner.elapsed_time = elapsed_time;
+        inner.sequence = sequence;
+        inner.first_sequence = None;
+        Ok(())
+    }
+
+    /// Rebase the generator on a new start time, e.g. during planned maintenance well before the time bits
+    /// overflow, as `InfallibleSonyFlake` does by itself on overflow. The elapsed time and sequence restart from 0.
+    /// Returns `Error::StartTimeAheadOfCurrentTime` if `new_start` is ahead of the clock.
+    ///
+    /// This breaks the monotonicity of the ids unless the time part of the new ids stays greater than that
+    /// of the old ones, i.e. unless the new start time is earlier than the old one. Ids with the same time
+    /// part from before and after the reset can also collide, so make sure the old ids are no longer in use.
+    ///
+    /// Clones of the generator share its start time, so the reset applies to all of them.
+    pub fn reset_start_time(&self, new_start: DateTime<Utc>) -> Result<(), Error> {
+        let mut inner = self.inner.lock();
+        let start_nanos = new_start.timestamp_nanos_opt().ok_or(Error::StartTimeOutOfRange(new_start))?;
+        if start_nanos > inner.clock.now_nanos() {
+            return Err(Error::StartTimeAheadOfCurrentTime(new_start));
+        }
+        let start_time = start_nanos / self.time_unit;
+
+        #[cfg(feature = "duplicate-guard")]
+        if inner.registration.is_some() && start_time != inner.start_time {
+            inner.registration = Some(guard::Registration::new(self.machine_id, start_time)?);
+        }
+
+        inner.start_time = start_time;
+        inner.elapsed_time = 0;
+        inner.sequence = 0;
+        inner.first_sequence = None;
+        inner.consecutive_sleeps = 0;
+        inner.spare_machine_id = None;
+        Ok(())
+    }
+
+    /// Move the generator to the position of an id it generated earlier, e.g. decoded from the last
+    /// persisted id, so that it resumes right after that id. Returns `Error::InvalidMachineID` if the
+    /// id was generated with another machine id.
+    pub fn seek_to(&self, parts: &IDParts) -> Result<(), Error> {
+        let machine_id_bits = self.layout.machine_id - self.borrowed_bits as i64;
+        let machine_id = (parts.machine_id & ((1 << machine_id_bits) - 1)) as u16;
+        if machine_id != self.machine_id {
+            return Err(Error::InvalidMachineID(parts.machine_id as u16));
+        }
+
+        let sequence_bits = sequence_bits(self.layout, self.tag_bits, self.borrowed_bits);
+        let count = (parts.time << sequence_bits)
+            | (parts.sequence & ((1 << (self.layout.sequence - self.tag_bits as i64)) - 1)) << self.borrowed_bits
+            | parts.machine_id >> machine_id_bits;
+
+        // in sequence-only mode the state holds the next count
+        self.inner
+            .lock()
+            .set_count(if self.sequence_only { count + 1 } else { count }, sequence_bits);
+        Ok(())
+    }
+
+    /// Reserve a block of `n` contiguous ids at once; the returned [`IdBlock`] yields them in order.
+    ///
+    /// The block starts right after the last id handed out (or at the current time) and the generator
+    /// moves past it, so later ids never collide with the block. Unlike `next_id`, this never sleeps:
+    /// a block larger than what is left of the current 10 msec window borrows time from the future,
+    /// and subsequent calls to `next_id` wait for the clock to catch up when their sequence wraps.
+    /// The borrowed time does not count against the tolerance of `Settings::set_clock_regression_tolerance`.
+    ///
+    /// [`IdBlock`]: struct.IdBlock.html
+    pub fn reserve_block(&self, n: u32) -> Result<IdBlock, Error> {
+        let sequence_bits = sequence_bits(self.layout, self.tag_bits, self.borrowed_bits);
+
+        if n == 0 {
+            return Ok(IdBlock {
+                next: 0,
+                end: 0,
+                machine_id: self.machine_id,
+                layout: self.layout,
+                tag_bits: self.tag_bits,
+                borrowed_bits: self.borrowed_bits,
+                obfuscation_key: self.obfuscation_key,
+                bit_reversal: self.bit_reversal,
+            });
+        }
+
+        let mut inner = self.inner.lock();
+
+        let (first, current, exhausted) = if self.sequence_only {
+            // in sequence-only mode the state already holds the next count
+            (inner.count(sequence_bits), 0, Error::CounterExhausted)
+        } else {
+            let start_time = inner.start_time;
+            let current = inner.current(start_time, self.daily_rolling);
+            if inner.elapsed_time < current {
+                ((current as u64) << sequence_bits, current, Error::TimeOverflow)
+            } else {
+                (inner.count(sequence_bits) + 1, current, Error::TimeOverflow)
+            }
+        };
+
+        let end = first + n as u64;
+        let last_time = ((end - 1) >> sequence_bits) as i64;
+        if last_time >= 1 << self.layout.time {
+            return Err(exhausted);
+        }
+        if !self.sequence_only && last_time > current {
+            inner.borrowed = last_time - current;
+            inner.borrowed_until = last_time;
+        }
+
+        // leave the state on the last id of the block, or on the next one in sequence-only mode
+        inner.set_count(if self.sequence_only { end } else { end - 1 }, sequence_bits);
+        inner.generated += n as u64;
+
+        Ok(IdBlock {
+            next: first,
+            end,
+            machine_id: self.machine_id,
+            layout: self.layout,
+            tag_bits: self.tag_bits,
+            borrowed_bits: self.borrowed_bits,
+            obfuscation_key: self.obfuscation_key,
+            bit_reversal: self.bit_reversal,
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+impl<M: MachineID, C: MachineIDChecker> TryFrom<Settings<M, C>> for SonyFlake {
+    type Error = Error;
+
+    /// Same as `SonyFlake::new`.
+    fn try_from(st: Settings<M, C>) -> Result<Self, Self::Error> {
+        Self::new(st)
+    }
+}
+
+/// Returns a new `SonyFlake` referencing the same state as `self`.
+#[cfg(feature = "std")]
+impl Clone for SonyFlake {
+    fn clone(&self) -> Self {
+        Self {
+            time_unit: self.time_unit,
+            layout: self.layout,
+            machine_id: self.machine_id,
+            sequence_only: self.sequence_only,
+            daily_rolling: self.daily_rolling,
+            bit_reversal: self.bit_reversal,
+            obfuscation_key: self.obfuscation_key,
+            borrowed_bits: self.borrowed_bits,
+            tag_bits: self.tag_bits,
+            max_consecutive_sleeps: self.max_consecutive_sleeps,
+            clock_regression_tolerance: self.clock_regression_tolerance,
+            id_prefix: self.id_prefix.clone(),
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+/// InfallibleSonyFlake is a distributed unique ID generator, which will always generate a unique id.
+/// If time overflows, it will refresh the start time to current time.
+#[derive(Debug)]
+#[cfg(feature = "std")]
+pub struct InfallibleSonyFlake {
+    time_unit: i64,
+    layout: BitLayout,
+    machine_id: u16,
+    sequence_only: bool,
+    daily_rolling: bool,
+    bit_reversal: bool,
+    obfuscation_key: Option<u64>,
+    borrowed_bits: u8,
+    inner: Arc<Mutex<Inner>>,
+    /// the start time, sent again on every rebase, see `subscribe_rebase`
+    #[cfg(feature = "tokio")]
+    rebase: Arc<tokio::sync::watch::Sender<DateTime<Utc>>>,
+}
+
+#[cfg(feature = "std")]
+impl InfallibleSonyFlake {
+    /// Create a new SonyFlake with the default configuration.
+    /// For custom configuration see [`builder`].
+    ///
+    /// [`builder`]: struct.SonyFlake.html#method.builder
+    pub fn new<M: MachineID, C: MachineIDChecker>(mut st: Settings<M, C>) -> Result<Self, Error> {
+        let sequence_only = st.sequence_only;
+        let daily_rolling = st.daily_rolling;
+        let bit_reversal = st.bit_reversal;
+        let obfuscation_key = st.machine_id_obfuscation;
+        let time_unit = st.get_time_unit()?;
+        // before the clock moves into the state
+        let start_time = st.get_start_time()?;
+        let layout = st.bit_layout;
+        let inner = Inner {
+            start_time,
+            ..Inner::new(&mut st, time_unit)
+        };
+        let borrowed_bits = st.borrowed_bits;
+        st.check_sequence_start(sequence_bits(layout, 0, borrowed_bits))?;
+        #[cfg(feature = "duplicate-guard")]
+        let guard_duplicates = st.guard_duplicates;
+
+        let machine_id = st.get_and_check_machine_id()?;
+        check_borrowed_bits(layout, borrowed_bits, machine_id)?;
+
+        #[cfg(feature = "duplicate-guard")]
+        let inner = Inner {
+            registration: if guard_duplicates {
+                Some(guard::Registration::new(machine_id, start_time)?)
+            } else {
+                None
+            },
+            ..inner
+        };
+
+        Ok(Self {
+            time_unit,
+            layout,
+            machine_id,
+            sequence_only,
+            daily_rolling,
+            bit_reversal,
+            obfuscation_key,
+            borrowed_bits,
+            inner: Arc::new(Mutex::new(inner)),
+            #[cfg(feature = "tokio")]
+            rebase: Arc::new(tokio::sync::watch::Sender::new(from_sonyflake_time(start_time, time_unit))),
+        })
+    }
+
+    /// Generate the next unique id.
+    /// After the SonyFlake time overflows, next_id refreshes the start time to the current time.
+    /// It waits for the next 10 msec window after releasing the lock on the state, as `SonyFlake::next_id` does.
+    pub fn next_id(&self) -> u64 {
+        let mut inner = self.inner.lock();
+        let (id, wait) = self.next_id_locked(&mut inner);
+        wait_unlocked(inner, wait);
+        id
+    }
+
+    /// Generate the next unique id like `next_id`, but when the sequence of the current 10 msec window is
+    /// used up, wait for the next window with `tokio::time::sleep` instead of blocking the thread.
+    ///
+    /// Unlike `SonyFlake::next_id_async`, it never fails: when the time overflows, it rebases to a fresh epoch
+    /// starting at the current time and sends the new start time to the receivers of `subscribe_rebase`.
+    #[cfg(feature = "tokio")]
+    pub async fn next_id_async(&self) -> u64 {
+        let (id, wait) = {
+            let mut inner = self.inner.lock();
+            self.next_id_locked(&mut inner)
+        };
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+        id
+    }
+
+    /// Returns a receiver of the start time of the generator, which changes whenever the time overflows
+    /// and the generator rebases to a fresh epoch, so consumers can react to the epoch change,
+    /// e.g. by invalidating assumptions on the time part of the ids.
+    ///
+    /// Every rebase is sent, whether `next_id`, `next_ids` or `next_id_async` generated the id which overflowed.
+    #[cfg(feature = "tokio")]
+    pub fn subscribe_rebase(&self) -> tokio::sync::watch::Receiver<DateTime<Utc>> {
+        self.rebase.subscribe()
+    }
+
+    /// Generate `n` unique ids at once, locking the state only once instead of once per id,
+    /// and sleeping across 10 msec windows while holding the lock.
+    /// The ids are in increasing order, unless bit reversal is enabled.
+    pub fn next_ids(&self, n: usize) -> Vec<u64> {
+        let mut inner = self.inner.lock();
+        (0..n)
+            .map(|_| {
+                let (id, wait) = self.next_id_locked(&mut inner);
+                inner.wait(wait);
+                id
+            })
+            .collect()
+    }
+
+    /// Advances the state to the next id, returning it and how long to wait before handing it out.
+    fn next_id_locked(&self, inner: &mut Inner) -> (u64, Duration) {
+        let (id, wait) = self.next_ordered_id(inner);
+        let id = match self.obfuscation_key {
+            Some(key) => obfuscate_machine_id(self.layout, id, key),
+            None => id,
+        };
+        if self.bit_reversal {
+            (bit_reverse_id(id), wait)
+        } else {
+            (id, wait)
+        }
+    }
+
+    fn next_ordered_id(&self, inner: &mut Inner) -> (u64, Duration) {
+        let sequence_bits = sequence_bits(self.layout, 0, self.borrowed_bits);
+
+        if self.sequence_only {
+            let (elapsed_time, sequence) = inner.next_count(sequence_bits, self.layout.time).unwrap_or_else(|| {
+                inner.elapsed_time = 0;
+                inner.sequence = 0;
+                inner.record_overflow();
+                inner.next_count(sequence_bits, self.layout.time).unwrap()
+            });
+            inner.generated += 1;
+            inner.record_id(Duration::ZERO);
+            let (sequence, machine_id) = split_borrowed(self.layout, sequence, self.machine_id, self.borrowed_bits);
+            return (self.layout.compose(elapsed_time, sequence, machine_id), Duration::ZERO);
+        }
+
+        let current = inner.current(inner.start_time, self.daily_rolling);
+        let wait = inner.advance(current, sequence_bits);
+        inner.generated += 1;
+
+        if inner.elapsed_time >= 1 << self.layout.time {
+            inner.record_overflow();
+            inner.record_id(Duration::ZERO);
+            inner.start_time = inner.clock.elapsed_time(0);
+            inner.elapsed_time = 0;
+            inner.sequence = 0;
+            #[cfg(feature = "tokio")]
+            self.rebase.send_replace(from_sonyflake_time(inner.start_time, self.time_unit));
+            return (self.layout.compose(inner.elapsed_time, inner.sequence, self.machine_id), Duration::ZERO);
+        }
+
+        inner.record_id(wait);
+        let machine_id = inner.spare_machine_id.unwrap_or(self.machine_id);
+        let (sequence, machine_id) = split_borrowed(self.layout, inner.sequence, machine_id, self.borrowed_bits);
+        (self.layout.compose(inner.elapsed_time, sequence, machine_id), wait)
+    }
+
+    /// Returns whether the generator is ahead of the clock, i.e. it has used up the sequence of
+    /// the current 10 msec window and is handing out ids from a future window.
+    /// Being in this state for long is a sign of sustained overload.
+    pub fn is_borrowing_future(&self) -> bool {
+        self.future_drift() > Duration::from_secs(0)
+    }
+
+    /// Returns how far the generator's time is ahead of the clock, or zero if it is not borrowing from the future.
+    pub fn future_drift(&self) -> Duration {
+        if self.sequence_only {
+            return Duration::from_secs(0);
+        }
+        let inner = self.inner.lock();
+        inner.future_drift(inner.start_time)
+    }
+
+    /// Returns the fraction of the ID lifetime elapsed since the start time:
+    /// 0.0 at the start time, approaching 1.0 as the time bits near overflow.
+    pub fn lifetime_elapsed_fraction(&self) -> f64 {
+        let inner = self.inner.lock();
+        inner.clock.elapsed_time(inner.start_time) as f64 / (1u64 << self.layout.time) as f64
+    }
+
+    /// Returns the start time the ids are generated from, truncated to the time unit, to decode the time of the ids,
+    /// see [`IDParts::to_datetime`]. The start time moves to the time of the overflow when the time bits overflow.
+    ///
+    /// [`IDParts::to_datetime`]: struct.IDParts.html#method.to_datetime
+    pub fn start_time(&self) -> DateTime<Utc> {
+        from_sonyflake_time(self.inner.lock().start_time, self.time_unit)
+    }
+
+    /// Returns the time unit of the time part of the ids, see `Settings::set_time_unit`.
+    pub fn time_unit(&self) -> Duration {
+        Duration::from_nanos(self.time_unit as u64)
+    }
+
+    /// Returns the allocation of the bits of the ids, see `Settings::set_bit_layout`.
+    pub fn bit_layout(&self) -> BitLayout {
+        self.layout
+    }
+
+    /// Returns the time an id from this generator was generated at, decoding its time part
+    /// with the generator's start time and time unit.
+    pub fn timestamp_of(&self, id: u64) -> DateTime<Utc> {
+        from_sonyflake_time(self.inner.lock().start_time + self.layout.decompose(id).time as i64, self.time_unit)
+    }
+
+    /// Returns the machine id the generator was constructed with, e.g. the one derived from the private IP address.
+    pub fn machine_id(&self) -> u16 {
+        self.machine_id
+    }
+
+    /// Returns an iterator yielding ids from a clone of the generator indefinitely, e.g. `sf.iter().take(100)`.
+    pub fn iter(&self) -> InfallibleSonyFlakeIter {
+        InfallibleSonyFlakeIter::new(self.clone())
+    }
+
+    /// Returns the fraction of the current 10 msec window's sequence space already used,
+    /// or 0.0 if no id has been generated in the current window yet. A momentary gauge of the
+    /// pressure on the generator.
+    pub fn current_window_fill(&self) -> f64 {
+        let mut inner = self.inner.lock();
+        let start_time = inner.start_time;
+        inner.window_fill(start_time, self.daily_rolling, sequence_bits(self.layout, 0, self.borrowed_bits))
+    }
+
+    /// Returns a fingerprint of the generator's stable configuration: the start time and the widths
+    /// of the ID fields and the time unit, but not the machine id or any state.
+    /// Generators with the same layout and start time share a fingerprint, so a control plane
+    /// can compare fingerprints to detect configuration drift across a fleet.
+    pub fn config_fingerprint(&self) -> u64 {
+        config_fingerprint(
+            self.inner.lock().start_time * self.time_unit,
+            self.layout.time,
+            self.layout.sequence + self.borrowed_bits as i64,
+            self.layout.machine_id - self.borrowed_bits as i64,
+            self.time_unit,
+        )
+    }
+
+    /// Returns a snapshot of the generator's [`Health`], for monitoring.
+    ///
+    /// [`Health`]: struct.Health.html
+    pub fn health(&self) -> Health {
+        let (generated, elapsed_time) = {
+            let inner = self.inner.lock();
+            (inner.generated, inner.clock.elapsed_time(inner.start_time))
+        };
+        Health::new(self.machine_id, elapsed_time, self.layout.time, self.time_unit, generated, self.future_drift())
+    }
+}
+
+#[cfg(feature = "std")]
+impl<M: MachineID, C: MachineIDChecker> TryFrom<Settings<M, C>> for InfallibleSonyFlake {
+    type Error = Error;
+
+    /// Same as `InfallibleSonyFlake::new`.
+    fn try_from(st: Settings<M, C>) -> Result<Self, Self::Error> {
+        Self::new(st)
+    }
+}
+
+/// Returns a new `InfallibleSonyFlake` referencing the same state as `self`.
+#[cfg(feature = "std")]
+impl Clone for InfallibleSonyFlake {
+    fn clone(&self) -> Self {
+        Self {
+            time_unit: self.time_unit,
+            layout: self.layout,
+            machine_id: self.machine_id,
+            sequence_only: self.sequence_only,
+            daily_rolling: self.daily_rolling,
+            bit_reversal: self.bit_reversal,
+            obfuscation_key: self.obfuscation_key,
+            borrowed_bits: self.borrowed_bits,
+            inner: self.inner.clone(),
+            #[cfg(feature = "tokio")]
+            rebase: self.rebase.clone(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+fn private_ipv4(interfaces: &[NetworkInterface]) -> Option<Ipv4Addr> {
+    interfaces
+        .iter()
+        .filter(|interface| interface.is_up() && !interface.is_loopback())
+        .map(|interface| {
+            interface
+                .ips
+                .iter()
+                .map(|ip_addr| ip_addr.ip()) // convert to std
+                .find(|ip_addr| match ip_addr {
+                    IpAddr::V4(ipv4) => is_private_ipv4(*ipv4),
+                    IpAddr::V6(_) => false,
+                })
+                .and_then(|ip_addr| match ip_addr {
+                    IpAddr::V4(ipv4) => Some(ipv4), // make sure the return type is Ipv4Addr
+                    _ => None,
+                })
+        })
+        .find(|ip| ip.is_some())
+        .flatten()
+}
+
+#[cfg(feature = "std")]
+fn is_private_ipv4(ip: Ipv4Addr) -> bool {
+    let octets = ip.octets();
+    octets[0] == 10
+        || octets[0] == 172 && (octets[1] >= 16 && octets[1] < 32)
+        || octets[0] == 192 && octets[1] == 168
+}
+
+#[cfg(feature = "std")]
+fn default_machine_id(ipv6_fallback: bool) -> Result<u16, Error> {
+    default_machine_id_of(&interfaces(), ipv6_fallback)
+}
+
+#[cfg(feature = "std")]
+fn lower_16_bit_private_ip_of(interfaces: &[NetworkInterface]) -> Result<u16, Error> {
+    if interfaces.is_empty() {
+        return Err(Error::NoNetworkInterfaces);
+    }
+
+    match private_ipv4(interfaces) {
+        Some(ip) => {
+            let octets = ip.octets();
+            Ok(((octets[2] as u16) << 8) + (octets[3] as u16))
+        }
+        None => Err(Error::NoPrivateIPv4Address),
+    }
+}
+
+#[cfg(feature = "std")]
+fn private_ipv6(interfaces: &[NetworkInterface]) -> Option<Ipv6Addr> {
+    let ipv6 = interfaces
+        .iter()
+        .filter(|interface| interface.is_up() && !interface.is_loopback())
+        .flat_map(|interface| interface.ips.iter())
+        .filter_map(|ip_network| match ip_network.ip() {
+            IpAddr::V6(ipv6) => Some(ipv6),
+            IpAddr::V4(_) => None,
+        })
+        .collect::<Vec<_>>();
+
+    // unique local addresses, fc00::/7, are preferred over link-local ones, fe80::/10
+    ipv6.iter()
+        .find(|ip| ip.segments()[0] & 0xfe00 == 0xfc00)
+        .or_else(|| ipv6.iter().find(|ip| ip.segments()[0] & 0xffc0 == 0xfe80))
+        .copied()
+}
+
+/// Like `lower_16_bit_private_ip_of`, falling back to the lower 16 bits of a unique local or link-local IPv6 address.
+#[cfg(feature = "std")]
+fn lower_16_bit_private_ip_or_ipv6_of(interfaces: &[NetworkInterface]) -> Result<u16, Error> {
+    match lower_16_bit_private_ip_of(interfaces) {
+        Err(Error::NoPrivateIPv4Address) => private_ipv6(interfaces)
+            .map(|ip| ip.segments()[7])
+            .ok_or(Error::NoPrivateIPAddress),
+        result => result,
+    }
+}
+
+/// `hash_to_machine_id` hashes arbitrary bytes, e.g. a hostname or a MAC address, into a 16-bit machine id
+/// with FNV-1a, xor-folded to 16 bits. The hash is fast, not cryptographic, and stable across process runs,
+/// platforms and versions of the crate, so the same host always maps to the same machine id.
+///
+/// Distinct inputs may hash to the same machine id; if two hosts of a fleet collide, pick a seed with
+/// [`hash_to_machine_id_with_seed`] instead.
+///
+/// [`hash_to_machine_id_with_seed`]: fn.hash_to_machine_id_with_seed.html
+pub fn hash_to_machine_id(bytes: &[u8]) -> u16 {
+    fold_to_16_bits(fnv1a(FNV_OFFSET_BASIS, bytes))
+}
+
+/// `hash_to_machine_id_with_seed` hashes `seed` followed by `bytes` into a 16-bit machine id like
+/// [`hash_to_machine_id`]. Every host of a fleet must use the same seed.
+///
+/// [`hash_to_machine_id`]: fn.hash_to_machine_id.html
+pub fn hash_to_machine_id_with_seed(bytes: &[u8], seed: u64) -> u16 {
+    fold_to_16_bits(fnv1a(fnv1a(FNV_OFFSET_BASIS, &seed.to_le_bytes()), bytes))
+}
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// FNV-1a of `bytes`, going on from `hash`
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(hash, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+fn fold_to_16_bits(hash: u64) -> u16 {
+    (hash ^ hash >> 16 ^ hash >> 32 ^ hash >> 48) as u16
+}
+
+/// Hash of the MAC address of the first non-loopback interface, see `hash_to_machine_id`.
+#[cfg(feature = "std")]
+fn lower_16_bit_mac_hash_of(interfaces: &[NetworkInterface]) -> Option<u16> {
+    let mac = interfaces
+        .iter()
+        .filter(|interface| !interface.is_loopback())
+        .find_map(|interface| interface.mac.filter(|mac| !mac.is_zero()))?;
+    Some(hash_to_machine_id(&mac.octets()))
+}
+
+/// The default machine id: the lower 16 bits of the private IPv4 address, or of the private IPv6 address
+/// with `ipv6_fallback`, and failing that the hash of the MAC address.
+#[cfg(feature = "std")]
+fn default_machine_id_of(interfaces: &[NetworkInterface], ipv6_fallback: bool) -> Result<u16, Error> {
+    let machine_id = if ipv6_fallback {
+        lower_16_bit_private_ip_or_ipv6_of(interfaces)
+    } else {
+        lower_16_bit_private_ip_of(interfaces)
+    };
+    match machine_id {
+        Err(err @ (Error::NoPrivateIPv4Address | Error::NoPrivateIPAddress)) => {
+            lower_16_bit_mac_hash_of(interfaces).ok_or(err)
+        }
+        result => result,
+    }
+}
+
+/// the machine id set by `Settings::set_machine_id_value`
+#[cfg(feature = "std")]
+struct FixedMachineID(u16);
+
+#[cfg(feature = "std")]
+impl MachineID for FixedMachineID {
+    fn machine_id(&mut self) -> Result<u16, Box<dyn core::error::Error + Send + Sync + 'static>> {
+        Ok(self.0)
+    }
+}
+
+/// the callback set by `Settings::set_on_exhaustion`
+#[cfg(feature = "std")]
+struct ExhaustionHook(Box<dyn FnMut() -> Option<u16> + Send>);
+
+#[cfg(feature = "std")]
+impl Debug for ExhaustionHook {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.write_str("ExhaustionHook")
+    }
+}
+
+/// the callback set by `Settings::set_on_overflow_approaching`, with the elapsed time it fires at
+#[cfg(feature = "std")]
+struct OverflowHook {
+    threshold: i64,
+    limit: i64,
+    fired: bool,
+    callback: Box<dyn FnMut(i64, i64) + Send>,
+}
+
+#[cfg(feature = "std")]
+impl Debug for OverflowHook {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("OverflowHook")
+            .field("threshold", &self.threshold)
+            .field("limit", &self.limit)
+            .field("fired", &self.fired)
+            .finish()
+    }
+}
+
+#[derive(Debug, Default)]
+#[cfg(feature = "std")]
+struct Inner {
+    /// the start time of the generator, shared by its clones as it moves on a reset or a time overflow
+    start_time: i64,
+    elapsed_time: i64,
+    sequence: u16,
+    generated: u64,
+    /// the number of sequence wraps since the generator last started a window at the current time
+    consecutive_sleeps: u32,
+    /// how far the last block of `SonyFlake::reserve_block` moved the state ahead of the clock,
+    /// until the clock reaches the end of the block at `borrowed_until`
+    borrowed: i64,
+    borrowed_until: i64,
+    /// the start of the current UTC day, with a daily rolling epoch
+    day_start: i64,
+    /// the machine id handed out by the exhaustion hook for the rest of the current window
+    spare_machine_id: Option<u16>,
+    /// a spare machine id taken from the exhaustion hook ahead of the wrap of the sequence, see `must_wait`
+    next_spare_machine_id: Option<u16>,
+    /// the sequence of the first window, set by `Settings::set_sequence_start`
+    first_sequence: Option<u16>,
+    clock_sampler: Option<ClockSampler>,
+    /// shared, so a thread can wait for the next window on it after releasing the lock
+    clock: Arc<UnitClock>,
+    on_exhaustion: Option<ExhaustionHook>,
+    on_overflow_approaching: Option<OverflowHook>,
+    metrics: Option<Box<dyn Metrics>>,
+    wait_strategy: WaitStrategy,
+    /// the claim on the machine id and start time, released with the last clone of the generator
+    #[cfg(feature = "duplicate-guard")]
+    registration: Option<guard::Registration>,
+}
+
+#[cfg(feature = "std")]
+impl Inner {
+    fn new<M, C>(st: &mut Settings<M, C>, time_unit: i64) -> Self {
+        let (elapsed_time, sequence) = match st.first_id_strategy {
+            // in sequence-only mode the state holds the next counter value to hand out
+            _ if st.sequence_only => (0, st.sequence_start.unwrap_or(0)),
+            // the first id starts a new window at `first_sequence`
+            _ if st.sequence_start.is_some() => (-1, 0),
+            FirstIdStrategy::Legacy => (0, 1 << (st.bit_layout.sequence - 1)),
+            // behind any current time, so the first id always starts a new window at sequence 0
+            FirstIdStrategy::Sequential => (-1, 0),
+        };
+
+        Self {
+            start_time: 0,
+            elapsed_time,
+            sequence,
+            generated: 0,
+            consecutive_sleeps: 0,
+            borrowed: 0,
+            borrowed_until: 0,
+            day_start: 0,
+            spare_machine_id: None,
+            next_spare_machine_id: None,
+            first_sequence: if st.sequence_only { None } else { st.sequence_start },
+            clock_sampler: st.clock_sampler.take(),
+            clock: Arc::new(UnitClock::new(st.nano_clock.take(), time_unit)),
+            on_exhaustion: st.on_exhaustion.take(),
+            on_overflow_approaching: st.on_overflow_approaching.take().map(|callback| {
+                let limit = 1i64 << st.bit_layout.time;
+                OverflowHook {
+                    threshold: (limit as f64 * st.overflow_threshold) as i64,
+                    limit,
+                    fired: false,
+                    callback,
+                }
+            }),
+            metrics: st.metrics.take(),
+            wait_strategy: st.wait_strategy,
+            #[cfg(feature = "duplicate-guard")]
+            registration: None,
+        }
+    }
+
+    /// Calls the overflow hook the first time the elapsed time crosses its threshold.
+    fn check_overflow_approaching(&mut self) {
+        if let Some(hook) = &mut self.on_overflow_approaching {
+            if !hook.fired && self.elapsed_time >= hook.threshold {
+                hook.fired = true;
+                (hook.callback)(self.elapsed_time, hook.limit);
+            }
+        }
+    }
+
+    /// Reports an id generated after waiting `wait` for its window to the metrics, if any.
+    fn record_id(&self, wait: Duration) {
+        if let Some(metrics) = &self.metrics {
+            if !wait.is_zero() {
+                metrics.on_sleep(wait);
+            }
+            metrics.on_id_generated();
+        }
+    }
+
+    /// Reports that the ids ran out to the metrics, if any.
+    fn record_overflow(&self) {
+        if let Some(metrics) = &self.metrics {
+            metrics.on_overflow();
+        }
+    }
+
+    /// Waits `wait`, as returned by `advance`, with the wait strategy of the generator.
+    fn wait(&self, wait: Duration) {
+        if !wait.is_zero() {
+            self.clock.wait(self.wait_strategy, wait);
+        }
+    }
+
+    fn future_drift(&self, start_time: i64) -> Duration {
+        let ahead = self.elapsed_time - self.clock.elapsed_time(start_time);
+        if ahead > 0 {
+            Duration::from_nanos((ahead * self.clock.time_unit()) as u64)
+        } else {
+            Duration::from_secs(0)
+        }
+    }
+
+    /// Advances the state to the next id at the `current` elapsed time. Returns how long the caller must sleep
+    /// before handing the id out, which is zero unless the sequence wrapped into a future window.
+    fn advance(&mut self, current: i64, sequence_bits: i64) -> Duration {
+        let mask_sequence = sequence_mask(sequence_bits);
+
+        if let Some(clock_sampler) = &mut self.clock_sampler {
+            clock_sampler.record(current);
+        }
+
+        if self.elapsed_time < current {
+            self.elapsed_time = current;
+            self.sequence = self.first_sequence.take().unwrap_or(0);
+            self.consecutive_sleeps = 0;
+            self.spare_machine_id = None;
+        } else {
+            // self.elapsed_time >= current
+            self.sequence = self.sequence.wrapping_add(1) & mask_sequence;
+            if self.sequence == 0 {
+                let next_spare = self.next_spare_machine_id.take();
+                if let Some(spare) = next_spare.or_else(|| self.on_exhaustion.as_mut().and_then(|hook| (hook.0)())) {
+                    self.spare_machine_id = Some(spare);
+                    return Duration::ZERO;
+                }
+                self.spare_machine_id = None;
+                self.elapsed_time += 1;
+                self.consecutive_sleeps += 1;
+                let overtime = self.elapsed_time - current;
+                return self.clock.sleep_time(overtime);
+            }
+        }
+        Duration::ZERO
+    }
+
+    /// Returns the current elapsed time since `start_time`, or since the start of the UTC day
+    /// with a daily rolling epoch, restarting the state when a new day begins.
+    fn current(&mut self, start_time: i64, daily_rolling: bool) -> i64 {
+        if !daily_rolling {
+            return self.clock.elapsed_time(start_time);
+        }
+
+        let now = self.clock.elapsed_time(0);
+        let day_start = now - now.rem_euclid(self.clock.units_per_day());
+        if self.day_start != day_start {
+            self.day_start = day_start;
+            // behind any time of the day, so the day starts a new window at sequence 0
+            self.elapsed_time = -1;
+            self.sequence = 0;
+        }
+        now - day_start
+    }
+
+    fn window_fill(&mut self, start_time: i64, daily_rolling: bool, sequence_bits: i64) -> f64 {
+        if self.elapsed_time == self.current(start_time, daily_rolling) {
+            self.sequence as f64 / ((1 << sequence_bits) - 1) as f64
+        } else {
+            0.0
+        }
+    }
+
+    /// Whether `advance` would wrap the sequence and sleep.
+    fn would_sleep(&self, current: i64, sequence_bits: i64) -> bool {
+        self.elapsed_time >= current && self.sequence.wrapping_add(1) & sequence_mask(sequence_bits) == 0
+    }
+
+    /// Like `would_sleep`, but asks the exhaustion hook for a spare machine id first,
+    /// keeping it for the next `advance`.
+    fn must_wait(&mut self, current: i64, sequence_bits: i64) -> bool {
+        if !self.would_sleep(current, sequence_bits) {
+            return false;
+        }
+        if self.next_spare_machine_id.is_none() {
+            self.next_spare_machine_id = self.on_exhaustion.as_mut().and_then(|hook| (hook.0)());
+        }
+        self.next_spare_machine_id.is_none()
+    }
+
+    /// The elapsed time and sequence as a single counter.
+    fn count(&self, sequence_bits: i64) -> u64 {
+        ((self.elapsed_time as u64) << sequence_bits) | self.sequence as u64
+    }
+
+    fn set_count(&mut self, count: u64, sequence_bits: i64) {
+        self.elapsed_time = (count >> sequence_bits) as i64;
+        self.sequence = (count & ((1 << sequence_bits) - 1)) as u16;
+    }
+
+    /// Returns the current counter value as `(elapsed_time, sequence)` and advances the counter,
+    /// or `None` if the `time_bits` time bits and the sequence bits are exhausted.
+    fn next_count(&mut self, sequence_bits: i64, time_bits: i64) -> Option<(i64, u16)> {
+        if self.elapsed_time >= 1 << time_bits {
+            return None;
+        }
+
+        let count = (self.elapsed_time, self.sequence);
+        self.sequence = self.sequence.wrapping_add(1) & sequence_mask(sequence_bits);
+        if self.sequence == 0 {
+            self.elapsed_time += 1;
+        }
+        Some(count)
+    }
+}
+
+/// `IdBlock` is a block of contiguous ids reserved by [`SonyFlake::reserve_block`].
+/// It yields the ids in increasing order, encoded like the ids of `next_id`, so with bit reversal
+/// enabled they are reversed and no longer increasing.
+///
+/// [`SonyFlake::reserve_block`]: struct.SonyFlake.html#method.reserve_block
+#[derive(Debug, Clone)]
+#[cfg(feature = "std")]
+pub struct IdBlock {
+    next: u64,
+    end: u64,
+    machine_id: u16,
+    layout: BitLayout,
+    tag_bits: u8,
+    borrowed_bits: u8,
+    obfuscation_key: Option<u64>,
+    bit_reversal: bool,
+}
+
+#[cfg(feature = "std")]
+impl Iterator for IdBlock {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.end {
+            return None;
+        }
+        let sequence_bits = sequence_bits(self.layout, self.tag_bits, self.borrowed_bits);
+        let count = self.next;
+        self.next += 1;
+        let (sequence, machine_id) = split_borrowed(
+            self.layout,
+            (count & ((1 << sequence_bits) - 1)) as u16,
+            self.machine_id,
+            self.borrowed_bits,
+        );
+        let id = self.layout.compose((count >> sequence_bits) as i64, sequence, machine_id);
+        let id = match self.obfuscation_key {
+            Some(key) => obfuscate_machine_id(self.layout, id, key),
+            None => id,
+        };
+        Some(if self.bit_reversal { bit_reverse_id(id) } else { id })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = (self.end - self.next) as usize;
+        (len, Some(len))
+    }
+}
+
+#[cfg(feature = "std")]
+impl ExactSizeIterator for IdBlock {}
+
+/// FNV-1a hash of an ID layout: the start time in nanoseconds, the bit lengths of the fields and the time unit.
+#[cfg(feature = "std")]
+fn config_fingerprint(start_time_nanos: i64, time_bits: i64, sequence_bits: i64, machine_id_bits: i64, time_unit: i64) -> u64 {
+    [start_time_nanos, time_bits, sequence_bits, machine_id_bits, time_unit]
+        .iter()
+        .fold(FNV_OFFSET_BASIS, |hash, value| fnv1a(hash, &value.to_le_bytes()))
+}
+
+/// The number of bits a generator counts ids within a time window with: the sequence
+/// without its tag bits, extended into the borrowed machine id bits.
+#[cfg(feature = "std")]
+fn sequence_bits(layout: BitLayout, tag_bits: u8, borrowed_bits: u8) -> i64 {
+    layout.sequence - tag_bits as i64 + borrowed_bits as i64
+}
+
+/// The mask of a sequence of `sequence_bits` bits, up to 16.
+fn sequence_mask(sequence_bits: i64) -> u16 {
+    ((1u32 << sequence_bits) - 1) as u16
+}
+
+/// Checks that the machine id fits the machine id bits of the layout left after borrowing `borrowed_bits`,
+/// and that the sequence extended into them still fits a `u16`.
+fn check_borrowed_bits(layout: BitLayout, borrowed_bits: u8, machine_id: u16) -> Result<(), Error> {
+    if borrowed_bits as i64 > layout.machine_id.min(16 - layout.sequence) {
+        return Err(Error::InvalidBorrowedBits(borrowed_bits));
+    }
+    if machine_id as u64 >> (layout.machine_id - borrowed_bits as i64) != 0 {
+        return Err(Error::InvalidMachineID(machine_id));
+    }
+    Ok(())
+}
+
+/// Releases the lock on the state, then waits `wait`, as returned by `Inner::advance`,
+/// so other threads are not blocked while this one waits for the next window.
+#[cfg(feature = "std")]
+fn wait_unlocked(inner: MutexGuard<'_, Inner>, wait: Duration) {
+    if !wait.is_zero() {
+        let (clock, strategy) = (inner.clock.clone(), inner.wait_strategy);
+        drop(inner);
+        clock.wait(strategy, wait);
+    }
+}
+
+/// Splits a sequence extended into the top `borrowed_bits` bits of the machine id
+/// back into the sequence and machine id fields of an ID.
+#[cfg(feature = "std")]
+fn split_borrowed(layout: BitLayout, sequence: u16, machine_id: u16, borrowed_bits: u8) -> (u16, u16) {
+    let borrowed = sequence as u32 & ((1 << borrowed_bits) - 1);
+    (
+        sequence >> borrowed_bits,
+        machine_id | (borrowed << (layout.machine_id - borrowed_bits as i64)) as u16,
+    )
+}
+
+fn to_id(elapsed_time: i64, seq: u16, machine_id: u16) -> u64 {
+    (elapsed_time as u64) << (BIT_LEN_SEQUENCE + BIT_LEN_MACHINE_ID)
+        | (seq as u64) << BIT_LEN_MACHINE_ID
+        | (machine_id as u64)
+}
+
+/// Fails with `Error::StartTimeOutOfRange` if `time` does not fit in nanoseconds since the unix epoch.
+fn to_sonyflake_time(time: DateTime<Utc>, time_unit: i64) -> Result<i64, Error> {
+    time.timestamp_nanos_opt()
+        .map(|nanos| nanos / time_unit)
+        .ok_or(Error::StartTimeOutOfRange(time))
+}
+
+#[cfg(feature = "std")]
+fn from_sonyflake_time(time: i64, time_unit: i64) -> DateTime<Utc> {
+    // the time range of the layout fits in nanoseconds, but not always on top of the start time
+    Utc.timestamp_nanos(time.saturating_mul(time_unit))
+}
+
+/// nanoseconds since the unix epoch, as read on every `next_id`
+#[cfg(all(feature = "std", not(feature = "coarse-clock")))]
+fn now_nanos() -> i64 {
+    clock::saturating_timestamp_nanos(Utc::now())
+}
+
+/// nanoseconds since the unix epoch, as read on every `next_id`
+#[cfg(feature = "coarse-clock")]
+fn now_nanos() -> i64 {
+    coarsetime::Clock::now_since_epoch().as_nanos() as i64
+}
+
+/// `IDParts` contains the bit parts for an ID.
+///
+/// The parts can be recomputed from the ID, so binary formats such as bincode only carry the ID, and the
+/// parts are restored with [`decompose`] on deserialization, that is with the default layout. Human-readable
+/// formats such as JSON carry all the parts.
+///
+/// [`decompose`]: fn.decompose.html
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct IDParts {
+    id: u64,
+    msb: u64,
+    time: u64,
+    sequence: u64,
+    machine_id: u64,
+}
+
+/// the full form of `IDParts` in human-readable formats
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "IDParts")]
+struct FullIDParts {
+    id: u64,
+    msb: u64,
+    time: u64,
+    sequence: u64,
+    machine_id: u64,
+}
+
+impl serde::Serialize for IDParts {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if !serializer.is_human_readable() {
+            return serializer.serialize_u64(self.id);
+        }
+        FullIDParts {
+            id: self.id,
+            msb: self.msb,
+            time: self.time,
+            sequence: self.sequence,
+            machine_id: self.machine_id,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for IDParts {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if !deserializer.is_human_readable() {
+            return u64::deserialize(deserializer).map(decompose);
+        }
+        let parts = FullIDParts::deserialize(deserializer)?;
+        Ok(IDParts {
+            id: parts.id,
+            msb: parts.msb,
+            time: parts.time,
+            sequence: parts.sequence,
+            machine_id: parts.machine_id,
+        })
+    }
+}
+
+impl IDParts {
+    /// `decompose` returns a set of SonyFlake ID parts.
+    pub fn decompose(id: u64) -> Self {
+        decompose(id)
+    }
+
+    /// `get_id` returns the original ID
+    pub fn get_id(&self) -> u64 {
+        self.id
+    }
+
+    /// `get_msb` returns msb for the id
+    pub fn get_msb(&self) -> u64 {
+        self.msb
+    }
+
+    /// `get_time` returns a timestamp
+    pub fn get_time(&self) -> u64 {
+        self.time
+    }
+
+    /// `get_sequence` returns sequence
+    pub fn get_sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// `get_tag` returns the tag of an ID generated with `tag_bits` reserved tag bits,
+    /// see `Settings::reserve_tag_bits`. It returns 0 if `tag_bits` is not shorter than the sequence.
+    pub fn get_tag(&self, tag_bits: u8) -> u8 {
+        self.get_tag_with_layout(tag_bits, &BitLayout::SONYFLAKE)
+    }
+
+    /// `get_tag_with_layout` returns the tag of an ID of `layout` generated with `tag_bits` reserved tag bits,
+    /// see `Settings::reserve_tag_bits`. It returns 0 if `tag_bits` is not shorter than the sequence of `layout`.
+    pub fn get_tag_with_layout(&self, tag_bits: u8, layout: &BitLayout) -> u8 {
+        if tag_bits as i64 >= layout.sequence {
+            return 0;
+        }
+        (self.sequence >> (layout.sequence - tag_bits as i64)) as u8
+    }
+
+    /// `get_machine_id` returns the machine id
+    pub fn get_machine_id(&self) -> u64 {
+        self.machine_id
+    }
+
+    /// `machine_id_hex` returns the machine id as lowercase hex zero-padded to 4 digits, e.g. `0a01`,
+    /// for consistent formatting in logs
+    pub fn machine_id_hex(&self) -> String {
+        format!("{:04x}", self.machine_id)
+    }
+
+    /// `machine_id_octets` returns the high and low bytes of the machine id, which are the last two octets
+    /// of the private IPv4 address the default machine id is taken from, e.g. `(12, 34)` for `10.0.12.34`
+    pub fn machine_id_octets(&self) -> (u8, u8) {
+        ((self.machine_id >> 8) as u8, self.machine_id as u8)
+    }
+
+    /// `to_datetime` returns the wall-clock time the ID was generated at, given the `start_time`
+    /// of the generator which produced it, at 10 msec precision.
+    /// For a generator with a custom time unit, use `SonyFlake::timestamp_of` instead.
+    pub fn to_datetime(&self, start_time: DateTime<Utc>) -> DateTime<Utc> {
+        start_time + units_to_duration(self.time as i64)
+    }
+
+    /// `cmp_by_time` compares the time and then the sequence of two IDs, ignoring the machine id,
+    /// e.g. to merge the ID streams of several machines into a single time-ordered one.
+    /// The natural ordering of the IDs also compares the machine id last.
+    pub fn cmp_by_time(&self, other: &IDParts) -> Ordering {
+        (self.time, self.sequence).cmp(&(other.time, other.sequence))
+    }
+}
+
+/// `decompose` returns a set of SonyFlake ID parts.
+pub fn decompose(id: u64) -> IDParts {
+    let mask_seq = ((1u64 << BIT_LEN_SEQUENCE) - 1) << BIT_LEN_MACHINE_ID;
+    let mask_machine_id = (1u64 << BIT_LEN_MACHINE_ID) - 1;
+
+    let msb = id >> 63;
+    let time = id >> (BIT_LEN_SEQUENCE + BIT_LEN_MACHINE_ID);
+
+    let seq = (id & mask_seq) >> BIT_LEN_MACHINE_ID;
+    let machine_id = id & mask_machine_id;
+    IDParts {
+        id,
+        msb,
+        time,
+        sequence: seq,
+        machine_id,
+    }
+}
+
+/// `decompose_borrowed` returns a set of SonyFlake ID parts for an ID generated with
+/// `borrowed_bits` machine id bits borrowed for the sequence,
+/// see [`Settings::borrow_machine_bits_for_sequence`].
+///
+/// [`Settings::borrow_machine_bits_for_sequence`]: struct.Settings.html#method.borrow_machine_bits_for_sequence
+pub fn decompose_borrowed(id: u64, borrowed_bits: u8) -> IDParts {
+    let parts = decompose(id);
+    let machine_id_bits = BIT_LEN_MACHINE_ID - borrowed_bits as i64;
+    IDParts {
+        sequence: parts.sequence << borrowed_bits | parts.machine_id >> machine_id_bits,
+        machine_id: parts.machine_id & ((1 << machine_id_bits) - 1),
+        ..parts
+    }
+}
+
+/// `decompose_with_layout` returns a set of ID parts for an ID generated with a custom bit layout,
+/// see [`Settings::set_bit_layout`]; it is the same as [`BitLayout::decompose`].
+///
+/// [`Settings::set_bit_layout`]: struct.Settings.html#method.set_bit_layout
+/// [`BitLayout::decompose`]: struct.BitLayout.html#method.decompose
+pub fn decompose_with_layout(id: u64, layout: &BitLayout) -> IDParts {
+    layout.decompose(id)
+}
+
+/// `decompose_obfuscated` returns a set of SonyFlake ID parts for an ID generated with
+/// [`Settings::set_machine_id_obfuscation`] and `key`, with the plain machine id.
+/// It assumes the default layout; for ids of a custom layout, use [`decompose_obfuscated_with_layout`].
+///
+/// [`Settings::set_machine_id_obfuscation`]: struct.Settings.html#method.set_machine_id_obfuscation
+/// [`decompose_obfuscated_with_layout`]: fn.decompose_obfuscated_with_layout.html
+pub fn decompose_obfuscated(id: u64, key: u64) -> IDParts {
+    decompose_obfuscated_with_layout(id, key, &BitLayout::SONYFLAKE)
+}
+
+/// `decompose_obfuscated_with_layout` returns a set of ID parts for an ID of `layout` generated with
+/// [`Settings::set_machine_id_obfuscation`] and `key`, with the plain machine id.
+///
+/// [`Settings::set_machine_id_obfuscation`]: struct.Settings.html#method.set_machine_id_obfuscation
+pub fn decompose_obfuscated_with_layout(id: u64, key: u64, layout: &BitLayout) -> IDParts {
+    layout.decompose(obfuscate_machine_id(*layout, id, key))
+}
+
+/// XORs the machine id bits of `id` with a mask derived from `key` and the time and sequence of the ID.
+/// It is its own inverse, as it leaves the time and sequence untouched.
+fn obfuscate_machine_id(layout: BitLayout, id: u64, key: u64) -> u64 {
+    let parts = layout.decompose(id);
+    // the splitmix64 finalizer of the key, time and sequence
+    let mut z = key
+        ^ parts.time.wrapping_mul(0x9e37_79b9_7f4a_7c15)
+        ^ parts.sequence.wrapping_mul(0xc2b2_ae3d_27d4_eb4f);
+    z = (z ^ z >> 30).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ z >> 27).wrapping_mul(0x94d0_49bb_1331_11eb);
+    let mask = (z ^ z >> 31) & ((1 << layout.machine_id) - 1);
+    layout.compose(parts.time as i64, parts.sequence as u16, (parts.machine_id ^ mask) as u16)
+}
+
+/// `random_valid_id` returns a uniformly random well-formed SonyFlake ID drawn from `rng`:
+/// the most significant bit is zero and every field is within range.
+///
+/// This does not generate unique IDs, it produces arbitrary IDs for test harnesses.
+/// Use a seeded RNG to make the IDs reproducible.
+#[cfg(feature = "rand")]
+pub fn random_valid_id<R: rand::Rng + ?Sized>(rng: &mut R) -> u64 {
+    rng.gen::<u64>() >> (64 - BIT_LEN_TIME - BIT_LEN_SEQUENCE - BIT_LEN_MACHINE_ID)
+}
+
+/// `compose` packs the elapsed time, sequence and machine id into a SonyFlake ID; it is the inverse of [`decompose`].
+/// The fields are expected to fit their bit lengths (39, 8 and 16 bits).
+///
+/// [`decompose`]: fn.decompose.html
+pub fn compose(elapsed_time: i64, sequence: u16, machine_id: u16) -> u64 {
+    to_id(elapsed_time, sequence, machine_id)
+}
+
+/// `timestamp_of` returns the wall-clock time an ID was generated at, given the `start_time`
+/// of the generator which produced it, see [`IDParts::to_datetime`].
+///
+/// [`IDParts::to_datetime`]: struct.IDParts.html#method.to_datetime
+pub fn timestamp_of(id: u64, start_time: DateTime<Utc>) -> DateTime<Utc> {
+    decompose(id).to_datetime(start_time)
+}
+
+/// `units_to_duration` converts a count of SonyFlake time units (10 msec each), e.g. the time part of an ID,
+/// to a `chrono::Duration`; it is the inverse of [`duration_to_units`].
+///
+/// [`duration_to_units`]: fn.duration_to_units.html
+pub fn units_to_duration(units: i64) -> chrono::Duration {
+    chrono::Duration::nanoseconds(units * FLAKE_TIME_UNIT)
+}
+
+/// `max_lifetime` returns the span of time the ids of the default layout and time unit cover from the start time,
+/// 2^39 units of 10 msec, about 174 years. See `SonyFlake::remaining_lifetime` for the time left to a generator.
+pub const fn max_lifetime() -> Duration {
+    Duration::from_nanos((1u64 << BIT_LEN_TIME) * FLAKE_TIME_UNIT as u64)
+}
+
+/// `duration_to_units` converts a `chrono::Duration` to a count of whole SonyFlake time units (10 msec each),
+/// truncating toward zero; it is the inverse of [`units_to_duration`].
+///
+/// [`units_to_duration`]: fn.units_to_duration.html
+pub fn duration_to_units(d: chrono::Duration) -> i64 {
+    match d.num_nanoseconds() {
+        Some(nanos) => nanos / FLAKE_TIME_UNIT,
+        // too long to count in nanoseconds, a 10 msec unit is a whole number of milliseconds
+        None => d.num_milliseconds() / (FLAKE_TIME_UNIT / 1_000_000),
+    }
+}
+
+/// `rolling_timestamp` returns the time an ID generated with a daily rolling epoch was generated at,
+/// given the UTC `day` it was generated on, see [`Settings::daily_rolling_epoch`].
+///
+/// [`Settings::daily_rolling_epoch`]: struct.Settings.html#method.daily_rolling_epoch
+pub fn rolling_timestamp(id: u64, day: NaiveDate) -> DateTime<Utc> {
+    let day_start = Utc.from_utc_datetime(&day.and_hms_opt(0, 0, 0).unwrap());
+    day_start + units_to_duration(decompose(id).time as i64)
+}
+
+/// `synthetic_id` builds the ID a record created at `created_at` would have had, for backfilling
+/// existing records ordered by creation time with IDs from a generator started at `start_time`.
+///
+/// The caller supplies a distinct `sequence` for each record sharing a 10 msec window.
+/// Returns `Error::CreatedBeforeStartTime` if `created_at` is before `start_time`,
+/// `Error::TimeOverflow` if it is past the lifetime of the IDs, and `Error::SequenceOutOfRange`
+/// if `sequence` does not fit in 8 bits.
+pub fn synthetic_id(
+    created_at: DateTime<Utc>,
+    start_time: DateTime<Utc>,
+    sequence: u16,
+    machine_id: u16,
+) -> Result<u64, Error> {
+    if created_at < start_time {
+        return Err(Error::CreatedBeforeStartTime(created_at));
+    }
+    if sequence >= 1 << BIT_LEN_SEQUENCE {
+        return Err(Error::SequenceOutOfRange(sequence));
+    }
+
+    let elapsed_time = duration_to_units(created_at - start_time);
+    if elapsed_time >= 1 << BIT_LEN_TIME {
+        return Err(Error::TimeOverflow);
+    }
+
+    Ok(to_id(elapsed_time, sequence, machine_id))
+}
+
+/// `ids_between` returns how many IDs a single generator would produce between `a` and `b` at a steady
+/// `rate_per_sec`, clamped to the most a generator can produce: 2^8 IDs per 10 msec, i.e. 25600 per second.
+/// Returns 0 if `b` is not after `a`.
+pub fn ids_between(a: DateTime<Utc>, b: DateTime<Utc>, rate_per_sec: u64) -> u64 {
+    let max_per_sec = (1u64 << BIT_LEN_SEQUENCE) * (1_000_000_000 / FLAKE_TIME_UNIT) as u64;
+    let span = match (b - a).to_std() {
+        Ok(span) => span,
+        Err(_) => return 0,
+    };
+    (span.as_nanos() * rate_per_sec.min(max_per_sec) as u128 / 1_000_000_000) as u64
+}
+
+/// `quantize_id_time` rounds the time part of an ID down to a multiple of `bucket_units` units of 10 msec
+/// and zeroes its sequence, so all the IDs a machine generated within the same time bucket map to
+/// the same value, e.g. as a group key. A `bucket_units` of 0 is treated as 1.
+pub fn quantize_id_time(id: u64, bucket_units: u64) -> u64 {
+    let parts = decompose(id);
+    let time = parts.time - parts.time % bucket_units.max(1);
+    to_id(time as i64, 0, parts.machine_id as u16)
+}
+
+/// `bit_reverse_id` reverses the order of the 63 low bits of an ID, keeping the msb zero.
+/// It is its own inverse for IDs: `bit_reverse_id(bit_reverse_id(id)) == id`.
+pub fn bit_reverse_id(id: u64) -> u64 {
+    id.reverse_bits() >> 1
+}
+
+/// `next_cursor` returns the smallest valid ID greater than `last_seen`, the inclusive lower bound
+/// for the next page of a cursor-based scan over data keyed and ordered by ID, or `None` if
+/// `last_seen` is the largest valid ID.
+///
+/// IDs from different machines interleave in the key space, so a page may hold IDs from any machine,
+/// and a page boundary says nothing about how many IDs were generated in between. Scanning with
+/// `id >= next_cursor(last_seen)` is the same as `id > last_seen`, for stores which only offer
+/// inclusive range starts.
+pub fn next_cursor(last_seen: u64) -> Option<u64> {
+    let next = last_seen.checked_add(1)?;
+    if next >> (BIT_LEN_TIME + BIT_LEN_SEQUENCE + BIT_LEN_MACHINE_ID) != 0 {
+        return None;
+    }
+    Some(next)
+}
+
+/// `ids_for_window` yields all the 2^8 IDs a machine can generate within the 10 msec window `time`,
+/// in sequence order, e.g. to exhaustively test code handling IDs.
+/// Returns `Error::TimeOverflow` if `time` does not fit in 39 bits.
+pub fn ids_for_window(time: u64, machine_id: u16) -> Result<impl Iterator<Item = u64>, Error> {
+    if time >= 1 << BIT_LEN_TIME {
+        return Err(Error::TimeOverflow);
+    }
+    Ok((0..1u16 << BIT_LEN_SEQUENCE).map(move |sequence| to_id(time as i64, sequence, machine_id)))
+}
+
+/// `format_ids_table` decomposes the IDs generated from `start_time` into an aligned text table
+/// with the columns id, time (RFC 3339), sequence and machine id, one row per ID, for log inspection tools.
+/// With no IDs, only the header row is returned.
+pub fn format_ids_table(ids: &[u64], start_time: DateTime<Utc>) -> String {
+    let header = ["id", "time", "sequence", "machine_id"].map(String::from);
+    let rows = core::iter::once(header)
+        .chain(ids.iter().map(|id| {
+            let parts = decompose(*id);
+            [
+                parts.id.to_string(),
+                parts.to_datetime(start_time).to_rfc3339_opts(SecondsFormat::Millis, true),
+                parts.sequence.to_string(),
+                parts.machine_id.to_string(),
+            ]
+        }))
+        .collect::<Vec<_>>();
+
+    let mut widths = [0; 4];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let mut table = String::new();
+    for row in &rows {
+        let line = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = *width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        table.push_str(line.trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+/// `assert_single_machine` checks that all the ids were generated by the same machine and returns its machine id.
+/// If the ids carry more than one machine id, or there are no ids at all, it returns
+/// `Error::MultipleMachineIds` with the distinct machine ids found.
+pub fn assert_single_machine(ids: &[u64]) -> Result<u16, Error> {
+    let machine_ids = ids
+        .iter()
+        .map(|id| decompose(*id).machine_id as u16)
+        .collect::<BTreeSet<_>>();
+
+    if machine_ids.len() == 1 {
+        Ok(*machine_ids.iter().next().unwrap())
+    } else {
+        Err(Error::MultipleMachineIds(machine_ids))
+    }
+}
+
+#[cfg(feature = "std")]
+fn default_start_time() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2021, 8, 6, 0, 0, 0).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Error as FlakeError, assert_single_machine, bit_reverse_id, compose, duration_to_units, format_ids_table, ids_for_window, units_to_duration, quantize_id_time, ids_between, next_cursor, rolling_timestamp, synthetic_id, timestamp_of, decompose_borrowed, max_lifetime, default_machine_id, lower_16_bit_private_ip_of, lower_16_bit_private_ip_or_ipv6_of, lower_16_bit_mac_hash_of, default_machine_id_of, decompose_obfuscated, hash_to_machine_id, hash_to_machine_id_with_seed, Backpressure, BitLayout, FirstIdStrategy, WaitStrategy, to_sonyflake_time, IDParts, FLAKE_TIME_UNIT, Settings, SonyFlake, InfallibleSonyFlake, BIT_LEN_SEQUENCE, MachineID, MachineIDChecker, BIT_LEN_TIME};
+    use crate::clock::{FixedClock, ManualClock};
+    use chrono::{TimeZone, Utc};
+    use std::time::Duration;
+    use std::error::Error;
+    use std::thread::JoinHandle;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_sonyflake_once() {
+        let now = Utc::now();
+        let f = Settings::new().set_start_time(now).into_sonyflake().unwrap();
+
+        let sleep_time = 500u64;
+        std::thread::sleep(Duration::from_millis(sleep_time));
+        let id = f.next_id().unwrap();
+
+        let parts = IDParts::decompose(id);
+        assert_eq!(parts.get_msb(), 0);
+        assert_eq!(parts.get_sequence(), 0);
+        assert!(parts.get_time() < sleep_time || parts.get_time() > sleep_time + 1);
+        assert_eq!(parts.machine_id, default_machine_id(false).unwrap() as u64);
+    }
+
+    #[test]
+    fn test_infallible_sonyflake_once() {
+        let now = Utc::now();
+        let f = Settings::new().set_start_time(now).into_infallible_sonyflake().unwrap();
+
+        let sleep_time = 500u64;
+        std::thread::sleep(Duration::from_millis(sleep_time));
+        let id = f.next_id();
+
+        let parts = IDParts::decompose(id);
+        assert_eq!(parts.get_msb(), 0);
+        assert_eq!(parts.get_sequence(), 0);
+        assert!(parts.get_time() < sleep_time || parts.get_time() > sleep_time + 1);
+        assert_eq!(parts.machine_id, default_machine_id(false).unwrap() as u64);
+    }
+
+    #[test]
+    fn test_sonyflake_for_10_sec() {
+        let now = Utc::now();
+        let start_time = to_sonyflake_time(now, FLAKE_TIME_UNIT).unwrap();
+        let f = SonyFlake::new(Settings::new().set_start_time(now)).unwrap();
+
+        let mut num_id: u64 = 0;
+        let mut last_id: u64 = 0;
+        let mut max_seq: u64 = 0;
+
+        let machine_id = default_machine_id(false).unwrap() as u64;
+
+        let initial = to_sonyflake_time(Utc::now(), FLAKE_TIME_UNIT).unwrap();
+        let mut current = initial;
+
+        while current - initial < 1000 {
+            let id = f.next_id().unwrap();
+
+            let parts = IDParts::decompose(id);
+            num_id += 1;
+
+            assert!(id > last_id);
+            last_id = id;
+
+            current = to_sonyflake_time(Utc::now(), FLAKE_TIME_UNIT).unwrap();
+
+            assert_eq!(parts.get_msb(), 0);
+            let overtime = start_time + (parts.get_time() as i64) - current;
+            assert!(overtime <= 0);
+
+            if max_seq < parts.get_sequence() {
+                max_seq = parts.get_sequence();
+            }
+
+            assert_eq!(parts.get_machine_id(), machine_id);
+        }
+
+        assert_eq!(max_seq, (1 << BIT_LEN_SEQUENCE) - 1);
+        println!("number of id: {}", num_id);
+    }
+
+    #[test]
+    fn test_infallible_sonyflake_for_10_sec() {
+        let now = Utc::now();
+        let start_time = to_sonyflake_time(now, FLAKE_TIME_UNIT).unwrap();
+        let f = InfallibleSonyFlake::new(Settings::new().set_start_time(now)).unwrap();
+
+        let mut num_id: u64 = 0;
+        let mut last_id: u64 = 0;
+        let mut max_seq: u64 = 0;
+
+        let machine_id = default_machine_id(false).unwrap() as u64;
+
+        let initial = to_sonyflake_time(Utc::now(), FLAKE_TIME_UNIT).unwrap();
+        let mut current = initial;
+
+        while current - initial < 1000 {
+            let id = f.next_id();
+
+            let parts = IDParts::decompose(id);
+            num_id += 1;
+
+            assert!(id > last_id);
+            last_id = id;
+
+            current = to_sonyflake_time(Utc::now(), FLAKE_TIME_UNIT).unwrap();
+
+            assert_eq!(parts.get_msb(), 0);
+            let overtime = start_time + (parts.get_time() as i64) - current;
+            assert!(overtime <= 0);
+
+            if max_seq < parts.get_sequence() {
+                max_seq = parts.get_sequence();
+            }
+
+            assert_eq!(parts.get_machine_id(), machine_id);
+        }
+
+        assert_eq!(max_seq, (1 << BIT_LEN_SEQUENCE) - 1);
+        println!("number of id: {}", num_id);
+    }
+
+    struct CustomMachineID {
+        counter: u64,
+        id: u16,
+    }
+
+    impl MachineID for CustomMachineID {
+        fn machine_id(&mut self) -> Result<u16, Box<dyn Error + Send + Sync + 'static>> {
+            self.counter += 1;
+            if self.counter % 2 == 1 {
+                Ok(self.id)
+            } else {
+                Err(Box::new("NaN".parse::<u32>().unwrap_err()))
+            }
+        }
+    }
+
+    struct CustomMachineIDChecker;
+
+    impl MachineIDChecker for CustomMachineIDChecker {
+        fn check_machine_id(&self, id: u16) -> bool {
+            id % 2 == 1
+        }
+    }
+
+    #[test]
+    fn test_sonyflake_custom_machine_id_and_checker() {
+        let sf = Settings::new()
+            .set_machine_id(Box::new(CustomMachineID { counter: 0, id: 1 }))
+            .set_check_machine_id(Box::new(CustomMachineIDChecker {}))
+            .into_sonyflake().unwrap();
+        let id = sf.next_id().unwrap();
+        let parts = IDParts::decompose(id);
+        assert_eq!(parts.get_machine_id(), 1);
+
+        let err = Settings::new()
+            .set_machine_id(Box::new(CustomMachineID { counter: 0, id: 2 }))
+            .set_check_machine_id(Box::new(CustomMachineIDChecker {}))
+            .into_sonyflake().unwrap_err();
+
+        assert_eq!(format!("{}", err), FlakeError::InvalidMachineID(2).to_string());
+    }
+
+    #[test]
+    fn test_infallible_sonyflake_custom_machine_id_and_checker() {
+        let sf = Settings::new()
+            .set_machine_id(Box::new(CustomMachineID { counter: 0, id: 1 }))
+            .set_check_machine_id(Box::new(CustomMachineIDChecker {}))
+            .into_infallible_sonyflake().unwrap();
+        let id = sf.next_id();
+        let parts = IDParts::decompose(id);
+        assert_eq!(parts.get_machine_id(), 1);
+
+        let err = Settings::new()
+            .set_machine_id(Box::new(CustomMachineID { counter: 0, id: 2 }))
+            .set_check_machine_id(Box::new(CustomMachineIDChecker {}))
+            .into_infallible_sonyflake().unwrap_err();
+
+        assert_eq!(format!("{}", err), FlakeError::InvalidMachineID(2).to_string());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_fallible() {
+        let now = Utc::now();
+        let sf = Settings::new().set_start_time(now).into_sonyflake().unwrap();
+        sf.inner.lock().elapsed_time = 1 << BIT_LEN_TIME;
+        let _ = sf.next_id().unwrap();
+    }
+
+    #[test]
+    fn test_infallible() {
+        let now = Utc::now();
+        let sf = Settings::new().set_start_time(now).into_infallible_sonyflake().unwrap();
+        sf.inner.lock().elapsed_time = (1 << BIT_LEN_TIME) - 2;
+        let _ = sf.next_id();
+        let _ = sf.next_id();
+        let _ = sf.next_id();
+        let _ = sf.next_id();
+    }
+
+    #[test]
+    fn test_sonyflake_concurrency() {
+        let now = Utc::now();
+        let sf = Settings::new().set_start_time(now).into_sonyflake().unwrap();
+
+        let (tx, rx) = std::sync::mpsc::channel::<u64>();
+
+        let mut threads = Vec::<JoinHandle<()>>::with_capacity(1000);
+        for _ in 0..100 {
+            let thread_sf = sf.clone();
+            let thread_tx = tx.clone();
+            threads.push(std::thread::spawn(move || {
+                for _ in 0..1000 {
+                    thread_tx.send(thread_sf.next_id().unwrap()).unwrap();
+                }
+            }));
+        }
+
+        let mut ids = HashSet::new();
+        for _ in 0..100000 {
+            let id = rx.recv().unwrap();
+            assert!(!ids.contains(&id), "duplicate id: {}", id);
+            ids.insert(id);
+        }
+
+        for t in threads {
+            t.join().expect("thread panicked");
+        }
+    }
+
+    #[test]
+    fn test_clones_interleaved_at_sequence_wrap() {
+        use std::sync::atomic::{AtomicI64, Ordering};
+        use std::sync::Arc;
+
+        let start_time = Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap();
+        let start_nanos = start_time.timestamp_nanos_opt().unwrap();
+        let now = Arc::new(AtomicI64::new(start_nanos + 1000 * FLAKE_TIME_UNIT));
+        let a = Settings::new()
+            .set_start_time(start_time)
+            .set_machine_id_value(1)
+            .set_nano_clock(Box::new(ManualClock(now.clone())))
+            .into_sonyflake()
+            .unwrap();
+        let b = a.clone();
+
+        // put the shared state on the last id of the current window, so the next id wraps the sequence
+        let at_wrap = || {
+            let mut inner = a.inner.lock();
+            inner.elapsed_time = inner.clock.elapsed_time(inner.start_time);
+            inner.sequence = (1 << BIT_LEN_SEQUENCE) - 1;
+        };
+        at_wrap();
+
+        // the wrapping clone waits for the next window, which the clock reaches before the other clone's call
+        let (first, wait) = a.next_id_with_wait().unwrap();
+        assert_eq!(wait, Duration::from_millis(10));
+        now.fetch_add(FLAKE_TIME_UNIT, Ordering::SeqCst);
+        let (second, wait) = b.next_id_with_wait().unwrap();
+        assert_eq!(wait, Duration::ZERO);
+        let parts = [IDParts::decompose(first), IDParts::decompose(second)];
+        assert_eq!((parts[0].get_time(), parts[0].get_sequence()), (1001, 0));
+        assert_eq!((parts[1].get_time(), parts[1].get_sequence()), (1001, 1));
+
+        let mut ids = vec![first, second];
+        for _ in 0..300 {
+            ids.push(a.next_id().unwrap());
+            ids.push(b.next_id().unwrap());
+            now.fetch_add(FLAKE_TIME_UNIT / 2, Ordering::SeqCst);
+        }
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+
+        // the same boundary with the clones on two threads
+        at_wrap();
+        let barrier = std::sync::Arc::new(std::sync::Barrier::new(2));
+        let threads = vec![a, b]
+            .into_iter()
+            .map(|sf| {
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    (0..300).map(|_| sf.next_id().unwrap()).collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut distinct = ids.into_iter().collect::<HashSet<_>>();
+        for t in threads {
+            let thread_ids = t.join().expect("thread panicked");
+            assert!(thread_ids.windows(2).all(|w| w[0] < w[1]));
+            for id in thread_ids {
+                assert!(distinct.insert(id), "duplicate id: {}", id);
+            }
+        }
+        assert_eq!(distinct.len(), 1202);
+    }
+
+    #[test]
+    fn test_infallible_sonyflake_concurrency() {
+        let now = Utc::now();
+        let sf = Settings::new().set_start_time(now).into_infallible_sonyflake().unwrap();
+
+        let (tx, rx) = std::sync::mpsc::channel::<u64>();
+
+        let mut threads = Vec::<JoinHandle<()>>::with_capacity(1000);
+        for _ in 0..100 {
+            let thread_sf = sf.clone();
+            let thread_tx = tx.clone();
+            threads.push(std::thread::spawn(move || {
+                for _ in 0..1000 {
+                    thread_tx.send(thread_sf.next_id()).unwrap();
+                }
+            }));
+        }
+
+        let mut ids = HashSet::new();
+        for _ in 0..100000 {
+            let id = rx.recv().unwrap();
+            assert!(!ids.contains(&id), "duplicate id: {}", id);
+            ids.insert(id);
+        }
+
+        for t in threads {
+            t.join().expect("thread panicked");
+        }
+    }
+
+    #[test]
+    fn test_sequence_only_mode() {
+        let sf = Settings::new()
+            .set_machine_id_value(1)
+            .sequence_only_mode()
+            .into_sonyflake()
+            .unwrap();
+
+        let count = |id: u64| {
+            let parts = IDParts::decompose(id);
+            (parts.get_time() << BIT_LEN_SEQUENCE) | parts.get_sequence()
+        };
+
+        let mut last = sf.next_id().unwrap();
+        assert_eq!(count(last), 0);
+        // run across a sequence wrap to make sure the carry goes into the time bits
+        for _ in 0..1000 {
+            let id = sf.next_id().unwrap();
+            assert_eq!(count(id), count(last) + 1);
+            assert_eq!(IDParts::decompose(id).get_machine_id(), 1);
+            last = id;
+        }
+    }
+
+    #[test]
+    fn test_sequence_only_mode_exhausted() {
+        let sf = Settings::new()
+            .set_machine_id_value(1)
+            .sequence_only_mode()
+            .into_sonyflake()
+            .unwrap();
+        sf.inner.lock().elapsed_time = (1 << BIT_LEN_TIME) - 1;
+        sf.inner.lock().sequence = (1 << BIT_LEN_SEQUENCE) - 1;
+        let _ = sf.next_id().unwrap();
+        assert!(matches!(sf.next_id().unwrap_err(), FlakeError::CounterExhausted));
+
+        let sf = Settings::new()
+            .set_machine_id_value(1)
+            .sequence_only_mode()
+            .into_infallible_sonyflake()
+            .unwrap();
+        sf.inner.lock().elapsed_time = 1 << BIT_LEN_TIME;
+        let parts = IDParts::decompose(sf.next_id());
+        assert_eq!((parts.get_time(), parts.get_sequence()), (0, 0));
+    }
+
+    #[test]
+    fn test_next_id_fields() {
+        let now = Utc::now();
+        let new_sf = || {
+            Settings::new()
+                .set_start_time(now)
+                .set_machine_id_value(1)
+                .into_sonyflake()
+                .unwrap()
+        };
+
+        // put both generators ahead of the clock so they advance identically
+        let (a, b) = (new_sf(), new_sf());
+        for sf in [&a, &b] {
+            let mut inner = sf.inner.lock();
+            inner.elapsed_time = 50;
+            inner.sequence = 0;
+        }
+
+        let (elapsed_time, sequence, machine_id) = a.next_id_fields().unwrap();
+        assert_eq!((elapsed_time, sequence, machine_id), (50, 1, 1));
+        assert_eq!(compose(elapsed_time, sequence, machine_id), b.next_id().unwrap());
+    }
+
+    #[test]
+    fn test_reserve_block() {
+        let sf = Settings::new()
+            .set_machine_id_value(1)
+            .into_sonyflake()
+            .unwrap();
+
+        let first = sf.next_id().unwrap();
+        let block = sf.reserve_block(1000).unwrap();
+        assert_eq!(block.len(), 1000);
+        let ids = block.collect::<Vec<_>>();
+        assert!(ids[0] > first);
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+        assert!(sf.next_id().unwrap() > ids[999]);
+        assert_eq!(sf.reserve_block(0).unwrap().count(), 0);
+
+        sf.inner.lock().elapsed_time = (1 << BIT_LEN_TIME) - 1;
+        assert!(matches!(sf.reserve_block(1000).unwrap_err(), FlakeError::TimeOverflow));
+    }
+
+    #[test]
+    fn test_reserve_block_beyond_regression_tolerance() {
+        use std::sync::atomic::{AtomicI64, Ordering};
+        use std::sync::Arc;
+
+        let start_time = Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap();
+        let start_nanos = start_time.timestamp_nanos_opt().unwrap();
+        let now = Arc::new(AtomicI64::new(start_nanos + 1000 * FLAKE_TIME_UNIT));
+        let sf = Settings::new()
+            .set_start_time(start_time)
+            .set_machine_id_value(1)
+            .set_nano_clock(Box::new(ManualClock(now.clone())))
+            .into_sonyflake()
+            .unwrap();
+
+        // 234 windows ahead of the clock, well past the default tolerance of 100 windows
+        let ids = sf.reserve_block(60_000).unwrap().collect::<Vec<_>>();
+        let last = IDParts::decompose(ids[59_999]);
+        assert_eq!((last.get_time(), last.get_sequence()), (1234, 59_999 % 256));
+        let next = sf.next_id().unwrap();
+        assert!(next > ids[59_999]);
+        let parts = IDParts::decompose(next);
+        assert_eq!((parts.get_time(), parts.get_sequence()), (1234, 59_999 % 256 + 1));
+
+        // the clock moving back behind the reservation is still caught
+        now.store(start_nanos + 850 * FLAKE_TIME_UNIT, Ordering::SeqCst);
+        assert!(matches!(sf.next_id().unwrap_err(), FlakeError::ClockMovedBackwards(_)));
+
+        // once the clock is past the block, the tolerance applies to the last id alone
+        now.store(start_nanos + 1300 * FLAKE_TIME_UNIT, Ordering::SeqCst);
+        assert_eq!(IDParts::decompose(sf.next_id().unwrap()).get_time(), 1300);
+        now.store(start_nanos + 1150 * FLAKE_TIME_UNIT, Ordering::SeqCst);
+        assert!(matches!(sf.next_id().unwrap_err(), FlakeError::ClockMovedBackwards(_)));
+    }
+
+    #[test]
+    fn test_reserve_block_bit_reversal() {
+        let sf = Settings::new()
+            .set_machine_id_value(1)
+            .enable_bit_reversal()
+            .into_sonyflake()
+            .unwrap();
+
+        let first = bit_reverse_id(sf.next_id().unwrap());
+        let ids = sf.reserve_block(100).unwrap().collect::<Vec<_>>();
+        assert!(ids.iter().all(|id| sf.owns(*id)));
+        // encoded like the ids of next_id
+        let ordered = ids.iter().map(|id| bit_reverse_id(*id)).collect::<Vec<_>>();
+        assert!(ordered[0] > first);
+        assert!(ordered.windows(2).all(|w| w[0] < w[1]));
+        assert!(bit_reverse_id(sf.next_id().unwrap()) > ordered[99]);
+        assert!(ordered.iter().all(|id| IDParts::decompose(*id).get_machine_id() == 1));
+    }
+
+    #[test]
+    fn test_take_ids() {
+        let sf = Settings::new()
+            .set_machine_id_value(1)
+            .into_sonyflake()
+            .unwrap();
+        let (ids, err) = sf.take_ids(1000);
+        assert!(err.is_none());
+        assert_eq!(ids.len(), 1000);
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+
+        // the clock is in the last window of the lifetime, which has room for 256 ids
+        let now = Utc::now().timestamp_nanos_opt().unwrap() / FLAKE_TIME_UNIT * FLAKE_TIME_UNIT;
+        let start_time = Utc.timestamp_nanos(now - ((1 << BIT_LEN_TIME) - 1) * FLAKE_TIME_UNIT);
+        let sf = Settings::new()
+            .set_start_time(start_time)
+            .set_machine_id_value(1)
+            .set_nano_clock(Box::new(FixedClock(now)))
+            .set_first_id_strategy(FirstIdStrategy::Sequential)
+            .into_sonyflake()
+            .unwrap();
+        let (ids, err) = sf.take_ids(300);
+        assert_eq!(ids.len(), 256);
+        assert!(ids.iter().all(|id| IDParts::decompose(*id).get_time() == (1 << BIT_LEN_TIME) - 1));
+        assert!(matches!(err, Some(FlakeError::TimeOverflow)));
+    }
+
+    #[test]
+    fn test_borrowing_future() {
+        let sf = Settings::new()
+            .set_machine_id_value(1)
+            .into_sonyflake()
+            .unwrap();
+
+        sf.next_id().unwrap();
+        assert!(!sf.is_borrowing_future());
+        assert_eq!(sf.future_drift(), Duration::from_secs(0));
+
+        // saturate the next several windows at once
+        let _ = sf.reserve_block(10 << BIT_LEN_SEQUENCE).unwrap();
+        assert!(sf.is_borrowing_future());
+        assert!(sf.future_drift() > Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_borrow_machine_bits_for_sequence() {
+        let sf = Settings::new()
+            .set_machine_id_value(0x0abc)
+            .borrow_machine_bits_for_sequence(4)
+            .into_sonyflake()
+            .unwrap();
+
+        let mut per_window = std::collections::HashMap::new();
+        let mut last_id = 0;
+        for _ in 0..5000 {
+            let id = sf.next_id().unwrap();
+            assert!(id > last_id);
+            last_id = id;
+
+            let parts = decompose_borrowed(id, 4);
+            assert_eq!(parts.get_machine_id(), 0x0abc);
+            assert!(parts.get_sequence() < 1 << (BIT_LEN_SEQUENCE + 4));
+            *per_window.entry(parts.get_time()).or_insert(0) += 1;
+        }
+        assert!(per_window.values().any(|n| *n > 1 << BIT_LEN_SEQUENCE));
+
+        // the same id decomposes to the plain layout without knowing about the borrowed bits
+        let parts = IDParts::decompose(last_id);
+        assert_eq!(parts.get_machine_id() & 0x0fff, 0x0abc);
+
+        let err = Settings::new()
+            .set_machine_id_value(0x1abc)
+            .borrow_machine_bits_for_sequence(4)
+            .into_sonyflake()
+            .unwrap_err();
+        assert!(matches!(err, FlakeError::InvalidMachineID(0x1abc)));
+
+        let err = Settings::new()
+            .set_machine_id_value(1)
+            .borrow_machine_bits_for_sequence(9)
+            .into_sonyflake()
+            .unwrap_err();
+        assert!(matches!(err, FlakeError::InvalidBorrowedBits(9)));
+    }
+
+    #[test]
+    fn test_lifetime_elapsed_fraction() {
+        let sf = Settings::new()
+            .set_start_time(Utc.with_ymd_and_hms(1990, 1, 1, 0, 0, 0).unwrap())
+            .set_machine_id_value(1)
+            .into_sonyflake()
+            .unwrap();
+        // 30+ years of a ~174 year lifetime
+        assert!(sf.lifetime_elapsed_fraction() > 0.15);
+
+        let sf = Settings::new()
+            .set_start_time(Utc::now() - chrono::Duration::seconds(1))
+            .set_machine_id_value(1)
+            .into_infallible_sonyflake()
+            .unwrap();
+        assert!(sf.lifetime_elapsed_fraction() < 1e-6);
+    }
+
+    #[cfg(feature = "rand")]
+    #[test]
+    fn test_random_valid_id() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(42);
+        let ids = (0..10000).map(|_| crate::random_valid_id(&mut rng)).collect::<Vec<_>>();
+        for id in &ids {
+            assert_eq!(id >> 63, 0);
+            let parts = IDParts::decompose(*id);
+            assert!(parts.get_time() < 1 << BIT_LEN_TIME);
+            assert!(parts.get_sequence() < 1 << BIT_LEN_SEQUENCE);
+            assert_eq!(compose(parts.get_time() as i64, parts.get_sequence() as u16, parts.get_machine_id() as u16), *id);
+        }
+
+        let mut rng = StdRng::seed_from_u64(42);
+        assert_eq!(crate::random_valid_id(&mut rng), ids[0]);
+    }
+
+    #[test]
+    fn test_first_id_strategy_sequential() {
+        let sf = Settings::new()
+            .set_start_time(Utc::now())
+            .set_machine_id_value(1)
+            .set_first_id_strategy(FirstIdStrategy::Sequential)
+            .into_sonyflake()
+            .unwrap();
+        assert_eq!(IDParts::decompose(sf.next_id().unwrap()).get_sequence(), 0);
+
+        let sf = Settings::new()
+            .set_machine_id_value(1)
+            .set_first_id_strategy(FirstIdStrategy::Sequential)
+            .into_infallible_sonyflake()
+            .unwrap();
+        assert_eq!(IDParts::decompose(sf.next_id()).get_sequence(), 0);
+    }
+
+    #[test]
+    fn test_sequence_start() {
+        let settings = || Settings::new().set_machine_id_value(1);
+
+        let sf = settings().set_start_time(Utc::now()).set_sequence_start(42).into_sonyflake().unwrap();
+        let first = IDParts::decompose(sf.next_id().unwrap());
+        assert_eq!(first.get_sequence(), 42);
+        let second = IDParts::decompose(sf.next_id().unwrap());
+        assert!(second.get_time() > first.get_time() || second.get_sequence() == 43);
+
+        let sf = settings().set_sequence_start(255).into_infallible_sonyflake().unwrap();
+        assert_eq!(IDParts::decompose(sf.next_id()).get_sequence(), 255);
+        // the sequence wraps into the next window
+        let parts = IDParts::decompose(sf.next_id());
+        assert_eq!(parts.get_sequence(), 0);
+
+        assert!(matches!(
+            settings().set_sequence_start(256).into_sonyflake().unwrap_err(),
+            FlakeError::SequenceOutOfRange(256)
+        ));
+        assert!(matches!(
+            settings().set_sequence_start(128).reserve_tag_bits(1).into_sonyflake().unwrap_err(),
+            FlakeError::SequenceOutOfRange(128)
+        ));
+        assert!(matches!(
+            settings().set_sequence_start(256).into_infallible_sonyflake().unwrap_err(),
+            FlakeError::SequenceOutOfRange(256)
+        ));
+    }
+
+    #[test]
+    fn test_synthetic_id() {
+        let start_time = Utc.with_ymd_and_hms(2021, 8, 6, 0, 0, 0).unwrap();
+        let t1 = Utc.with_ymd_and_hms(2022, 1, 1, 12, 0, 0).unwrap();
+        let t2 = t1 + chrono::Duration::milliseconds(25);
+        let t3 = Utc.with_ymd_and_hms(2023, 6, 1, 0, 0, 0).unwrap();
+
+        // records in created_at order, the ones sharing a timestamp with distinct sequences
+        let records = [(t1, 0), (t1, 1), (t1, 2), (t2, 0), (t3, 0), (t3, 1)];
+        let ids = records
+            .iter()
+            .map(|(created_at, sequence)| synthetic_id(*created_at, start_time, *sequence, 5).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(ids.iter().collect::<HashSet<_>>().len(), records.len());
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+        let parts = IDParts::decompose(ids[3]);
+        assert_eq!(parts.get_time() as i64, (t2 - start_time).num_milliseconds() / 10);
+        assert_eq!(parts.get_machine_id(), 5);
+
+        assert!(matches!(
+            synthetic_id(start_time - chrono::Duration::seconds(1), start_time, 0, 5).unwrap_err(),
+            FlakeError::CreatedBeforeStartTime(_)
+        ));
+        assert!(matches!(synthetic_id(t1, start_time, 256, 5).unwrap_err(), FlakeError::SequenceOutOfRange(256)));
+        assert!(matches!(
+            synthetic_id(start_time + chrono::Duration::days(365 * 200), start_time, 0, 5).unwrap_err(),
+            FlakeError::TimeOverflow
+        ));
+    }
+
+    #[test]
+    fn test_next_prefixed() {
+        let sf = Settings::new()
+            .set_machine_id_value(1)
+            .set_id_prefix("ord")
+            .into_sonyflake()
+            .unwrap();
+        let prefixed = sf.next_prefixed().unwrap();
+        assert!(prefixed.starts_with("ord_"));
+        let id = sf.strip_prefix_and_decode(&prefixed).unwrap();
+        assert_eq!(IDParts::decompose(id).get_machine_id(), 1);
+        assert!(sf.next_id().unwrap() > id);
+
+        assert!(matches!(
+            sf.strip_prefix_and_decode(&prefixed.replacen("ord", "usr", 1)).unwrap_err(),
+            FlakeError::InvalidEncodedID(_)
+        ));
+        assert!(sf.strip_prefix_and_decode("ord3F8a").is_err());
+
+        let err = Settings::new()
+            .set_machine_id_value(1)
+            .set_id_prefix("ord_v2")
+            .into_sonyflake()
+            .unwrap_err();
+        assert!(matches!(err, FlakeError::InvalidPrefix(prefix) if prefix == "ord_v2"));
+    }
+
+    #[test]
+    fn test_next_id_tagged() {
+        let sf = Settings::new()
+            .set_machine_id_value(1)
+            .reserve_tag_bits(4)
+            .into_sonyflake()
+            .unwrap();
+
+        let mut ids = HashSet::new();
+        for i in 0..1000 {
+            let tag = (i % 16) as u8;
+            let id = sf.next_id_tagged(tag).unwrap();
+            let parts = IDParts::decompose(id);
+            assert_eq!(parts.get_tag(4), tag);
+            assert_eq!(parts.get_machine_id(), 1);
+            assert!(ids.insert(id), "duplicate id: {}", id);
+        }
+
+        // ids of the same tag stay unique and increasing
+        let same_tag = (0..300).map(|_| sf.next_id_tagged(9).unwrap()).collect::<Vec<_>>();
+        assert!(same_tag.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(IDParts::decompose(sf.next_id().unwrap()).get_tag(4), 0);
+
+        assert!(matches!(sf.next_id_tagged(16).unwrap_err(), FlakeError::InvalidTag(16)));
+        // invalid tag lengths do not overflow the shift
+        let parts = IDParts::decompose(sf.next_id_tagged(15).unwrap());
+        assert_eq!((parts.get_tag(8), parts.get_tag(9), parts.get_tag(255)), (0, 0, 0));
+
+        let layout = BitLayout::new(35, 16, 12).unwrap();
+        let sf = Settings::new()
+            .set_machine_id_value(1)
+            .set_bit_layout(layout)
+            .reserve_tag_bits(6)
+            .into_sonyflake()
+            .unwrap();
+        let parts = layout.decompose(sf.next_id_tagged(42).unwrap());
+        assert_eq!(parts.get_tag_with_layout(6, &layout), 42);
+        assert_eq!(parts.get_tag_with_layout(16, &layout), 0);
+        let err = Settings::new()
+            .set_machine_id_value(1)
+            .reserve_tag_bits(8)
+            .into_sonyflake()
+            .unwrap_err();
+        assert!(matches!(err, FlakeError::InvalidTagBits(8)));
+    }
+
+    #[test]
+    fn test_max_consecutive_sleeps() {
+        use std::sync::atomic::{AtomicI64, Ordering};
+        use std::sync::Arc;
+
+        let start_time = Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap();
+        let start_nanos = start_time.timestamp_nanos_opt().unwrap();
+        let now = Arc::new(AtomicI64::new(start_nanos + 1000 * FLAKE_TIME_UNIT));
+        let sf = Settings::new()
+            .set_start_time(start_time)
+            .set_machine_id_value(1)
+            .set_nano_clock(Box::new(ManualClock(now.clone())))
+            .set_first_id_strategy(FirstIdStrategy::Sequential)
+            .set_max_consecutive_sleeps(3)
+            .into_sonyflake()
+            .unwrap();
+
+        // saturate every window, moving the clock on to the next window only once the sequence wraps into it
+        for _ in 0..1 << BIT_LEN_SEQUENCE {
+            sf.next_id().unwrap();
+        }
+        for sleeps in 1..=3 {
+            let (id, wait) = sf.next_id_with_wait().unwrap();
+            assert!(wait > Duration::ZERO);
+            assert_eq!(IDParts::decompose(id).get_time(), 1000 + sleeps);
+            assert_eq!(sf.inner.lock().consecutive_sleeps, sleeps as u32);
+            now.fetch_add(FLAKE_TIME_UNIT, Ordering::SeqCst);
+            for _ in 1..1 << BIT_LEN_SEQUENCE {
+                assert_eq!(sf.next_id_with_wait().unwrap().1, Duration::ZERO);
+            }
+        }
+        assert!(matches!(sf.next_id().unwrap_err(), FlakeError::RateExceeded));
+        assert_eq!(sf.inner.lock().consecutive_sleeps, 3);
+
+        // the overload clears once the clock catches up and a call starts a window without sleeping
+        now.fetch_add(FLAKE_TIME_UNIT, Ordering::SeqCst);
+        assert_eq!(sf.next_id_with_wait().unwrap().1, Duration::ZERO);
+        assert_eq!(sf.inner.lock().consecutive_sleeps, 0);
+    }
+
+    #[test]
+    fn test_daily_rolling_epoch() {
+        let units_per_day = crate::NANOS_PER_DAY / FLAKE_TIME_UNIT;
+        let sf = Settings::new()
+            .set_machine_id_value(1)
+            .daily_rolling_epoch()
+            .into_sonyflake()
+            .unwrap();
+
+        let before = Utc::now();
+        let id = sf.next_id().unwrap();
+        assert!(IDParts::decompose(id).get_time() < units_per_day as u64);
+        let at = rolling_timestamp(id, before.date_naive());
+        assert!(at <= Utc::now() && before - at < chrono::Duration::milliseconds(10));
+
+        // move the state to the end of the previous day, as if the last id was generated at 23:59:59.99
+        {
+            let mut inner = sf.inner.lock();
+            inner.day_start -= units_per_day;
+            inner.elapsed_time = units_per_day - 1;
+            inner.sequence = 17;
+        }
+        let yesterday = compose(units_per_day - 1, 17, 1);
+        assert!(IDParts::decompose(yesterday).get_time() < units_per_day as u64);
+
+        // the epoch resets instead of continuing from the previous day's time
+        let id = sf.next_id().unwrap();
+        let parts = IDParts::decompose(id);
+        assert_eq!(parts.get_sequence(), 0);
+        assert!(parts.get_time() < units_per_day as u64);
+        assert_eq!(rolling_timestamp(id, Utc::now().date_naive()).date_naive(), Utc::now().date_naive());
+        assert!(sf.next_id().unwrap() > id);
+    }
+
+    #[test]
+    fn test_seek_to() {
+        let start_time = Utc::now();
+        let sf = Settings::new()
+            .set_start_time(start_time)
+            .set_machine_id_value(1)
+            .into_sonyflake()
+            .unwrap();
+        // an id from the future, which a fresh generator would otherwise not pass for a while
+        {
+            let mut inner = sf.inner.lock();
+            inner.elapsed_time = inner.clock.elapsed_time(inner.start_time) + 50;
+        }
+        let last = sf.next_id().unwrap();
+        let parts = IDParts::decompose(last);
+
+        let sf = Settings::new()
+            .set_start_time(start_time)
+            .set_machine_id_value(1)
+            .into_sonyflake()
+            .unwrap();
+        sf.seek_to(&parts).unwrap();
+        let next = sf.next_id().unwrap();
+        assert!(next > last);
+        assert_eq!(IDParts::decompose(next).get_sequence(), parts.get_sequence() + 1);
+
+        let other = Settings::new()
+            .set_start_time(start_time)
+            .set_machine_id_value(3)
+            .into_sonyflake()
+            .unwrap();
+        assert!(matches!(other.seek_to(&parts).unwrap_err(), FlakeError::InvalidMachineID(1)));
+
+        let counter = Settings::new()
+            .set_machine_id_value(1)
+            .sequence_only_mode()
+            .into_sonyflake()
+            .unwrap();
+        let last = counter.next_id().unwrap();
+        counter.seek_to(&IDParts::decompose(last + (10 << 16))).unwrap();
+        assert_eq!(counter.next_id().unwrap(), last + (11 << 16));
+    }
+
+    #[test]
+    fn test_next_cursor() {
+        let last_seen = compose(1000, 255, u16::MAX);
+        let cursor = next_cursor(last_seen).unwrap();
+        assert!(cursor > last_seen);
+        let parts = IDParts::decompose(cursor);
+        assert_eq!(parts.get_msb(), 0);
+        assert_eq!((parts.get_time(), parts.get_sequence(), parts.get_machine_id()), (1001, 0, 0));
+
+        assert_eq!(next_cursor(0), Some(1));
+        assert_eq!(next_cursor((1 << 63) - 1), None);
+    }
+
+    #[test]
+    fn test_config_fingerprint() {
+        let start_time = Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap();
+        let a = Settings::new()
+            .set_start_time(start_time)
+            .set_machine_id_value(1)
+            .into_sonyflake()
+            .unwrap();
+        let b = Settings::new()
+            .set_start_time(start_time)
+            .set_machine_id_value(2)
+            .into_infallible_sonyflake()
+            .unwrap();
+        assert_eq!(a.config_fingerprint(), b.config_fingerprint());
+
+        // a different time unit
+        let c = Settings::new()
+            .set_start_time(start_time)
+            .set_machine_id_value(1)
+            .into_sonyflake32()
+            .unwrap();
+        assert_ne!(a.config_fingerprint(), c.config_fingerprint());
+
+        // a different start time
+        let d = Settings::new()
+            .set_machine_id_value(1)
+            .into_sonyflake()
+            .unwrap();
+        assert_ne!(a.config_fingerprint(), d.config_fingerprint());
+    }
+
+    #[test]
+    fn test_ids_between() {
+        let a = Utc.with_ymd_and_hms(2021, 8, 6, 0, 0, 0).unwrap();
+        let b = a + chrono::Duration::minutes(1);
+        assert_eq!(ids_between(a, b, 1000), 60 * 1000);
+        // above the ceiling of 2^8 ids per 10 msec
+        assert_eq!(ids_between(a, b, 1_000_000), 60 * 25600);
+        assert_eq!(ids_between(b, a, 1000), 0);
+    }
+
+    #[test]
+    fn test_state_and_restore_state() {
+        let start_time = Utc::now();
+        let sf = Settings::new()
+            .set_start_time(start_time)
+            .set_machine_id_value(1)
+            .into_sonyflake()
+            .unwrap();
+        // borrow from the future, so the snapshot is ahead of a fresh generator
+        {
+            let mut inner = sf.inner.lock();
+            inner.elapsed_time = inner.clock.elapsed_time(inner.start_time) + 50;
+            inner.sequence = 0;
+        }
+        let last = (0..5).map(|_| sf.next_id().unwrap()).collect::<Vec<_>>()[4];
+        let (elapsed_time, sequence) = sf.state();
+        assert_eq!(compose(elapsed_time, sequence, 1), last);
+
+        let restored = Settings::new()
+            .set_start_time(start_time)
+            .set_machine_id_value(1)
+            .into_sonyflake()
+            .unwrap();
+        restored.restore_state(elapsed_time, sequence).unwrap();
+        let ids = (0..200).map(|_| restored.next_id().unwrap()).collect::<Vec<_>>();
+        assert!(ids[0] > last);
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+
+        assert!(matches!(restored.restore_state(-1, 0).unwrap_err(), FlakeError::TimeOverflow));
+        assert!(matches!(restored.restore_state(1 << BIT_LEN_TIME, 0).unwrap_err(), FlakeError::TimeOverflow));
+        assert!(matches!(restored.restore_state(0, 256).unwrap_err(), FlakeError::SequenceOutOfRange(256)));
+    }
+
+    #[test]
+    fn test_no_network_interfaces() {
+        use pnet::datalink::NetworkInterface;
+
+        assert!(matches!(lower_16_bit_private_ip_of(&[]).unwrap_err(), FlakeError::NoNetworkInterfaces));
+
+        let public_only = NetworkInterface {
+            name: "eth0".to_string(),
+            description: String::new(),
+            index: 2,
+            mac: None,
+            ips: vec!["203.0.113.7/24".parse().unwrap()],
+            flags: 1, // IFF_UP
+        };
+        assert!(matches!(
+            lower_16_bit_private_ip_of(std::slice::from_ref(&public_only)).unwrap_err(),
+            FlakeError::NoPrivateIPv4Address
+        ));
+
+        let private = NetworkInterface {
+            ips: vec!["10.1.2.3/8".parse().unwrap()],
+            ..public_only
+        };
+        assert_eq!(lower_16_bit_private_ip_of(&[private]).unwrap(), (2 << 8) + 3);
+    }
+
+    #[test]
+    fn test_ipv6_fallback() {
+        use pnet::datalink::NetworkInterface;
+
+        let interface = |ips: &[&str]| NetworkInterface {
+            name: "eth0".to_string(),
+            description: String::new(),
+            index: 2,
+            mac: None,
+            ips: ips.iter().map(|ip| ip.parse().unwrap()).collect(),
+            flags: 1, // IFF_UP
+        };
+
+        let link_local = interface(&["2001:db8::1/64", "fe80::1:abcd/64"]);
+        assert!(matches!(
+            lower_16_bit_private_ip_of(std::slice::from_ref(&link_local)).unwrap_err(),
+            FlakeError::NoPrivateIPv4Address
+        ));
+        assert_eq!(lower_16_bit_private_ip_or_ipv6_of(std::slice::from_ref(&link_local)).unwrap(), 0xabcd);
+
+        // a unique local address wins over a link-local one, on any interface
+        let unique_local = interface(&["fd12:3456:789a::42/64"]);
+        assert_eq!(lower_16_bit_private_ip_or_ipv6_of(&[link_local, unique_local]).unwrap(), 0x42);
+
+        // a private IPv4 address still comes first
+        assert_eq!(
+            lower_16_bit_private_ip_or_ipv6_of(&[interface(&["fd00::7/64", "192.168.3.4/24"])]).unwrap(),
+            (3 << 8) + 4
+        );
+
+        assert!(matches!(
+            lower_16_bit_private_ip_or_ipv6_of(&[interface(&["2001:db8::1/64"])]).unwrap_err(),
+            FlakeError::NoPrivateIPAddress
+        ));
+
+        let sf = Settings::new().fallback_to_ipv6().into_sonyflake().unwrap();
+        assert_eq!(sf.machine_id(), default_machine_id(false).unwrap());
+    }
+
+    #[test]
+    fn test_mac_fallback() {
+        use pnet::datalink::{MacAddr, NetworkInterface};
+
+        let interface = |name: &str, mac: Option<MacAddr>, ips: &[&str], flags: u32| NetworkInterface {
+            name: name.to_string(),
+            description: String::new(),
+            index: 2,
+            mac,
+            ips: ips.iter().map(|ip| ip.parse().unwrap()).collect(),
+            flags,
+        };
+        let mac = MacAddr::new(0x02, 0x42, 0xac, 0x11, 0x00, 0x02);
+        let public = interface("eth0", Some(mac), &["203.0.113.7/24"], 1); // IFF_UP
+        // IFF_UP | IFF_LOOPBACK
+        let loopback = interface("lo", Some(MacAddr::new(0, 0, 0, 0, 0, 1)), &["127.0.0.1/8"], 1 | 8);
+        let hash = lower_16_bit_mac_hash_of(std::slice::from_ref(&public)).unwrap();
+
+        // the loopback interface and zero MAC addresses are skipped
+        let no_mac = interface("tun0", Some(MacAddr::zero()), &[], 1);
+        assert_eq!(lower_16_bit_mac_hash_of(&[loopback.clone(), no_mac.clone(), public.clone()]), Some(hash));
+        assert_eq!(lower_16_bit_mac_hash_of(&[loopback.clone(), no_mac]), None);
+        let other = interface("eth1", Some(MacAddr::new(0x02, 0x42, 0xac, 0x11, 0x00, 0x03)), &[], 1);
+        assert_ne!(lower_16_bit_mac_hash_of(&[other]), Some(hash));
+
+        // a private ip address comes first, then the MAC address
+        let private = interface("eth1", None, &["10.1.2.3/8"], 1);
+        assert_eq!(default_machine_id_of(&[public.clone(), private], false).unwrap(), (2 << 8) + 3);
+        assert_eq!(default_machine_id_of(std::slice::from_ref(&public), false).unwrap(), hash);
+        let ipv6 = interface("eth1", None, &["fd00::7/64"], 1);
+        assert_eq!(default_machine_id_of(&[public, ipv6.clone()], true).unwrap(), 7);
+
+        assert!(matches!(
+            default_machine_id_of(std::slice::from_ref(&loopback), false).unwrap_err(),
+            FlakeError::NoPrivateIPv4Address
+        ));
+        assert!(matches!(
+            default_machine_id_of(&[loopback, interface("eth0", None, &["2001:db8::1/64"], 1)], true).unwrap_err(),
+            FlakeError::NoPrivateIPAddress
+        ));
+        assert!(matches!(default_machine_id_of(&[], false).unwrap_err(), FlakeError::NoNetworkInterfaces));
+    }
+
+    #[test]
+    fn test_bit_reversal() {
+        for x in [0, 1, 42, compose(1000, 7, 3), (1 << 63) - 1] {
+            assert_eq!(bit_reverse_id(bit_reverse_id(x)), x);
+            assert_eq!(bit_reverse_id(x) >> 63, 0);
+        }
+
+        let sf = Settings::new()
+            .set_machine_id_value(1)
+            .enable_bit_reversal()
+            .into_sonyflake()
+            .unwrap();
+        let reversed = (0..100).map(|_| sf.next_id().unwrap()).collect::<Vec<_>>();
+        for w in reversed.windows(2) {
+            // the sequence lands in bits 39 to 46, so consecutive ids differ in the high half
+            assert_ne!(w[0] >> 32, w[1] >> 32);
+            assert!(bit_reverse_id(w[0]) < bit_reverse_id(w[1]));
+        }
+        assert_eq!(IDParts::decompose(bit_reverse_id(reversed[0])).get_machine_id(), 1);
+    }
+
+    #[test]
+    fn test_quantize_id_time() {
+        // one second buckets
+        let a = compose(1000, 3, 7);
+        let b = compose(1099, 200, 7);
+        let c = compose(1100, 0, 7);
+        assert_eq!(quantize_id_time(a, 100), quantize_id_time(b, 100));
+        assert_eq!(quantize_id_time(a, 100), compose(1000, 0, 7));
+        assert_ne!(quantize_id_time(b, 100), quantize_id_time(c, 100));
+        assert_eq!(quantize_id_time(b, 0), compose(1099, 0, 7));
+    }
+
+    #[test]
+    fn test_current_window_fill() {
+        let start_time = Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap();
+        let now = start_time.timestamp_nanos_opt().unwrap() + 1_000_000_000;
+        let sf = Settings::new()
+            .set_start_time(start_time)
+            .set_machine_id_value(1)
+            .set_nano_clock(Box::new(FixedClock(now)))
+            .into_sonyflake()
+            .unwrap();
+        assert_eq!(sf.current_window_fill(), 0.0);
+
+        for _ in 0..128 {
+            sf.next_id().unwrap();
+        }
+        assert!((sf.current_window_fill() - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_from_state() {
+        let start_time = Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap();
+        // a little ahead of the clock, so the ids go on from the state
+        let elapsed_time = (Utc::now() - start_time).num_milliseconds() / 10 + 20;
+        let sf = SonyFlake::from_state(start_time, 5, elapsed_time, 7).unwrap();
+        assert_eq!((sf.machine_id(), sf.start_time()), (5, start_time));
+        assert_eq!(sf.state(), (elapsed_time, 7));
+        let parts = IDParts::decompose(sf.next_id().unwrap());
+        assert_eq!((parts.get_time(), parts.get_sequence(), parts.get_machine_id()), (elapsed_time as u64, 8, 5));
+
+        assert!(matches!(
+            SonyFlake::from_state(start_time, 5, 1 << BIT_LEN_TIME, 0).unwrap_err(),
+            FlakeError::TimeOverflow
+        ));
+        assert!(matches!(SonyFlake::from_state(start_time, 5, -1, 0).unwrap_err(), FlakeError::TimeOverflow));
+        assert!(matches!(
+            SonyFlake::from_state(start_time, 5, 0, 256).unwrap_err(),
+            FlakeError::SequenceOutOfRange(256)
+        ));
+        let future = Utc::now() + chrono::Duration::days(1);
+        assert!(matches!(
+            SonyFlake::from_state(future, 5, 0, 0).unwrap_err(),
+            FlakeError::StartTimeAheadOfCurrentTime(_)
+        ));
+    }
+
+    #[test]
+    fn test_reset_start_time() {
+        let start_time = Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap();
+        let sf = Settings::new()
+            .set_start_time(start_time)
+            .set_machine_id_value(11)
+            .into_sonyflake()
+            .unwrap();
+        let old = sf.next_id().unwrap();
+
+        // an earlier start time keeps the ids increasing
+        let new_start = Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap();
+        sf.reset_start_time(new_start).unwrap();
+        assert_eq!(sf.start_time(), new_start);
+        let new = sf.next_id().unwrap();
+        assert!(new > old);
+        let parts = IDParts::decompose(new);
+        assert_eq!((parts.get_sequence(), parts.get_machine_id()), (0, 11));
+        assert!((Utc::now() - sf.timestamp_of(new)).num_seconds() < 5);
+
+        let future = Utc::now() + chrono::Duration::days(1);
+        assert!(matches!(
+            sf.reset_start_time(future).unwrap_err(),
+            FlakeError::StartTimeAheadOfCurrentTime(_)
+        ));
+        assert_eq!(sf.start_time(), new_start);
+    }
+
+    #[test]
+    fn test_reset_start_time_shared_by_clones() {
+        let start_time = Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap();
+        let sf = Settings::new()
+            .set_start_time(start_time)
+            .set_machine_id_value(11)
+            .into_sonyflake()
+            .unwrap();
+        let clone = sf.clone();
+        let old = sf.next_id().unwrap();
+
+        let new_start = Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap();
+        sf.reset_start_time(new_start).unwrap();
+        assert_eq!(clone.start_time(), new_start);
+        let new = clone.next_id().unwrap();
+        assert!(new > old);
+        assert!((Utc::now() - sf.timestamp_of(new)).num_seconds() < 5);
+        // the reset handle carries on from the window of the clone
+        assert!(sf.next_id().unwrap() > new);
+    }
+
+    #[test]
+    fn test_with_verified_machine_id() {
+        let start_time = Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap();
+        let sf = SonyFlake::with_verified_machine_id(54321, start_time).unwrap();
+        let parts = IDParts::decompose(sf.next_id().unwrap());
+        assert_eq!(parts.get_machine_id(), 54321);
+
+        let future = Utc::now() + chrono::Duration::days(1);
+        assert!(matches!(
+            SonyFlake::with_verified_machine_id(1, future).unwrap_err(),
+            FlakeError::StartTimeAheadOfCurrentTime(_)
+        ));
+    }
+
+    #[test]
+    fn test_duration_units() {
+        assert_eq!(units_to_duration(1), chrono::Duration::milliseconds(10));
+        assert_eq!(duration_to_units(chrono::Duration::seconds(1)), 100);
+        assert_eq!(duration_to_units(chrono::Duration::microseconds(19_999)), 1);
+
+        let d = chrono::Duration::minutes(90);
+        assert_eq!(units_to_duration(duration_to_units(d)), d);
+        assert_eq!(duration_to_units(chrono::Duration::days(365 * 1000)), 365 * 1000 * 8_640_000);
+    }
+
+    #[test]
+    fn test_ids_for_window() {
+        let ids = ids_for_window(12345, 678).unwrap().collect::<Vec<_>>();
+        assert_eq!(ids.len(), 256);
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+        for (sequence, id) in ids.iter().enumerate() {
+            let parts = IDParts::decompose(*id);
+            assert_eq!(parts.get_time(), 12345);
+            assert_eq!(parts.get_sequence(), sequence as u64);
+            assert_eq!(parts.get_machine_id(), 678);
+        }
+
+        assert!(matches!(ids_for_window(1 << BIT_LEN_TIME, 1).err(), Some(FlakeError::TimeOverflow)));
+    }
+
+    #[test]
+    fn test_on_exhaustion() {
+        let start_time = Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap();
+        let now = start_time.timestamp_nanos_opt().unwrap() + 1_000_000_000;
+        let mut spares = vec![7];
+        let sf = Settings::new()
+            .set_start_time(start_time)
+            .set_machine_id_value(1)
+            .set_nano_clock(Box::new(FixedClock(now)))
+            .set_on_exhaustion(Box::new(move || spares.pop()))
+            .into_sonyflake()
+            .unwrap();
+
+        let first = IDParts::decompose(sf.next_id().unwrap());
+        for _ in 1..256 {
+            sf.next_id().unwrap();
+        }
+        let spare = IDParts::decompose(sf.next_id().unwrap());
+        assert_eq!(spare.get_machine_id(), 7);
+        assert_eq!(spare.get_sequence(), 0);
+        // no sleep: the spare id stays in the same window
+        assert_eq!(spare.get_time(), first.get_time());
+        assert!(!sf.is_borrowing_future());
+    }
+
+    #[test]
+    fn test_machine_id_transform() {
+        let machine_id_of = |raw: u16| {
+            let sf = Settings::new()
+                .set_machine_id_value(raw)
+                .set_machine_id_transform(Box::new(|id| id ^ 0xa5c3))
+                .into_sonyflake()
+                .unwrap();
+            IDParts::decompose(sf.next_id().unwrap()).get_machine_id() as u16
+        };
+
+        let a = machine_id_of(0x0102);
+        let b = machine_id_of(0x0103);
+        assert_eq!(a, 0x0102 ^ 0xa5c3);
+        assert_ne!(a, 0x0102);
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn test_next_id_checked() {
+        let start_time = Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap();
+        let now = start_time.timestamp_nanos_opt().unwrap() + 1_000_000_000;
+        let sf = Settings::new()
+            .set_start_time(start_time)
+            .set_machine_id_value(1)
+            .set_nano_clock(Box::new(FixedClock(now)))
+            .into_sonyflake()
+            .unwrap();
+
+        for _ in 0..256 {
+            sf.next_id_checked().unwrap();
+        }
+        assert!(matches!(
+            sf.next_id_checked().unwrap_err(),
+            Backpressure::Saturated { retry_after } if retry_after == Duration::from_millis(10)
+        ));
+        assert!(!sf.is_borrowing_future());
+
+        sf.inner.lock().elapsed_time = (1 << BIT_LEN_TIME) - 1;
+        sf.inner.lock().sequence = 255;
+        assert!(matches!(sf.next_id_checked().unwrap_err(), Backpressure::OverflowImminent));
+
+        // the exhaustion hook supplies spare machine ids before the window counts as saturated
+        let mut spares = vec![7];
+        let sf = Settings::new()
+            .set_start_time(start_time)
+            .set_machine_id_value(1)
+            .set_nano_clock(Box::new(FixedClock(now)))
+            .set_on_exhaustion(Box::new(move || spares.pop()))
+            .into_sonyflake()
+            .unwrap();
+        let ids = (0..512).map(|_| sf.next_id_checked().unwrap()).collect::<Vec<_>>();
+        assert_eq!(ids.iter().collect::<HashSet<_>>().len(), 512);
+        assert_eq!(IDParts::decompose(ids[256]).get_machine_id(), 7);
+        assert!(matches!(sf.next_id_checked().unwrap_err(), Backpressure::Saturated { .. }));
+
+        // other failures carry their error
+        let sf = Settings::new()
+            .set_machine_id_value(1)
+            .sequence_only_mode()
+            .into_sonyflake()
+            .unwrap();
+        sf.inner.lock().elapsed_time = (1 << BIT_LEN_TIME) - 1;
+        sf.inner.lock().sequence = (1 << BIT_LEN_SEQUENCE) - 1;
+        sf.next_id_checked().unwrap();
+        let err = sf.next_id_checked().unwrap_err();
+        assert!(matches!(err, Backpressure::Failed(FlakeError::CounterExhausted)));
+        assert_eq!(err.to_string(), FlakeError::CounterExhausted.to_string());
+    }
+
+    #[test]
+    fn test_format_ids_table() {
+        let start_time = Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap();
+        let ids = [compose(100, 3, 42), compose(360_000, 0, 65535)];
+        let table = format_ids_table(&ids, start_time);
+        let lines = table.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("id"));
+        assert!(lines[0].contains("machine_id"));
+        assert!(lines[1].contains("2021-01-01T00:00:01.000Z"));
+        assert!(lines[1].ends_with(" 42"));
+        assert!(lines[2].contains("2021-01-01T01:00:00.000Z"));
+        assert!(lines[2].ends_with(" 65535"));
+        // the columns are aligned
+        assert_eq!(lines[1].find("2021"), lines[0].find("time"));
+
+        assert_eq!(format_ids_table(&[], start_time).lines().count(), 1);
+    }
+
+    #[test]
+    fn test_to_datetime() {
+        let start_time = Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap();
+        let created_at = Utc.with_ymd_and_hms(2022, 6, 15, 12, 30, 45).unwrap() + chrono::Duration::milliseconds(120);
+        let id = synthetic_id(created_at, start_time, 0, 1).unwrap();
+        assert_eq!(IDParts::decompose(id).to_datetime(start_time), created_at);
+        assert_eq!(timestamp_of(id, start_time), created_at);
+
+        let sf = Settings::new()
+            .set_start_time(start_time)
+            .set_machine_id_value(1)
+            .into_sonyflake()
+            .unwrap();
+        let before = Utc::now();
+        let generated_at = timestamp_of(sf.next_id().unwrap(), start_time);
+        assert!(generated_at > before - chrono::Duration::milliseconds(10));
+        assert!(generated_at <= Utc::now());
+    }
+
+    #[test]
+    fn test_start_time() {
+        let start_time = Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap() + chrono::Duration::milliseconds(120);
+        let sf = Settings::new()
+            .set_start_time(start_time + chrono::Duration::microseconds(4321))
+            .set_machine_id_value(1)
+            .into_sonyflake()
+            .unwrap();
+        assert_eq!(sf.start_time(), start_time);
+        let id = sf.next_id().unwrap();
+        assert!(IDParts::decompose(id).to_datetime(sf.start_time()) <= Utc::now());
+
+        let sf = Settings::new()
+            .set_start_time(start_time)
+            .set_machine_id_value(1)
+            .into_infallible_sonyflake()
+            .unwrap();
+        assert_eq!(sf.start_time(), start_time);
+    }
+
+    #[test]
+    fn test_machine_id() {
+        let sf = Settings::new()
+            .set_machine_id_value(4242)
+            .into_sonyflake()
+            .unwrap();
+        assert_eq!(sf.machine_id(), 4242);
+
+        let sf = Settings::new().into_infallible_sonyflake().unwrap();
+        assert_eq!(sf.machine_id(), default_machine_id(false).unwrap());
+    }
+
+    #[test]
+    fn test_next_ids() {
+        let sf = Settings::new()
+            .set_machine_id_value(1)
+            .into_sonyflake()
+            .unwrap();
+        let ids = sf.next_ids(1000).unwrap();
+        assert_eq!(ids.len(), 1000);
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+        assert!(sf.next_id().unwrap() > ids[999]);
+
+        let sf = Settings::new()
+            .set_machine_id_value(1)
+            .into_infallible_sonyflake()
+            .unwrap();
+        let ids = sf.next_ids(1000);
+        assert_eq!(ids.len(), 1000);
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+        assert!(sf.next_id() > ids[999]);
+        assert!(sf.next_ids(0).is_empty());
+    }
+
+    #[test]
+    fn test_time_unit() {
+        let start_time = Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap();
+        let now = start_time.timestamp_nanos_opt().unwrap() + 1_234_000_000;
+
+        let sf = Settings::new()
+            .set_start_time(start_time)
+            .set_machine_id_value(1)
+            .set_time_unit(Duration::from_millis(1))
+            .set_nano_clock(Box::new(FixedClock(now)))
+            .into_sonyflake()
+            .unwrap();
+        assert_eq!(sf.time_unit(), Duration::from_millis(1));
+
+        let id = sf.next_id().unwrap();
+        assert_eq!(IDParts::decompose(id).get_time(), 1234);
+        assert_eq!(sf.timestamp_of(id), start_time + chrono::Duration::milliseconds(1234));
+        assert_eq!(sf.start_time(), start_time);
+
+        // the sequence of a 1 msec window is used up 1 msec later
+        for _ in 1..256 {
+            sf.next_id().unwrap();
+        }
+        assert!(matches!(
+            sf.next_id_checked().unwrap_err(),
+            Backpressure::Saturated { retry_after } if retry_after == Duration::from_millis(1)
+        ));
+
+        // 2^39 secs overflow nanoseconds
+        for time_unit in [Duration::from_secs(0), Duration::from_secs(2 * 24 * 60 * 60), Duration::from_secs(1)] {
+            assert!(matches!(
+                Settings::new().set_time_unit(time_unit).into_sonyflake().unwrap_err(),
+                FlakeError::InvalidTimeUnit(unit) if unit == time_unit
+            ));
+        }
+    }
+
+    #[test]
+    fn test_timestamp_of_coarse_time_unit() {
+        // 2^33 secs, which end past the year 2262 counted from 2021
+        let settings = || {
+            Settings::new()
+                .set_start_time(Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap())
+                .set_machine_id_value(1)
+                .set_bit_layout(BitLayout::new(33, 14, 16).unwrap())
+                .set_time_unit(Duration::from_secs(1))
+        };
+        let max = (1 << 63) - 1;
+        let sf = settings().into_sonyflake().unwrap();
+        assert_eq!(sf.timestamp_of(max), Utc.timestamp_nanos(i64::MAX));
+        let sf = settings().into_infallible_sonyflake().unwrap();
+        assert_eq!(sf.timestamp_of(max), Utc.timestamp_nanos(i64::MAX));
+    }
+
+    #[test]
+    fn test_snowflake() {
+        // 2024-01-01 00:00:00 UTC
+        let now_millis = 1_704_067_200_000;
+        let sf = Settings::snowflake()
+            .set_machine_id_value(321)
+            .set_nano_clock(Box::new(FixedClock(now_millis * 1_000_000)))
+            .set_first_id_strategy(FirstIdStrategy::Sequential)
+            .into_sonyflake()
+            .unwrap();
+
+        let timestamp = (now_millis - 1_288_834_974_657) as u64;
+        assert_eq!(sf.next_id().unwrap(), timestamp << 22 | 321 << 12);
+        let id = sf.next_id().unwrap();
+        assert_eq!(id, timestamp << 22 | 321 << 12 | 1);
+
+        let parts = sf.bit_layout().decompose(id);
+        assert_eq!((parts.get_time(), parts.get_sequence(), parts.get_machine_id()), (timestamp, 1, 321));
+        assert_eq!(sf.timestamp_of(id).timestamp_millis(), now_millis);
+
+        assert!(matches!(
+            Settings::snowflake()
+                .set_machine_id_value(1024)
+                .into_sonyflake()
+                .unwrap_err(),
+            FlakeError::MachineIDOutOfRange(1024)
+        ));
+    }
+
+    #[test]
+    fn test_generic_settings() {
+        let now = Utc::now().timestamp_nanos_opt().unwrap();
+        let boxed = Settings::new()
+            .set_machine_id(Box::new(CustomMachineID { counter: 0, id: 77 }))
+            .set_check_machine_id(Box::new(CustomMachineIDChecker))
+            .set_nano_clock(Box::new(FixedClock(now)))
+            .set_first_id_strategy(FirstIdStrategy::Sequential)
+            .into_sonyflake()
+            .unwrap();
+        let generic = Settings::<CustomMachineID, CustomMachineIDChecker>::default()
+            .set_machine_id(CustomMachineID { counter: 0, id: 77 })
+            .set_check_machine_id(CustomMachineIDChecker)
+            .set_nano_clock(Box::new(FixedClock(now)))
+            .set_first_id_strategy(FirstIdStrategy::Sequential)
+            .into_sonyflake()
+            .unwrap();
+        for _ in 0..300 {
+            assert_eq!(boxed.next_id().unwrap(), generic.next_id().unwrap());
+        }
+
+        // the checker defaults to a boxed one when only the machine id source is named
+        let sf = Settings::<CustomMachineID>::default()
+            .set_machine_id(CustomMachineID { counter: 0, id: 78 })
+            .into_infallible_sonyflake()
+            .unwrap();
+        assert_eq!(sf.machine_id(), 78);
     }
 
-    /// `get_sequence` returns sequence
-    pub fn get_sequence(&self) -> u64 {
-        self.sequence
-    }
+    #[cfg(feature = "tokio")]
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_next_id_async() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
 
-    /// `get_machine_id` returns the machine id
-    pub fn get_machine_id(&self) -> u64 {
-        self.machine_id
-    }
-}
+        let sf = Settings::new()
+            .set_machine_id_value(5)
+            .into_sonyflake()
+            .unwrap();
 
-/// `decompose` returns a set of SonyFlake ID parts.
-pub fn decompose(id: u64) -> IDParts {
-    let mask_seq = ((1 << BIT_LEN_SEQUENCE) - 1 as u64) << BIT_LEN_MACHINE_ID;
-    let mask_machine_id = (1 << BIT_LEN_MACHINE_ID) - 1 as u64;
+        // a task which only makes progress while the generator waits without blocking the thread
+        let ticks = Arc::new(AtomicUsize::new(0));
+        let ticker = tokio::spawn({
+            let ticks = ticks.clone();
+            async move {
+                loop {
+                    ticks.fetch_add(1, Ordering::Relaxed);
+                    tokio::task::yield_now().await;
+                }
+            }
+        });
 
-    let msb = id >> 63;
-    let time = id >> (BIT_LEN_SEQUENCE + BIT_LEN_MACHINE_ID);
+        // crosses several 10 msec windows
+        let mut last = 0;
+        for _ in 0..1000 {
+            let id = sf.next_id_async().await.unwrap();
+            assert!(id > last);
+            last = id;
+        }
+        assert!(ticks.load(Ordering::Relaxed) > 0);
+        ticker.abort();
 
-    let seq = (id & mask_seq) >> BIT_LEN_MACHINE_ID;
-    let machine_id = id & mask_machine_id;
-    IDParts {
-        id,
-        msb,
-        time,
-        sequence: seq,
-        machine_id,
+        let sf = sf;
+        assert!(sf.next_id().unwrap() > last);
     }
-}
 
-fn default_start_time() -> DateTime<Utc> {
-    Utc.ymd(2021, 8, 6).and_hms_nano(0, 0, 0, 0)
-}
+    #[cfg(feature = "tokio")]
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_infallible_next_id_async_rebase() {
+        use std::sync::atomic::{AtomicI64, Ordering};
+        use std::sync::Arc;
 
-#[cfg(test)]
-mod tests {
-    use crate::{Error as FlakeError, lower_16_bit_private_ip, to_sonyflake_time, IDParts, Settings, SonyFlake, InfallibleSonyFlake, BIT_LEN_SEQUENCE, MachineID, MachineIDChecker, BIT_LEN_TIME};
-    use chrono::Utc;
-    use std::time::Duration;
-    use std::error::Error;
-    use std::thread::JoinHandle;
-    use std::collections::HashSet;
+        // a lifetime of about 250 days
+        let layout = BitLayout::new(31, 16, 16).unwrap();
+        let start_time = Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap();
+        let start_nanos = start_time.timestamp_nanos_opt().unwrap();
+        let now = Arc::new(AtomicI64::new(start_nanos + 1000 * FLAKE_TIME_UNIT));
+        let sf = Settings::new()
+            .set_start_time(start_time)
+            .set_machine_id_value(5)
+            .set_nano_clock(Box::new(ManualClock(now.clone())))
+            .set_bit_layout(layout)
+            .into_infallible_sonyflake()
+            .unwrap();
+        let mut rebases = sf.subscribe_rebase();
+        assert_eq!(*rebases.borrow_and_update(), start_time);
 
-    #[test]
-    fn test_sonyflake_once() {
-        let now = Utc::now();
-        let mut f = Settings::new().set_start_time(now).into_sonyflake().unwrap();
+        assert_eq!(layout.decompose(sf.next_id_async().await).get_time(), 1000);
+        assert!(!rebases.has_changed().unwrap());
 
-        let sleep_time = 500u64;
-        std::thread::sleep(Duration::from_millis(sleep_time));
-        let id = f.next_id().unwrap();
+        // the time bits overflow: the generator rebases instead of failing
+        let rebased_nanos = start_nanos + (1 << layout.time_bits()) * FLAKE_TIME_UNIT;
+        now.store(rebased_nanos, Ordering::SeqCst);
+        let notified = tokio::spawn({
+            let mut rebases = rebases.clone();
+            async move {
+                rebases.changed().await.unwrap();
+                *rebases.borrow_and_update()
+            }
+        });
+        let parts = layout.decompose(sf.next_id_async().await);
+        assert_eq!((parts.get_time(), parts.get_sequence()), (0, 0));
 
-        let parts = IDParts::decompose(id);
-        assert_eq!(parts.get_msb(), 0);
-        assert_eq!(parts.get_sequence(), 0);
-        assert!(parts.get_time() < sleep_time || parts.get_time() > sleep_time + 1);
-        assert_eq!(parts.machine_id, lower_16_bit_private_ip().unwrap() as u64);
+        let rebased = Utc.timestamp_nanos(rebased_nanos);
+        assert_eq!(notified.await.unwrap(), rebased);
+        assert_eq!(*rebases.borrow_and_update(), rebased);
+        assert_eq!(sf.start_time(), rebased);
+
+        // clones share the channel, and the sync generation path notifies too
+        let clone = sf.clone();
+        now.store(rebased_nanos + (1 << layout.time_bits()) * FLAKE_TIME_UNIT, Ordering::SeqCst);
+        clone.next_id();
+        assert!(rebases.has_changed().unwrap());
+        assert_eq!(*rebases.borrow_and_update(), clone.start_time());
     }
 
     #[test]
-    fn test_infallible_sonyflake_once() {
-        let now = Utc::now();
-        let mut f = Settings::new().set_start_time(now).into_infallible_sonyflake().unwrap();
+    fn test_next_id_with_wait() {
+        // the start of a 10 msec window
+        let now = Utc::now().timestamp_nanos_opt().unwrap() / FLAKE_TIME_UNIT * FLAKE_TIME_UNIT;
+        let sf = Settings::new()
+            .set_machine_id_value(9)
+            .set_nano_clock(Box::new(FixedClock(now)))
+            .set_first_id_strategy(FirstIdStrategy::Sequential)
+            .into_sonyflake()
+            .unwrap();
 
-        let sleep_time = 500u64;
-        std::thread::sleep(Duration::from_millis(sleep_time));
-        let id = f.next_id();
+        let ids = (0..256).map(|_| sf.next_id_with_wait().unwrap()).collect::<Vec<_>>();
+        assert!(ids.iter().all(|(_, wait)| *wait == Duration::ZERO));
 
+        // the 257th id wraps the sequence and waits for the whole next window
+        let (id, wait) = sf.next_id_with_wait().unwrap();
+        assert_eq!(wait, Duration::from_millis(10));
         let parts = IDParts::decompose(id);
-        assert_eq!(parts.get_msb(), 0);
+        assert_eq!(parts.get_time(), IDParts::decompose(ids[255].0).get_time() + 1);
         assert_eq!(parts.get_sequence(), 0);
-        assert!(parts.get_time() < sleep_time || parts.get_time() > sleep_time + 1);
-        assert_eq!(parts.machine_id, lower_16_bit_private_ip().unwrap() as u64);
     }
 
     #[test]
-    fn test_sonyflake_for_10_sec() {
-        let now = Utc::now();
-        let start_time = to_sonyflake_time(now);
-        let mut f = SonyFlake::new(Settings::new().set_start_time(now)).unwrap();
-
-        let mut num_id: u64 = 0;
-        let mut last_id: u64 = 0;
-        let mut max_seq: u64 = 0;
+    fn test_next_id_waits_without_the_lock() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
 
-        let machine_id = lower_16_bit_private_ip().unwrap() as u64;
+        // windows of half a second, so the wait for the next one dwarfs generating an id
+        let time_unit = 500_000_000;
+        let now = Utc::now().timestamp_nanos_opt().unwrap() / time_unit * time_unit;
+        // 2^39 units of half a second overflow nanoseconds
+        let layout = BitLayout::new(31, 16, 16).unwrap();
+        let sf = Settings::new()
+            .set_machine_id_value(9)
+            .set_nano_clock(Box::new(FixedClock(now)))
+            .set_bit_layout(layout)
+            .set_time_unit(Duration::from_nanos(time_unit as u64))
+            .set_first_id_strategy(FirstIdStrategy::Sequential)
+            .into_sonyflake()
+            .unwrap();
+        for _ in 0..1 << 16 {
+            sf.next_id().unwrap();
+        }
 
-        let initial = to_sonyflake_time(Utc::now());
-        let mut current = initial.clone();
+        // the next id waits for the next window
+        let done = Arc::new(AtomicBool::new(false));
+        let waiter = {
+            let (sf, done) = (sf.clone(), done.clone());
+            std::thread::spawn(move || {
+                let (id, wait) = sf.next_id_with_wait().unwrap();
+                done.store(true, Ordering::SeqCst);
+                (id, wait)
+            })
+        };
+        while !sf.is_borrowing_future() {
+            std::thread::yield_now();
+        }
 
-        while current - initial < 1000 {
-            let id = f.next_id().unwrap();
+        // meanwhile, other threads are not blocked on the lock
+        let other = sf.next_id().unwrap();
+        assert!(!done.load(Ordering::SeqCst));
+        let (id, wait) = waiter.join().unwrap();
+        assert_eq!(wait, Duration::from_millis(500));
+        assert_eq!(layout.decompose(id).get_sequence(), 0);
+        assert_eq!(layout.decompose(other).get_sequence(), 1);
+        assert_eq!(layout.decompose(other).get_time(), layout.decompose(id).get_time());
+    }
 
-            let parts = IDParts::decompose(id);
-            num_id += 1;
+    #[test]
+    fn test_wait_strategy_spin() {
+        use std::sync::atomic::{AtomicI64, Ordering};
+        use std::sync::Arc;
 
-            assert!(id > last_id);
-            last_id = id;
+        /// a clock moving on by a microsecond at every read
+        struct TickingClock(Arc<AtomicI64>);
 
-            current = to_sonyflake_time(Utc::now());
+        impl crate::NanoClock for TickingClock {
+            fn now_nanos(&self) -> i64 {
+                self.0.fetch_add(1_000, Ordering::Relaxed)
+            }
+        }
 
-            assert_eq!(parts.get_msb(), 0);
-            let overtime = start_time + (parts.get_time() as i64) - current;
-            assert!(overtime <= 0);
+        // the start of a 10 msec window
+        let now = Utc::now().timestamp_nanos_opt().unwrap() / FLAKE_TIME_UNIT * FLAKE_TIME_UNIT;
+        let clock = Arc::new(AtomicI64::new(now));
+        let sf = Settings::new()
+            .set_machine_id_value(9)
+            .set_nano_clock(Box::new(TickingClock(clock.clone())))
+            .set_first_id_strategy(FirstIdStrategy::Sequential)
+            .set_wait_strategy(WaitStrategy::Spin)
+            .into_sonyflake()
+            .unwrap();
 
-            if max_seq < parts.get_sequence() {
-                max_seq = parts.get_sequence();
-            }
+        let ids = (0..256).map(|_| sf.next_id().unwrap()).collect::<Vec<_>>();
+        assert!(clock.load(Ordering::Relaxed) < now + FLAKE_TIME_UNIT);
 
-            assert_eq!(parts.get_machine_id(), machine_id);
-        }
+        // the 257th id spins on the clock until the next window starts
+        let (id, wait) = sf.next_id_with_wait().unwrap();
+        assert!(wait > Duration::ZERO);
+        assert!(clock.load(Ordering::Relaxed) >= now + FLAKE_TIME_UNIT);
+        let parts = IDParts::decompose(id);
+        assert_eq!(parts.get_time(), IDParts::decompose(ids[255]).get_time() + 1);
+        assert_eq!(parts.get_sequence(), 0);
+    }
 
-        assert_eq!(max_seq, (1 << BIT_LEN_SEQUENCE) - 1);
-        println!("number of id: {}", num_id);
+    #[test]
+    fn test_current_elapsed_and_sequence() {
+        let sf = Settings::new()
+            .set_machine_id_value(1)
+            .into_sonyflake()
+            .unwrap();
+        let parts = IDParts::decompose(sf.next_id().unwrap());
+        assert_eq!(sf.current_elapsed(), parts.get_time() as i64);
+        assert_eq!(sf.current_sequence(), parts.get_sequence() as u16);
+        assert_eq!((sf.current_elapsed(), sf.current_sequence()), sf.state());
     }
 
     #[test]
-    fn test_infallible_sonyflake_for_10_sec() {
-        let now = Utc::now();
-        let start_time = to_sonyflake_time(now);
-        let mut f = InfallibleSonyFlake::new(Settings::new().set_start_time(now)).unwrap();
+    fn test_on_overflow_approaching() {
+        use std::sync::{Arc, Mutex};
 
-        let mut num_id: u64 = 0;
-        let mut last_id: u64 = 0;
-        let mut max_seq: u64 = 0;
+        // 2^31 time units of 10 msec, about 248 days
+        let layout = crate::BitLayout::new(31, 16, 16).unwrap();
+        let start_time = Utc::now() - chrono::Duration::days(240);
+        let fired = Arc::new(Mutex::new(Vec::new()));
+        let sf = |threshold: f64| {
+            let fired = fired.clone();
+            Settings::new()
+                .set_start_time(start_time)
+                .set_machine_id_value(1)
+                .set_bit_layout(layout)
+                .set_overflow_threshold(threshold)
+                .set_on_overflow_approaching(Box::new(move |elapsed_time, limit| {
+                    fired.lock().unwrap().push((elapsed_time, limit))
+                }))
+                .into_sonyflake()
+                .unwrap()
+        };
 
-        let machine_id = lower_16_bit_private_ip().unwrap() as u64;
+        let far = sf(0.99);
+        far.next_id().unwrap();
+        assert!(fired.lock().unwrap().is_empty());
 
-        let initial = to_sonyflake_time(Utc::now());
-        let mut current = initial.clone();
+        let near = sf(0.95);
+        let id = near.next_id().unwrap();
+        near.next_id().unwrap();
+        // only once
+        assert_eq!(*fired.lock().unwrap(), vec![(layout.decompose(id).get_time() as i64, 1 << 31)]);
+    }
 
-        while current - initial < 1000 {
-            let id = f.next_id();
+    #[test]
+    fn test_owns() {
+        let sf = Settings::new()
+            .set_machine_id_value(1)
+            .into_sonyflake()
+            .unwrap();
+        let other = Settings::new()
+            .set_machine_id_value(3)
+            .into_sonyflake()
+            .unwrap();
+        let id = sf.next_id().unwrap();
+        assert!(sf.owns(id));
+        assert!(!other.owns(id));
+        let other_id = other.next_id().unwrap();
+        assert!(other.owns(other_id));
 
-            let parts = IDParts::decompose(id);
-            num_id += 1;
+        // the borrowed bits of the machine id carry the sequence, not the machine id
+        let borrowing = Settings::new()
+            .set_machine_id_value(1)
+            .borrow_machine_bits_for_sequence(4)
+            .into_sonyflake()
+            .unwrap();
+        let ids = (0..1000).map(|_| borrowing.next_id().unwrap()).collect::<Vec<_>>();
+        assert!(ids.iter().all(|id| borrowing.owns(*id)));
 
-            assert!(id > last_id);
-            last_id = id;
+        let reversed = Settings::new()
+            .set_machine_id_value(1)
+            .enable_bit_reversal()
+            .into_sonyflake()
+            .unwrap();
+        let reversed_id = reversed.next_id().unwrap();
+        assert!(reversed.owns(reversed_id));
+    }
 
-            current = to_sonyflake_time(Utc::now());
+    #[test]
+    fn test_id_parts_serde() {
+        let parts = IDParts::decompose(compose(1234, 5, 6));
 
-            assert_eq!(parts.get_msb(), 0);
-            let overtime = start_time + (parts.get_time() as i64) - current;
-            assert!(overtime <= 0);
+        let json = serde_json::to_string(&parts).unwrap();
+        assert!(json.contains("\"machine_id\":6"));
+        assert_eq!(serde_json::from_str::<IDParts>(&json).unwrap(), parts);
 
-            if max_seq < parts.get_sequence() {
-                max_seq = parts.get_sequence();
-            }
+        // only the id
+        let bytes = bincode::serialize(&parts).unwrap();
+        assert_eq!(bytes.len(), 8);
+        assert_eq!(bincode::deserialize::<IDParts>(&bytes).unwrap(), parts);
+    }
 
-            assert_eq!(parts.get_machine_id(), machine_id);
-        }
+    #[test]
+    fn test_next_id_shared() {
+        use std::sync::Arc;
 
-        assert_eq!(max_seq, (1 << BIT_LEN_SEQUENCE) - 1);
-        println!("number of id: {}", num_id);
+        let sf = Arc::new(
+            Settings::new()
+                .set_machine_id_value(1)
+                .into_sonyflake()
+                .unwrap(),
+        );
+        let infallible = Arc::new(
+            Settings::new()
+                .set_machine_id_value(1)
+                .into_infallible_sonyflake()
+                .unwrap(),
+        );
+        let threads = (0..4)
+            .map(|_| {
+                let sf = sf.clone();
+                let infallible = infallible.clone();
+                std::thread::spawn(move || {
+                    (0..100)
+                        .map(|_| (sf.next_id().unwrap(), infallible.next_id()))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+        let ids = threads.into_iter().flat_map(|t| t.join().unwrap()).collect::<Vec<_>>();
+        assert_eq!(ids.iter().map(|(id, _)| *id).collect::<HashSet<_>>().len(), 400);
+        assert_eq!(ids.iter().map(|(_, id)| *id).collect::<HashSet<_>>().len(), 400);
     }
 
-    struct CustomMachineID {
-        counter: u64,
-        id: u16,
+    #[test]
+    fn test_infallible_clones_share_start_time() {
+        let sf = Settings::new()
+            .set_machine_id_value(1)
+            .into_infallible_sonyflake()
+            .unwrap();
+        let clone = sf.clone();
+        let before = clone.start_time();
+        sf.inner.lock().elapsed_time = 1 << BIT_LEN_TIME;
+        sf.next_id();
+        assert_ne!(sf.start_time(), before);
+        assert_eq!(clone.start_time(), sf.start_time());
     }
 
-    impl MachineID for CustomMachineID {
-        fn machine_id(&mut self) -> Result<u16, Box<dyn Error + Send + Sync + 'static>> {
-            self.counter += 1;
-            if self.counter % 2 != 0 {
-                Ok(self.id)
-            } else {
-                Err(Box::new("NaN".parse::<u32>().unwrap_err()))
-            }
-        }
-    }
+    #[test]
+    fn test_remaining_lifetime() {
+        assert_eq!(max_lifetime().as_secs() / (365 * 24 * 60 * 60), 174);
 
-    struct CustomMachineIDChecker;
+        let start_time = Utc::now() - chrono::Duration::days(1);
+        let sf = Settings::new()
+            .set_start_time(start_time)
+            .set_machine_id_value(1)
+            .into_sonyflake()
+            .unwrap();
+        let used = max_lifetime() - sf.remaining_lifetime();
+        assert!(used > Duration::from_secs(24 * 60 * 60 - 1) && used < Duration::from_secs(24 * 60 * 60 + 60));
 
-    impl MachineIDChecker for CustomMachineIDChecker {
-        fn check_machine_id(&self, id: u16) -> bool {
-            if id % 2 != 0 {
-                true
-            } else {
-                false
-            }
-        }
+        // 2^31 units of 1 sec, about 68 years
+        let sf = Settings::new()
+            .set_start_time(start_time)
+            .set_machine_id_value(1)
+            .set_bit_layout(crate::BitLayout::new(31, 16, 16).unwrap())
+            .set_time_unit(Duration::from_secs(1))
+            .into_sonyflake()
+            .unwrap();
+        let used = (1 << 31) - sf.remaining_lifetime().as_secs();
+        assert!(used > 24 * 60 * 60 - 2 && used < 24 * 60 * 60 + 60);
+
+        sf.inner.lock().elapsed_time = 1 << 31;
+        assert_eq!(sf.remaining_lifetime(), Duration::ZERO);
     }
 
     #[test]
-    fn test_sonyflake_custom_machine_id_and_checker() {
-        let mut sf = Settings::new()
-            .set_machine_id(Box::new(CustomMachineID { counter: 0, id: 1 }))
-            .set_check_machine_id(Box::new(CustomMachineIDChecker {}))
-            .into_sonyflake().unwrap();
-        let id = sf.next_id().unwrap();
-        let parts = IDParts::decompose(id);
-        assert_eq!(parts.get_machine_id(), 1);
+    fn test_set_machine_id_value() {
+        let sf = Settings::new().set_machine_id_value(42).into_sonyflake().unwrap();
+        assert_eq!(sf.machine_id(), 42);
+        assert_eq!(IDParts::decompose(sf.next_id().unwrap()).get_machine_id(), 42);
 
         let err = Settings::new()
-            .set_machine_id(Box::new(CustomMachineID { counter: 0, id: 2 }))
-            .set_check_machine_id(Box::new(CustomMachineIDChecker {}))
-            .into_sonyflake().unwrap_err();
+            .set_machine_id_value(42)
+            .set_check_machine_id(Box::new(CustomMachineIDChecker))
+            .into_sonyflake()
+            .unwrap_err();
+        assert!(matches!(err, FlakeError::InvalidMachineID(42)));
+    }
 
-        assert_eq!(format!("{}", err), FlakeError::InvalidMachineID(2).to_string());
+    #[test]
+    fn test_clock_moved_backwards() {
+        use std::sync::atomic::{AtomicI64, Ordering};
+        use std::sync::Arc;
+
+        let now = Utc::now().timestamp_nanos_opt().unwrap() / FLAKE_TIME_UNIT * FLAKE_TIME_UNIT;
+        let clock = Arc::new(AtomicI64::new(now));
+        let sf = |tolerance: Duration| {
+            Settings::new()
+                .set_start_time(Utc::now() - chrono::Duration::days(1))
+                .set_machine_id_value(1)
+                .set_nano_clock(Box::new(ManualClock(clock.clone())))
+                .set_clock_regression_tolerance(tolerance)
+                .into_sonyflake()
+                .unwrap()
+        };
+        let strict = sf(Duration::from_secs(1));
+        let lenient = sf(Duration::from_secs(5));
+        strict.next_id().unwrap();
+        lenient.next_id().unwrap();
+
+        // within the tolerance
+        clock.store(now - 500_000_000, Ordering::Relaxed);
+        strict.next_id().unwrap();
+
+        clock.store(now - 2_000_000_000, Ordering::Relaxed);
+        let err = strict.next_id().unwrap_err();
+        assert!(matches!(err, FlakeError::ClockMovedBackwards(by) if by == Duration::from_millis(1990)));
+        assert!(matches!(
+            strict.next_id_checked().unwrap_err(),
+            Backpressure::Saturated { retry_after } if retry_after == Duration::from_millis(1990)
+        ));
+        lenient.next_id().unwrap();
     }
 
     #[test]
-    fn test_infallible_sonyflake_custom_machine_id_and_checker() {
-        let mut sf = Settings::new()
-            .set_machine_id(Box::new(CustomMachineID { counter: 0, id: 1 }))
-            .set_check_machine_id(Box::new(CustomMachineIDChecker {}))
-            .into_infallible_sonyflake().unwrap();
-        let id = sf.next_id();
-        let parts = IDParts::decompose(id);
-        assert_eq!(parts.get_machine_id(), 1);
+    fn test_machine_id_formatting() {
+        let parts = IDParts::decompose(compose(100, 5, (12 << 8) + 34));
+        assert_eq!(parts.machine_id_hex(), "0c22");
+        assert_eq!(parts.machine_id_octets(), (12, 34));
 
-        let err = Settings::new()
-            .set_machine_id(Box::new(CustomMachineID { counter: 0, id: 2 }))
-            .set_check_machine_id(Box::new(CustomMachineIDChecker {}))
-            .into_infallible_sonyflake().unwrap_err();
+        let parts = IDParts::decompose(compose(100, 5, 7));
+        assert_eq!(parts.machine_id_hex(), "0007");
+        assert_eq!(parts.machine_id_octets(), (0, 7));
+        assert_eq!(IDParts::decompose(compose(0, 0, 65535)).machine_id_hex(), "ffff");
+    }
 
-        assert_eq!(format!("{}", err), FlakeError::InvalidMachineID(2).to_string());
+    #[test]
+    fn test_cmp_by_time() {
+        use core::cmp::Ordering;
+
+        let a = IDParts::decompose(compose(100, 5, 9));
+        assert_eq!(a.cmp_by_time(&IDParts::decompose(compose(100, 5, 1))), Ordering::Equal);
+        assert_eq!(a.cmp_by_time(&IDParts::decompose(compose(100, 6, 1))), Ordering::Less);
+        assert_eq!(a.cmp_by_time(&IDParts::decompose(compose(99, 255, 65535))), Ordering::Greater);
+
+        // merging the ids of two machines by time
+        let mut ids = [compose(2, 0, 7), compose(1, 3, 9), compose(1, 3, 2), compose(1, 0, 9)]
+            .iter()
+            .map(|id| IDParts::decompose(*id))
+            .collect::<Vec<_>>();
+        ids.sort_by(|a, b| a.cmp_by_time(b));
+        let times = ids.iter().map(|parts| (parts.get_time(), parts.get_sequence())).collect::<Vec<_>>();
+        assert_eq!(times, [(1, 0), (1, 3), (1, 3), (2, 0)]);
+        // the sort is stable, so equal times keep their order
+        assert_eq!(ids[1].get_machine_id(), 9);
     }
 
     #[test]
-    #[should_panic]
-    fn test_fallible() {
-        let now = Utc::now();
-        let mut sf = Settings::new().set_start_time(now).into_sonyflake().unwrap();
-        sf.inner.lock().elapsed_time = 1 << BIT_LEN_TIME;
-        let _ = sf.next_id().unwrap();
+    fn test_start_time_out_of_range() {
+        for year in [1600, 3000] {
+            let start_time = Utc.with_ymd_and_hms(year, 1, 1, 0, 0, 0).unwrap();
+            let err = Settings::new()
+                .set_start_time(start_time)
+                .set_machine_id_value(1)
+                .into_sonyflake()
+                .unwrap_err();
+            assert!(matches!(err, FlakeError::StartTimeOutOfRange(time) if time == start_time));
+        }
     }
 
     #[test]
-    fn test_infallible() {
-        let now = Utc::now();
-        let mut sf = Settings::new().set_start_time(now).into_infallible_sonyflake().unwrap();
-        sf.inner.lock().elapsed_time = (1 << BIT_LEN_TIME) - 2;
-        let _ = sf.next_id();
-        let _ = sf.next_id();
-        let _ = sf.next_id();
-        let _ = sf.next_id();
+    fn test_hash_to_machine_id() {
+        // pinned, as machine ids must not change across versions
+        assert_eq!(hash_to_machine_id(b"web-1"), 36610);
+        assert_eq!(hash_to_machine_id(b""), 61457);
+        assert_eq!(hash_to_machine_id_with_seed(b"web-1", 42), 10844);
+        assert_ne!(hash_to_machine_id(b"web-1"), hash_to_machine_id(b"web-2"));
+        assert_eq!(
+            hash_to_machine_id_with_seed(b"web-1", 42),
+            crate::HostnameMachineID::with_seed(42).machine_id_of("web-1")
+        );
     }
 
     #[test]
-    fn test_sonyflake_concurrency() {
-        let now = Utc::now();
-        let sf = Settings::new().set_start_time(now).into_sonyflake().unwrap();
+    fn test_validate() {
+        let st = Settings::new()
+            .set_machine_id_value(1)
+            .set_start_time(Utc::now() + chrono::Duration::days(1))
+            .set_time_unit(Duration::ZERO)
+            .reserve_tag_bits(8)
+            .set_id_prefix("ord_");
+        let errors = st.validate();
+        assert_eq!(errors.len(), 4);
+        assert!(matches!(errors[0], FlakeError::InvalidTimeUnit(_)));
+        assert!(matches!(errors[1], FlakeError::StartTimeAheadOfCurrentTime(_)));
+        assert!(matches!(errors[2], FlakeError::InvalidTagBits(8)));
+        assert!(matches!(&errors[3], FlakeError::InvalidPrefix(prefix) if prefix == "ord_"));
 
-        let (tx, rx) = std::sync::mpsc::channel::<u64>();
+        // the machine id source is not queried: its first call still succeeds
+        let st = Settings::new()
+            .set_machine_id_value(1)
+            .set_sequence_start(256);
+        assert!(matches!(st.validate()[..], [FlakeError::SequenceOutOfRange(256)]));
+        let st = st.set_sequence_start(255);
+        assert!(st.validate().is_empty());
+        assert_eq!(st.into_sonyflake().unwrap().machine_id(), 1);
+    }
 
-        let mut threads = Vec::<JoinHandle<()>>::with_capacity(1000);
-        for _ in 0..100 {
-            let mut thread_sf = sf.clone();
-            let thread_tx = tx.clone();
-            threads.push(std::thread::spawn(move || {
-                for _ in 0..1000 {
-                    thread_tx.send(thread_sf.next_id().unwrap()).unwrap();
-                }
-            }));
-        }
+    #[test]
+    fn test_try_from_settings() {
+        use std::convert::TryFrom;
 
-        let mut ids = HashSet::new();
-        for _ in 0..100000 {
-            let id = rx.recv().unwrap();
-            assert!(!ids.contains(&id), "duplicate id: {}", id);
-            ids.insert(id);
-        }
+        let settings = |id| Settings::new().set_machine_id_value(id);
+        assert_eq!(SonyFlake::try_from(settings(3)).unwrap().machine_id(), 3);
+        assert_eq!(InfallibleSonyFlake::try_from(settings(5)).unwrap().machine_id(), 5);
 
-        for t in threads {
-            t.join().expect("thread panicked");
-        }
+        let future = Utc::now() + chrono::Duration::days(1);
+        assert!(matches!(
+            SonyFlake::try_from(settings(3).set_start_time(future)).unwrap_err(),
+            FlakeError::StartTimeAheadOfCurrentTime(_)
+        ));
     }
 
     #[test]
-    fn test_infallible_sonyflake_concurrency() {
-        let now = Utc::now();
-        let sf = Settings::new().set_start_time(now).into_infallible_sonyflake().unwrap();
+    fn test_machine_id_obfuscation() {
+        let key = 0x5eed_1234_abcd_0042;
+        let sf = Settings::new()
+            .set_machine_id_value(1)
+            .set_machine_id_obfuscation(key)
+            .into_sonyflake()
+            .unwrap();
 
-        let (tx, rx) = std::sync::mpsc::channel::<u64>();
+        let ids = sf.next_ids(1000).unwrap();
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+        assert!(ids.iter().all(|id| decompose_obfuscated(*id, key).get_machine_id() == 1 && sf.owns(*id)));
+        // the machine id bits vary from id to id
+        let machine_ids = ids.iter().map(|id| IDParts::decompose(*id).get_machine_id()).collect::<HashSet<_>>();
+        assert!(machine_ids.len() > 100);
+        assert!(ids.iter().any(|id| decompose_obfuscated(*id, key + 1).get_machine_id() != 1));
 
-        let mut threads = Vec::<JoinHandle<()>>::with_capacity(1000);
-        for _ in 0..100 {
-            let mut thread_sf = sf.clone();
-            let thread_tx = tx.clone();
-            threads.push(std::thread::spawn(move || {
-                for _ in 0..1000 {
-                    thread_tx.send(thread_sf.next_id()).unwrap();
-                }
-            }));
-        }
+        let parts = decompose_obfuscated(ids[0], key);
+        assert_eq!(parts.get_time(), IDParts::decompose(ids[0]).get_time());
+        assert_eq!(parts.get_sequence(), IDParts::decompose(ids[0]).get_sequence());
 
-        let mut ids = HashSet::new();
-        for _ in 0..100000 {
-            let id = rx.recv().unwrap();
-            assert!(!ids.contains(&id), "duplicate id: {}", id);
-            ids.insert(id);
+        let block = sf.reserve_block(10).unwrap().collect::<Vec<_>>();
+        assert!(block.iter().all(|id| decompose_obfuscated(*id, key).get_machine_id() == 1));
+
+        // another machine with the same key never collides
+        let other = Settings::new()
+            .set_machine_id_value(3)
+            .set_machine_id_obfuscation(key)
+            .into_infallible_sonyflake()
+            .unwrap();
+        let other_ids = other.next_ids(1000);
+        assert!(other_ids.iter().all(|id| decompose_obfuscated(*id, key).get_machine_id() == 3));
+        let all = ids.iter().chain(&other_ids).chain(&block).collect::<HashSet<_>>();
+        assert_eq!(all.len(), 2010);
+    }
+
+    #[test]
+    fn test_assert_single_machine() {
+        let sf = Settings::new()
+            .set_machine_id_value(1)
+            .into_sonyflake()
+            .unwrap();
+        let mut ids = (0..10).map(|_| sf.next_id().unwrap()).collect::<Vec<_>>();
+        assert_eq!(assert_single_machine(&ids).unwrap(), 1);
+
+        let other = Settings::new()
+            .set_machine_id_value(3)
+            .into_sonyflake()
+            .unwrap();
+        ids.push(other.next_id().unwrap());
+        match assert_single_machine(&ids).unwrap_err() {
+            FlakeError::MultipleMachineIds(machine_ids) => {
+                assert_eq!(machine_ids.into_iter().collect::<Vec<_>>(), vec![1, 3]);
+            }
+            e => panic!("unexpected error: {}", e),
         }
+    }
 
-        for t in threads {
-            t.join().expect("thread panicked");
+    #[cfg(feature = "coarse-clock")]
+    #[test]
+    fn test_coarse_clock_monotonic() {
+        let sf = Settings::new()
+            .set_machine_id_value(1)
+            .into_sonyflake()
+            .unwrap();
+
+        let mut last_id = 0;
+        for _ in 0..100_000 {
+            let id = sf.next_id().unwrap();
+            assert!(id > last_id);
+            last_id = id;
         }
     }
 
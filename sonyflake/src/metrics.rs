@@ -0,0 +1,133 @@
+use std::fmt::{Debug, Formatter};
+use std::time::Duration;
+
+/// `Metrics` receives the generation events of a generator, e.g. to count them with Prometheus or statsd,
+/// see [`Settings::set_metrics`]. Every method does nothing by default, so implement only those needed.
+///
+/// The methods are called while the state of the generator is locked, so they should return quickly,
+/// e.g. by incrementing an atomic counter.
+///
+/// ```rust
+/// use infallible_sonyflake::{Metrics, Settings};
+/// use std::sync::atomic::{AtomicU64, Ordering};
+/// use std::sync::Arc;
+///
+/// struct IdCounter(Arc<AtomicU64>);
+///
+/// impl Metrics for IdCounter {
+///     fn on_id_generated(&self) {
+///         self.0.fetch_add(1, Ordering::Relaxed);
+///     }
+/// }
+///
+/// let generated = Arc::new(AtomicU64::new(0));
+/// let sf = Settings::new()
+///     .set_machine_id_value(1)
+///     .set_metrics(Box::new(IdCounter(generated.clone())))
+///     .into_sonyflake()
+///     .unwrap();
+/// sf.next_id().unwrap();
+/// assert_eq!(generated.load(Ordering::Relaxed), 1);
+/// ```
+///
+/// [`Settings::set_metrics`]: struct.Settings.html#method.set_metrics
+pub trait Metrics: Send + Sync {
+    /// `on_id_generated` is called for every id generated.
+    fn on_id_generated(&self) {}
+
+    /// `on_sleep` is called with how long the generator waits for the next time window
+    /// when the sequence of the current one is used up.
+    fn on_sleep(&self, _wait: Duration) {}
+
+    /// `on_overflow` is called when the ids run out: `SonyFlake` returns `Error::TimeOverflow`
+    /// or `Error::CounterExhausted`, and `InfallibleSonyFlake` moves its start time to the current time.
+    fn on_overflow(&self) {}
+}
+
+impl Debug for dyn Metrics {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Metrics")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Metrics;
+    use crate::{Error, Settings};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[derive(Default)]
+    struct Counters {
+        generated: AtomicU64,
+        sleeps: AtomicU64,
+        slept_nanos: AtomicU64,
+        overflows: AtomicU64,
+    }
+
+    struct CountingMetrics(Arc<Counters>);
+
+    impl Metrics for CountingMetrics {
+        fn on_id_generated(&self) {
+            self.0.generated.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn on_sleep(&self, wait: Duration) {
+            self.0.sleeps.fetch_add(1, Ordering::Relaxed);
+            self.0.slept_nanos.fetch_add(wait.as_nanos() as u64, Ordering::Relaxed);
+        }
+
+        fn on_overflow(&self) {
+            self.0.overflows.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_metrics() {
+        let counters = Arc::new(Counters::default());
+        let sf = Settings::new()
+            .set_machine_id_value(1)
+            .set_metrics(Box::new(CountingMetrics(counters.clone())))
+            .set_clock_regression_tolerance(Duration::MAX)
+            .into_sonyflake()
+            .unwrap();
+
+        // more ids than fit a window, so the generator sleeps at least once
+        sf.next_ids(1000).unwrap();
+        assert_eq!(counters.generated.load(Ordering::Relaxed), 1000);
+        assert!(counters.sleeps.load(Ordering::Relaxed) >= 1);
+        assert!(counters.slept_nanos.load(Ordering::Relaxed) > 0);
+        assert_eq!(counters.overflows.load(Ordering::Relaxed), 0);
+
+        let sleeps = counters.sleeps.load(Ordering::Relaxed);
+        sf.restore_state((1 << 39) - 1, 255).unwrap();
+        assert!(matches!(sf.next_id().unwrap_err(), Error::TimeOverflow));
+        assert_eq!(counters.overflows.load(Ordering::Relaxed), 1);
+        assert_eq!(counters.generated.load(Ordering::Relaxed), 1000);
+        assert_eq!(counters.sleeps.load(Ordering::Relaxed), sleeps);
+    }
+
+    #[test]
+    fn test_metrics_infallible() {
+        let counters = Arc::new(Counters::default());
+        let sf = Settings::new()
+            .set_machine_id_value(1)
+            .set_metrics(Box::new(CountingMetrics(counters.clone())))
+            .into_infallible_sonyflake()
+            .unwrap();
+
+        sf.next_ids(1000);
+        assert_eq!(counters.generated.load(Ordering::Relaxed), 1000);
+        assert!(counters.sleeps.load(Ordering::Relaxed) >= 1);
+
+        {
+            let mut inner = sf.inner.lock();
+            inner.elapsed_time = (1 << 39) - 1;
+            inner.sequence = 255;
+        }
+        sf.next_id();
+        assert_eq!(counters.overflows.load(Ordering::Relaxed), 1);
+        assert_eq!(counters.generated.load(Ordering::Relaxed), 1001);
+    }
+}
@@ -0,0 +1,185 @@
+use crate::{check_borrowed_bits, BitLayout, Error, MachineID, MachineIDChecker, Settings, ThreadSlots, UnitClock};
+use std::sync::Arc;
+
+/// `ThreadPartitionedSonyFlake` is a distributed unique ID generator which splits the sequence space
+/// into disjoint slices, one per calling thread, so threads generate ids without sharing a lock.
+///
+/// The first time a thread calls `next_id`, it registers and is assigned the next free slice.
+/// Each thread then reads the clock and advances its own sequence independently; ids stay unique
+/// because no two threads ever use the same sequence value within a time window.
+///
+/// The price is per-thread throughput: with `partitions` slices, each thread can generate
+/// `2^8 / partitions` IDs per 10 msec. Slices are never given back, so a thread which exits keeps
+/// its slice for the lifetime of the generator. Clones share the same set of slices, and the per-thread
+/// state of the generator is dropped with its last clone.
+#[derive(Debug, Clone)]
+pub struct ThreadPartitionedSonyFlake {
+    start_time: i64,
+    clock: Arc<UnitClock>,
+    layout: BitLayout,
+    machine_id: u16,
+    partitions: u16,
+    slots: ThreadSlots,
+}
+
+impl ThreadPartitionedSonyFlake {
+    /// Create a new `ThreadPartitionedSonyFlake` which splits the sequence into `partitions` slices.
+    /// `partitions` must be a power of two no greater than `2^8`, or `2^n` with `n` sequence bits
+    /// set by `Settings::set_bit_layout`.
+    pub fn new<M: MachineID, C: MachineIDChecker>(mut st: Settings<M, C>, partitions: u16) -> Result<Self, Error> {
+        let layout = st.bit_layout;
+        if !partitions.is_power_of_two() || partitions as u32 > 1 << layout.sequence {
+            return Err(Error::InvalidPartitionCount(partitions));
+        }
+
+        let time_unit = st.get_time_unit()?;
+        let start_time = st.get_start_time()?;
+        let clock = Arc::new(UnitClock::new(st.nano_clock.take(), time_unit));
+
+        let machine_id = st.get_and_check_machine_id()?;
+        check_borrowed_bits(layout, 0, machine_id)?;
+
+        Ok(Self {
+            start_time,
+            clock,
+            layout,
+            machine_id,
+            partitions,
+            slots: ThreadSlots::new(partitions),
+        })
+    }
+
+    /// Generate the next unique id from the calling thread's slice of the sequence.
+    /// Returns an error if all slices are taken by other threads, or if the time overflows.
+    pub fn next_id(&self) -> Result<u64, Error> {
+        let slice_bits = self.layout.sequence - self.partitions.trailing_zeros() as i64;
+        let (index, elapsed_time, sequence) =
+            self.slots.next(&self.clock, self.start_time, slice_bits, self.layout.time)?;
+        Ok(self.layout.compose(elapsed_time, (index << slice_bits) | sequence, self.machine_id))
+    }
+
+    /// Returns the number of threads which have been assigned a slice so far.
+    pub fn registered_threads(&self) -> u16 {
+        self.slots.registered()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ThreadPartitionedSonyFlake;
+    use crate::{Error, IDParts, NanoClock, Settings};
+    use chrono::Utc;
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{mpsc, Arc};
+    use std::time::Duration;
+
+    #[test]
+    fn test_thread_partitioned_concurrency() {
+        let sf = ThreadPartitionedSonyFlake::new(
+            Settings::new().set_machine_id_value(7),
+            8,
+        )
+        .unwrap();
+
+        let threads = (0..8)
+            .map(|_| {
+                let sf = sf.clone();
+                std::thread::spawn(move || {
+                    let mut ids = Vec::with_capacity(10000);
+                    for _ in 0..10000 {
+                        let id = sf.next_id().unwrap();
+                        if let Some(last) = ids.last() {
+                            assert!(id > *last);
+                        }
+                        ids.push(id);
+                    }
+                    ids
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut ids = HashSet::new();
+        for t in threads {
+            for id in t.join().expect("thread panicked") {
+                assert_eq!(IDParts::decompose(id).get_machine_id(), 7);
+                assert!(ids.insert(id), "duplicate id: {}", id);
+            }
+        }
+        assert_eq!(ids.len(), 80000);
+        assert_eq!(sf.registered_threads(), 8);
+    }
+
+    /// A clock which blocks the thread named "blocked" until released.
+    struct GateClock {
+        entered: Arc<AtomicBool>,
+        released: Arc<AtomicBool>,
+    }
+
+    impl NanoClock for GateClock {
+        fn now_nanos(&self) -> i64 {
+            if std::thread::current().name() == Some("blocked") {
+                self.entered.store(true, Ordering::SeqCst);
+                while !self.released.load(Ordering::SeqCst) {
+                    std::thread::yield_now();
+                }
+            }
+            Utc::now().timestamp_nanos_opt().unwrap()
+        }
+    }
+
+    #[test]
+    fn test_thread_partitioned_no_shared_lock() {
+        let entered = Arc::new(AtomicBool::new(false));
+        let released = Arc::new(AtomicBool::new(false));
+        let sf = ThreadPartitionedSonyFlake::new(
+            Settings::new().set_machine_id_value(7).set_nano_clock(Box::new(GateClock {
+                entered: entered.clone(),
+                released: released.clone(),
+            })),
+            2,
+        )
+        .unwrap();
+
+        // one thread stops in the middle of generating an id
+        let blocked = {
+            let sf = sf.clone();
+            std::thread::Builder::new()
+                .name("blocked".into())
+                .spawn(move || sf.next_id().unwrap())
+                .unwrap()
+        };
+        while !entered.load(Ordering::SeqCst) {
+            std::thread::yield_now();
+        }
+
+        // another thread does not wait for it, as no lock is shared between them
+        let (tx, rx) = mpsc::channel();
+        let other = sf.clone();
+        std::thread::spawn(move || tx.send((0..1000).map(|_| other.next_id().unwrap()).collect::<Vec<_>>()));
+        let ids = rx.recv_timeout(Duration::from_secs(10)).expect("blocked by another thread");
+        assert_eq!(ids.len(), 1000);
+
+        released.store(true, Ordering::SeqCst);
+        assert!(!ids.contains(&blocked.join().unwrap()));
+    }
+
+    #[test]
+    fn test_thread_partitioned_exhausted() {
+        let sf = ThreadPartitionedSonyFlake::new(
+            Settings::new().set_machine_id_value(7),
+            1,
+        )
+        .unwrap();
+        sf.next_id().unwrap();
+
+        let other = sf.clone();
+        let err = std::thread::spawn(move || other.next_id().unwrap_err()).join().unwrap();
+        assert!(matches!(err, Error::NoFreePartition));
+
+        assert!(matches!(
+            ThreadPartitionedSonyFlake::new(Settings::new(), 3).unwrap_err(),
+            Error::InvalidPartitionCount(3)
+        ));
+    }
+}
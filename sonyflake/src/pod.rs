@@ -0,0 +1,111 @@
+use crate::{hash_to_machine_id, MachineID};
+use gethostname::gethostname;
+use std::env;
+use std::fs;
+
+/// where Kubernetes mounts the namespace of the pod's service account
+const SERVICE_ACCOUNT_NAMESPACE: &str = "/var/run/secrets/kubernetes.io/serviceaccount/namespace";
+
+/// `PodOrdinalMachineID` is a [`MachineID`] using the ordinal of a Kubernetes StatefulSet pod, the number
+/// its name ends in, e.g. 3 for `worker-3`, as the machine id. The ordinal is stable across restarts
+/// and unique within the StatefulSet.
+///
+/// The pod name is read from the `HOSTNAME` environment variable, which Kubernetes sets to it,
+/// falling back to the system hostname.
+///
+/// StatefulSets of the same name in different namespaces have the same ordinals. To tell them apart,
+/// [`with_namespace_bits`] puts a hash of the namespace in the top bits of the machine id, leaving the
+/// rest for the ordinal. The namespace is read from the `POD_NAMESPACE` environment variable, e.g. set
+/// with the downward API, falling back to the namespace of the service account.
+///
+/// ```rust
+/// use infallible_sonyflake::{PodOrdinalMachineID, Settings};
+///
+/// std::env::set_var("HOSTNAME", "worker-3");
+/// let sf = Settings::new()
+///     .set_machine_id(Box::new(PodOrdinalMachineID::new()))
+///     .into_sonyflake()
+///     .unwrap();
+/// assert_eq!(sf.machine_id(), 3);
+/// ```
+///
+/// [`MachineID`]: trait.MachineID.html
+/// [`with_namespace_bits`]: struct.PodOrdinalMachineID.html#method.with_namespace_bits
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct PodOrdinalMachineID {
+    namespace_bits: u8,
+}
+
+impl PodOrdinalMachineID {
+    /// Create a new `PodOrdinalMachineID` using the ordinal alone.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Put the top `bits` bits of a hash of the namespace above the ordinal, which then has to fit in
+    /// the other `16 - bits` bits. Distinct namespaces may hash to the same bits.
+    pub fn with_namespace_bits(mut self, bits: u8) -> Self {
+        self.namespace_bits = bits.min(16);
+        self
+    }
+
+    /// Returns the machine id of the pod `pod_name` in `namespace`, which is only needed with namespace bits.
+    pub fn machine_id_of(&self, pod_name: &str, namespace: Option<&str>) -> Result<u16, String> {
+        let ordinal_bits = 16 - self.namespace_bits as u32;
+        let ordinal = pod_name
+            .rsplit_once('-')
+            .and_then(|(_, ordinal)| ordinal.parse::<u32>().ok())
+            .ok_or_else(|| format!("the pod name {:?} does not end in an ordinal", pod_name))?;
+        if ordinal >> ordinal_bits != 0 {
+            return Err(format!(
+                "the ordinal {} of the pod {:?} does not fit in {} bits",
+                ordinal, pod_name, ordinal_bits
+            ));
+        }
+        if self.namespace_bits == 0 {
+            return Ok(ordinal as u16);
+        }
+
+        let namespace = namespace.ok_or("the namespace of the pod is unknown")?;
+        let hash = hash_to_machine_id(namespace.as_bytes()) as u32;
+        Ok((hash >> ordinal_bits << ordinal_bits | ordinal) as u16)
+    }
+}
+
+impl MachineID for PodOrdinalMachineID {
+    fn machine_id(&mut self) -> Result<u16, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        let pod_name = match env::var("HOSTNAME") {
+            Ok(pod_name) if !pod_name.is_empty() => pod_name,
+            _ => gethostname().into_string().map_err(|_| "the hostname is not valid UTF-8")?,
+        };
+        let namespace = match env::var("POD_NAMESPACE") {
+            Ok(namespace) if !namespace.is_empty() => Some(namespace),
+            _ => fs::read_to_string(SERVICE_ACCOUNT_NAMESPACE).ok().map(|namespace| namespace.trim().to_string()),
+        };
+        Ok(self.machine_id_of(&pod_name, namespace.as_deref())?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PodOrdinalMachineID;
+
+    #[test]
+    fn test_pod_ordinal_machine_id() {
+        let machine_id = PodOrdinalMachineID::new();
+        assert_eq!(machine_id.machine_id_of("worker-3", None).unwrap(), 3);
+        assert_eq!(machine_id.machine_id_of("web-api-65535", None).unwrap(), 65535);
+        assert!(machine_id.machine_id_of("worker", None).is_err());
+        assert!(machine_id.machine_id_of("worker-x", None).is_err());
+        assert!(machine_id.machine_id_of("worker-65536", None).is_err());
+
+        let machine_id = PodOrdinalMachineID::new().with_namespace_bits(6);
+        let a = machine_id.machine_id_of("worker-3", Some("production")).unwrap();
+        let b = machine_id.machine_id_of("worker-3", Some("staging")).unwrap();
+        assert_eq!((a & 0x3ff, b & 0x3ff), (3, 3));
+        assert_ne!(a, b);
+        assert_eq!(machine_id.machine_id_of("worker-3", Some("production")).unwrap(), a);
+        assert!(machine_id.machine_id_of("worker-1024", Some("production")).is_err());
+        assert!(machine_id.machine_id_of("worker-3", None).is_err());
+    }
+}
@@ -0,0 +1,171 @@
+use crate::{check_borrowed_bits, BitLayout, Error, MachineID, MachineIDChecker, Settings, ThreadSlots, UnitClock};
+use std::sync::Arc;
+
+/// `SonyFlakePool` is a pool of distributed unique ID generators, one per calling thread, which
+/// partitions the machine id space so threads generate ids without sharing a lock.
+///
+/// The first time a thread calls `next_id`, it registers and is handed the next free generator.
+/// With `generators` set to `2^k`, generator `i` uses the machine id of the [`Settings`] with `i`
+/// in its top `k` bits, so each thread has its own machine id and the full sequence of a time window:
+/// `2^8` IDs per 10 msec per thread, instead of per process.
+///
+/// The machine id of the [`Settings`] therefore has to leave the top `k` bits free: with the
+/// default 16 machine id bits, a machine id below `2^(16 - k)`, e.g. below `2^13` for 8 generators.
+/// In general a pool has room for `2^(m - b)` generators, with `m` machine id bits set by
+/// `Settings::set_bit_layout` and a machine id `b` bits wide. The default machine id, the lower
+/// 16 bits of the private IP address, leaves no room, so set a narrower one.
+///
+/// Generators are never given back, so a thread which exits keeps its generator for the lifetime
+/// of the pool. Clones share the same set of generators, and the per-thread state of the pool is
+/// dropped with its last clone.
+///
+/// [`Settings`]: struct.Settings.html
+#[derive(Debug, Clone)]
+pub struct SonyFlakePool {
+    start_time: i64,
+    clock: Arc<UnitClock>,
+    layout: BitLayout,
+    machine_id: u16,
+    generators: u16,
+    slots: ThreadSlots,
+}
+
+impl SonyFlakePool {
+    /// Create a new `SonyFlakePool` of `generators` generators.
+    /// `generators` must be a power of two no greater than `2^n` with `n` machine id bits set by
+    /// `Settings::set_bit_layout`, and the machine id must leave the top bits for the generator index free.
+    pub fn new<M: MachineID, C: MachineIDChecker>(mut st: Settings<M, C>, generators: u16) -> Result<Self, Error> {
+        let layout = st.bit_layout;
+        if !generators.is_power_of_two() || generators as u64 > 1 << layout.machine_id {
+            return Err(Error::InvalidPartitionCount(generators));
+        }
+
+        let time_unit = st.get_time_unit()?;
+        let start_time = st.get_start_time()?;
+        let clock = Arc::new(UnitClock::new(st.nano_clock.take(), time_unit));
+
+        let machine_id = st.get_and_check_machine_id()?;
+        check_borrowed_bits(layout, 0, machine_id)?;
+        if machine_id as u64 >> (layout.machine_id - generators.trailing_zeros() as i64) != 0 {
+            return Err(Error::InvalidMachineID(machine_id));
+        }
+
+        Ok(Self {
+            start_time,
+            clock,
+            layout,
+            machine_id,
+            generators,
+            slots: ThreadSlots::new(generators),
+        })
+    }
+
+    /// Generate the next unique id with the calling thread's generator.
+    /// Returns an error if all generators are taken by other threads, or if the time overflows.
+    pub fn next_id(&self) -> Result<u64, Error> {
+        let (index, elapsed_time, sequence) =
+            self.slots.next(&self.clock, self.start_time, self.layout.sequence, self.layout.time)?;
+        Ok(self.layout.compose(elapsed_time, sequence, self.generator_machine_id(index)))
+    }
+
+    /// Returns the machine id of the pool, which the generators put their index on top of.
+    pub fn machine_id(&self) -> u16 {
+        self.machine_id
+    }
+
+    /// Returns the number of generators of the pool.
+    pub fn generators(&self) -> u16 {
+        self.generators
+    }
+
+    /// Returns the number of threads which have been handed a generator so far.
+    pub fn registered_threads(&self) -> u16 {
+        self.slots.registered()
+    }
+
+    /// Returns the allocation of the bits of the ids, see `Settings::set_bit_layout`.
+    pub fn bit_layout(&self) -> BitLayout {
+        self.layout
+    }
+
+    fn generator_machine_id(&self, index: u16) -> u16 {
+        let index_bits = self.generators.trailing_zeros() as i64;
+        self.machine_id | ((index as u64) << (self.layout.machine_id - index_bits)) as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SonyFlakePool;
+    use crate::{BitLayout, Error, IDParts, Settings};
+    use std::collections::HashSet;
+    use std::time::Duration;
+
+    #[test]
+    fn test_sonyflake_pool_concurrency() {
+        let pool = SonyFlakePool::new(Settings::new().set_machine_id_value(7), 8).unwrap();
+        assert_eq!(pool.generators(), 8);
+
+        let threads = (0..8)
+            .map(|_| {
+                let pool = pool.clone();
+                std::thread::spawn(move || (0..10000).map(|_| pool.next_id().unwrap()).collect::<Vec<_>>())
+            })
+            .collect::<Vec<_>>();
+
+        let mut ids = HashSet::new();
+        let mut machine_ids = HashSet::new();
+        for t in threads {
+            let thread_ids = t.join().expect("thread panicked");
+            assert!(thread_ids.windows(2).all(|w| w[0] < w[1]));
+            // one generator per thread
+            let machine_id = IDParts::decompose(thread_ids[0]).get_machine_id();
+            assert_eq!(machine_id & 0x1fff, 7);
+            assert!(thread_ids.iter().all(|id| IDParts::decompose(*id).get_machine_id() == machine_id));
+            assert!(machine_ids.insert(machine_id));
+            for id in thread_ids {
+                assert!(ids.insert(id), "duplicate id: {}", id);
+            }
+        }
+        assert_eq!(ids.len(), 80000);
+        assert_eq!(machine_ids.len(), 8);
+        assert_eq!(pool.registered_threads(), 8);
+    }
+
+    #[test]
+    fn test_sonyflake_pool_exhausted() {
+        let pool = SonyFlakePool::new(Settings::new().set_machine_id_value(7), 1).unwrap();
+        assert_eq!(IDParts::decompose(pool.next_id().unwrap()).get_machine_id(), 7);
+
+        let other = pool.clone();
+        let err = std::thread::spawn(move || other.next_id().unwrap_err()).join().unwrap();
+        assert!(matches!(err, Error::NoFreePartition));
+    }
+
+    #[test]
+    fn test_sonyflake_pool_invalid() {
+        assert!(matches!(
+            SonyFlakePool::new(Settings::new().set_machine_id_value(7), 3).unwrap_err(),
+            Error::InvalidPartitionCount(3)
+        ));
+
+        // the machine id has to leave the top 3 bits free for 8 generators
+        assert!(matches!(
+            SonyFlakePool::new(Settings::new().set_machine_id_value(1 << 13), 8).unwrap_err(),
+            Error::InvalidMachineID(8192)
+        ));
+
+        // 4 machine id bits fit at most 16 generators, with a time unit short enough for 51 time bits
+        let settings = || {
+            Settings::new()
+                .set_machine_id_value(0)
+                .set_bit_layout(BitLayout::new(51, 8, 4).unwrap())
+                .set_time_unit(Duration::from_micros(1))
+        };
+        assert_eq!(SonyFlakePool::new(settings(), 16).unwrap().generators(), 16);
+        assert!(matches!(
+            SonyFlakePool::new(settings(), 32).unwrap_err(),
+            Error::InvalidPartitionCount(32)
+        ));
+    }
+}
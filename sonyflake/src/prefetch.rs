@@ -0,0 +1,91 @@
+use crate::InfallibleSonyFlake;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::sync::Arc;
+
+/// `PrefetchSonyFlake` generates ids ahead of demand on a background thread, so `next_id`
+/// only takes an id off a bounded buffer.
+///
+/// The background thread keeps up to `capacity` ids buffered, generating with an
+/// [`InfallibleSonyFlake`], which rebases itself if the time overflows. It stops once the
+/// `PrefetchSonyFlake` is dropped. Prefetched ids carry the time they were generated at, not the
+/// time they were taken, so they may be up to `capacity / 2^8 * 10` msec older than `next_id` calls.
+///
+/// [`InfallibleSonyFlake`]: struct.InfallibleSonyFlake.html
+#[derive(Debug)]
+pub struct PrefetchSonyFlake {
+    ids: Receiver<u64>,
+    buffered: Arc<AtomicUsize>,
+}
+
+impl PrefetchSonyFlake {
+    /// Create a new `PrefetchSonyFlake` buffering up to `capacity` ids generated by `sf`.
+    pub fn new(sf: InfallibleSonyFlake, capacity: usize) -> Self {
+        let (tx, rx) = sync_channel(capacity.max(1));
+        let buffered = Arc::new(AtomicUsize::new(0));
+
+        let producer_buffered = buffered.clone();
+        std::thread::spawn(move || {
+            // stops when the consumer is dropped
+            while tx.send(sf.next_id()).is_ok() {
+                producer_buffered.fetch_add(1, Ordering::Release);
+            }
+        });
+
+        Self { ids: rx, buffered }
+    }
+
+    /// Take the next unique id, waiting for the background thread if the buffer is empty.
+    pub fn next_id(&mut self) -> u64 {
+        let id = self.ids.recv().expect("prefetch thread panicked");
+        // the producer may not have counted the id yet
+        let _ = self
+            .buffered
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1));
+        id
+    }
+
+    /// Returns roughly the number of ids waiting in the buffer.
+    pub fn buffered(&self) -> usize {
+        self.buffered.load(Ordering::Acquire)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PrefetchSonyFlake;
+    use crate::{IDParts, Settings};
+    use std::collections::HashSet;
+    use std::time::Duration;
+
+    #[test]
+    fn test_prefetch_sonyflake() {
+        let sf = Settings::new()
+            .set_machine_id_value(5)
+            .into_infallible_sonyflake()
+            .unwrap();
+        let mut prefetch = PrefetchSonyFlake::new(sf, 64);
+
+        let consumer = std::thread::spawn(move || {
+            let mut ids = HashSet::new();
+            let mut fill = Vec::new();
+            for i in 0..2000 {
+                let id = prefetch.next_id();
+                assert_eq!(IDParts::decompose(id).get_machine_id(), 5);
+                assert!(ids.insert(id), "duplicate id: {}", id);
+
+                // a steady demand well below the generation rate
+                if i % 10 == 9 {
+                    std::thread::sleep(Duration::from_millis(1));
+                    fill.push(prefetch.buffered());
+                }
+            }
+            (ids, fill)
+        });
+
+        let (ids, fill) = consumer.join().expect("consumer panicked");
+        assert_eq!(ids.len(), 2000);
+        let full = fill.iter().filter(|n| **n >= 32).count();
+        assert!(full * 2 >= fill.len(), "buffer fill: {:?}", fill);
+    }
+}
@@ -0,0 +1,149 @@
+use crate::{Error, MachineIDChecker};
+#[cfg(not(feature = "std"))]
+use alloc::{string::String, vec::Vec};
+use core::ops::RangeInclusive;
+
+/// `RegionMap` partitions the 16-bit machine id space into named regions for multi-region deployments.
+///
+/// Each region owns one or more non-overlapping ranges of machine ids. The map attributes a machine
+/// id to its region with [`region_of`], and builds a [`RegionChecker`] which only accepts machine ids
+/// of a given region, so a generator can never be constructed with an id allotted to another region.
+///
+/// ```rust
+/// use infallible_sonyflake::RegionMap;
+///
+/// let regions = RegionMap::new()
+///     .add_region("us-east", 0..=9999).unwrap()
+///     .add_region("eu-west", 10000..=19999).unwrap();
+/// assert_eq!(regions.region_of(1234), Some("us-east"));
+/// assert_eq!(regions.region_of(40000), None);
+/// ```
+///
+/// [`region_of`]: struct.RegionMap.html#method.region_of
+/// [`RegionChecker`]: struct.RegionChecker.html
+#[derive(Debug, Clone, Default)]
+pub struct RegionMap {
+    regions: Vec<(String, RangeInclusive<u16>)>,
+}
+
+impl RegionMap {
+    /// Create an empty `RegionMap`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allot the machine ids in `ids` to the region `name`. A region may own several ranges.
+    /// Returns `Error::InvalidRegion` if the range is empty or overlaps a range already in the map.
+    pub fn add_region(mut self, name: impl Into<String>, ids: RangeInclusive<u16>) -> Result<Self, Error> {
+        let name = name.into();
+        if ids.is_empty()
+            || self
+                .regions
+                .iter()
+                .any(|(_, other)| ids.start() <= other.end() && other.start() <= ids.end())
+        {
+            return Err(Error::InvalidRegion(name));
+        }
+
+        self.regions.push((name, ids));
+        Ok(self)
+    }
+
+    /// Remove all the ranges allotted to the region `name`.
+    pub fn remove_region(mut self, name: &str) -> Self {
+        self.regions.retain(|(region, _)| region != name);
+        self
+    }
+
+    /// Returns the name of the region the machine id is allotted to, if any.
+    pub fn region_of(&self, machine_id: u16) -> Option<&str> {
+        self.regions
+            .iter()
+            .find(|(_, ids)| ids.contains(&machine_id))
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Returns a checker accepting only the machine ids of the region `name`,
+    /// or `None` if the region is not in the map.
+    pub fn checker(&self, name: &str) -> Option<RegionChecker> {
+        let ids = self
+            .regions
+            .iter()
+            .filter(|(region, _)| region == name)
+            .map(|(_, ids)| ids.clone())
+            .collect::<Vec<_>>();
+
+        if ids.is_empty() {
+            None
+        } else {
+            Some(RegionChecker { ids })
+        }
+    }
+}
+
+/// `RegionChecker` is a [`MachineIDChecker`] rejecting machine ids outside of a region's allotment.
+/// It is built by [`RegionMap::checker`].
+///
+/// [`MachineIDChecker`]: trait.MachineIDChecker.html
+/// [`RegionMap::checker`]: struct.RegionMap.html#method.checker
+#[derive(Debug, Clone)]
+pub struct RegionChecker {
+    ids: Vec<RangeInclusive<u16>>,
+}
+
+impl MachineIDChecker for RegionChecker {
+    fn check_machine_id(&self, id: u16) -> bool {
+        self.ids.iter().any(|ids| ids.contains(&id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RegionMap;
+    use crate::{Error, IDParts, Settings};
+
+    #[test]
+    fn test_region_map() {
+        let regions = RegionMap::new()
+            .add_region("us-east", 0..=9999)
+            .unwrap()
+            .add_region("eu-west", 10000..=19999)
+            .unwrap()
+            .add_region("us-east", 60000..=65535)
+            .unwrap();
+
+        let sf = Settings::new()
+            .set_machine_id_value(4321)
+            .set_check_machine_id(Box::new(regions.checker("us-east").unwrap()))
+            .into_sonyflake()
+            .unwrap();
+        let parts = IDParts::decompose(sf.next_id().unwrap());
+        assert_eq!(regions.region_of(parts.get_machine_id() as u16), Some("us-east"));
+        assert_eq!(regions.region_of(61000), Some("us-east"));
+        assert_eq!(regions.region_of(12345), Some("eu-west"));
+        assert_eq!(regions.region_of(30000), None);
+
+        let err = Settings::new()
+            .set_machine_id_value(12345)
+            .set_check_machine_id(Box::new(regions.checker("us-east").unwrap()))
+            .into_sonyflake()
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidMachineID(12345)));
+
+        assert!(regions.checker("ap-south").is_none());
+        let regions = regions.remove_region("eu-west");
+        assert_eq!(regions.region_of(12345), None);
+    }
+
+    #[test]
+    fn test_region_map_overlap() {
+        let regions = RegionMap::new().add_region("us-east", 0..=9999).unwrap();
+        assert!(matches!(
+            regions.clone().add_region("eu-west", 9999..=19999).unwrap_err(),
+            Error::InvalidRegion(name) if name == "eu-west"
+        ));
+        #[allow(clippy::reversed_empty_ranges)]
+        let empty = 20..=10;
+        assert!(regions.add_region("eu-west", empty).is_err());
+    }
+}
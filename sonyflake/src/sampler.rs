@@ -0,0 +1,91 @@
+use std::fmt::{Debug, Formatter};
+
+/// `ClockStallHook` is notified when the clock appears to have stopped advancing,
+/// see [`Settings::set_clock_stall_hook`].
+///
+/// [`Settings::set_clock_stall_hook`]: struct.Settings.html#method.set_clock_stall_hook
+pub trait ClockStallHook: Send + Sync {
+    /// `on_clock_stall` receives the fraction of the sampled clock reads which were identical to
+    /// the previous read.
+    fn on_clock_stall(&self, stalled_fraction: f64);
+}
+
+/// Samples the clock reads of a generator in windows of `every` ids, and invokes the hook for each
+/// window in which at least `threshold` of the reads did not advance.
+pub(crate) struct ClockSampler {
+    every: u32,
+    threshold: f64,
+    hook: Box<dyn ClockStallHook>,
+    last_read: Option<i64>,
+    reads: u32,
+    stalled: u32,
+}
+
+impl ClockSampler {
+    pub(crate) fn new(every: u32, threshold: f64, hook: Box<dyn ClockStallHook>) -> Self {
+        Self {
+            every: every.max(1),
+            threshold,
+            hook,
+            last_read: None,
+            reads: 0,
+            stalled: 0,
+        }
+    }
+
+    pub(crate) fn record(&mut self, read: i64) {
+        if self.last_read == Some(read) {
+            self.stalled += 1;
+        }
+        self.last_read = Some(read);
+        self.reads += 1;
+
+        if self.reads == self.every {
+            let stalled_fraction = self.stalled as f64 / self.reads as f64;
+            if stalled_fraction >= self.threshold {
+                self.hook.on_clock_stall(stalled_fraction);
+            }
+            self.reads = 0;
+            self.stalled = 0;
+        }
+    }
+}
+
+impl Debug for ClockSampler {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClockSampler")
+            .field("every", &self.every)
+            .field("threshold", &self.threshold)
+            .field("reads", &self.reads)
+            .field("stalled", &self.stalled)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ClockSampler, ClockStallHook};
+    use std::sync::{Arc, Mutex};
+
+    struct RecordingHook(Arc<Mutex<Vec<f64>>>);
+
+    impl ClockStallHook for RecordingHook {
+        fn on_clock_stall(&self, stalled_fraction: f64) {
+            self.0.lock().unwrap().push(stalled_fraction);
+        }
+    }
+
+    #[test]
+    fn test_clock_sampler() {
+        let fired = Arc::new(Mutex::new(Vec::new()));
+        let mut sampler = ClockSampler::new(10, 0.5, Box::new(RecordingHook(fired.clone())));
+
+        // a clock advancing on every read
+        (0..100).for_each(|read| sampler.record(read));
+        assert!(fired.lock().unwrap().is_empty());
+
+        // a stalled clock
+        (0..20).for_each(|_| sampler.record(100));
+        assert_eq!(*fired.lock().unwrap(), vec![0.9, 1.0]);
+    }
+}
@@ -0,0 +1,130 @@
+use chrono::{DateTime, TimeZone, Utc};
+use std::time::Duration;
+
+/// `Snapshot` is the serializable state of a [`SonyFlake`], taken by [`SonyFlake::snapshot`]:
+/// the start time, time unit and machine id it generates ids with, and the position it has reached.
+///
+/// A worker persisting a snapshot at shutdown resumes with [`Settings::from_snapshot`] right after
+/// the last id it emitted, so it never reissues an id, even if the clock went backwards meanwhile:
+/// beyond the tolerance of `Settings::set_clock_regression_tolerance`, `next_id` returns
+/// `Error::ClockMovedBackwards` until the clock catches up.
+///
+/// ```rust
+/// use infallible_sonyflake::{Settings, Snapshot};
+///
+/// let sf = Settings::new().set_machine_id_value(3).into_sonyflake().unwrap();
+/// let last = sf.next_id().unwrap();
+/// let snapshot: Snapshot = sf.snapshot();
+///
+/// let resumed = Settings::from_snapshot(&snapshot).into_sonyflake().unwrap();
+/// assert!(resumed.next_id().unwrap() > last);
+/// ```
+///
+/// [`SonyFlake`]: struct.SonyFlake.html
+/// [`SonyFlake::snapshot`]: struct.SonyFlake.html#method.snapshot
+/// [`Settings::from_snapshot`]: struct.Settings.html#method.from_snapshot
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// the start time, in nanoseconds since the unix epoch
+    start_time: i64,
+    /// the time unit, in nanoseconds
+    time_unit: i64,
+    machine_id: u16,
+    elapsed_time: i64,
+    sequence: u16,
+}
+
+impl Snapshot {
+    pub(crate) fn new(start_time: i64, time_unit: i64, machine_id: u16, elapsed_time: i64, sequence: u16) -> Self {
+        Self {
+            start_time: start_time * time_unit,
+            time_unit,
+            machine_id,
+            elapsed_time,
+            sequence,
+        }
+    }
+
+    /// `get_start_time` returns the start time of the generator.
+    pub fn get_start_time(&self) -> DateTime<Utc> {
+        Utc.timestamp_nanos(self.start_time)
+    }
+
+    /// `get_time_unit` returns the time unit of the generator.
+    pub fn get_time_unit(&self) -> Duration {
+        Duration::from_nanos(self.time_unit as u64)
+    }
+
+    /// `get_machine_id` returns the machine id of the generator.
+    pub fn get_machine_id(&self) -> u16 {
+        self.machine_id
+    }
+
+    /// `get_elapsed_time` returns the time part of the last id generated.
+    pub fn get_elapsed_time(&self) -> i64 {
+        self.elapsed_time
+    }
+
+    /// `get_sequence` returns the sequence of the last id generated.
+    pub fn get_sequence(&self) -> u16 {
+        self.sequence
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Snapshot;
+    use crate::{Error, IDParts, Settings};
+    use chrono::{Duration, Utc};
+    use std::time::Duration as StdDuration;
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let start_time = Utc::now() - Duration::days(1);
+        let sf = Settings::new()
+            .set_start_time(start_time)
+            .set_machine_id_value(21)
+            .set_time_unit(StdDuration::from_millis(1))
+            .into_sonyflake()
+            .unwrap();
+        // ids from the future, as after a burst, so that the clock alone would not keep the resumed ids higher
+        {
+            let mut inner = sf.inner.lock();
+            inner.elapsed_time = inner.clock.elapsed_time(inner.start_time) + 100;
+        }
+        let last = (0..300).map(|_| sf.next_id().unwrap()).collect::<Vec<_>>()[299];
+
+        let json = serde_json::to_string(&sf.snapshot()).unwrap();
+        let snapshot: Snapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(snapshot, sf.snapshot());
+        assert_eq!(snapshot.get_machine_id(), 21);
+        assert_eq!(snapshot.get_time_unit(), StdDuration::from_millis(1));
+        assert_eq!(snapshot.get_start_time(), sf.start_time());
+        let parts = IDParts::decompose(last);
+        assert_eq!(snapshot.get_elapsed_time(), parts.get_time() as i64);
+        assert_eq!(snapshot.get_sequence(), parts.get_sequence() as u16);
+
+        let resumed = Settings::from_snapshot(&snapshot).into_sonyflake().unwrap();
+        assert_eq!(resumed.machine_id(), 21);
+        assert_eq!(resumed.start_time(), sf.start_time());
+        let ids = (0..300).map(|_| resumed.next_id().unwrap()).collect::<Vec<_>>();
+        assert!(ids[0] > last);
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn test_snapshot_out_of_range() {
+        let sf = Settings::new()
+            .set_machine_id_value(21)
+            .into_sonyflake()
+            .unwrap();
+        let snapshot = Snapshot {
+            sequence: 256,
+            ..sf.snapshot()
+        };
+        assert!(matches!(
+            Settings::from_snapshot(&snapshot).into_sonyflake().unwrap_err(),
+            Error::SequenceOutOfRange(256)
+        ));
+    }
+}
@@ -0,0 +1,175 @@
+use crate::{sequence_mask, Error, UnitClock};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU16, AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+
+/// source of the keys identifying a generator (and its clones) in the thread-local state
+static NEXT_KEY: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static WINDOWS: RefCell<HashMap<usize, (Weak<Slots>, Window)>> = RefCell::new(HashMap::new());
+}
+
+/// the time window of a thread, in the slot it was handed
+#[derive(Debug)]
+struct Window {
+    slot: u16,
+    elapsed_time: i64,
+    sequence: u16,
+}
+
+/// the slots of a generator, shared by its clones
+#[derive(Debug)]
+struct Slots {
+    key: usize,
+    count: u16,
+    next: AtomicU16,
+}
+
+impl Drop for Slots {
+    fn drop(&mut self) {
+        // the windows of other threads are purged when they next register
+        let _ = WINDOWS.try_with(|windows| {
+            if let Ok(mut windows) = windows.try_borrow_mut() {
+                windows.remove(&self.key);
+            }
+        });
+    }
+}
+
+/// `ThreadSlots` hands each calling thread one of `count` slots and keeps the thread's time window
+/// in thread-local storage, so threads generate ids without sharing a lock.
+///
+/// Slots are never given back, so a thread which exits keeps its slot for the lifetime of the generator.
+/// The thread-local windows of a generator are dropped with its last clone: right away on the dropping
+/// thread, and on any other thread the next time it registers with a generator.
+#[derive(Debug, Clone)]
+pub(crate) struct ThreadSlots {
+    slots: Arc<Slots>,
+}
+
+impl ThreadSlots {
+    pub(crate) fn new(count: u16) -> Self {
+        Self {
+            slots: Arc::new(Slots {
+                key: NEXT_KEY.fetch_add(1, Ordering::Relaxed),
+                count,
+                next: AtomicU16::new(0),
+            }),
+        }
+    }
+
+    /// Returns the number of threads which have been handed a slot so far.
+    pub(crate) fn registered(&self) -> u16 {
+        self.slots.next.load(Ordering::Acquire).min(self.slots.count)
+    }
+
+    /// Advances the window of the calling thread to its next id, registering the thread first if needed,
+    /// and returns the slot of the thread with the elapsed time and sequence of the id. The sequence of
+    /// `sequence_bits` bits wraps into the next time unit, sleeping until it starts.
+    pub(crate) fn next(
+        &self,
+        clock: &UnitClock,
+        start_time: i64,
+        sequence_bits: i64,
+        time_bits: i64,
+    ) -> Result<(u16, i64, u16), Error> {
+        let mask_sequence = sequence_mask(sequence_bits);
+
+        WINDOWS.with(|windows| {
+            let mut windows = windows.borrow_mut();
+            let key = self.slots.key;
+            if !windows.contains_key(&key) {
+                let window = Window {
+                    slot: self.register()?,
+                    elapsed_time: 0,
+                    sequence: 0,
+                };
+                // drop the windows of generators which are gone
+                windows.retain(|_, (slots, _)| slots.strong_count() > 0);
+                windows.insert(key, (Arc::downgrade(&self.slots), window));
+            }
+            let (_, window) = windows.get_mut(&key).expect("the window of a registered thread");
+
+            let current = clock.elapsed_time(start_time);
+
+            if window.elapsed_time < current {
+                window.elapsed_time = current;
+                window.sequence = 0;
+            } else {
+                // window.elapsed_time >= current
+                window.sequence = window.sequence.wrapping_add(1) & mask_sequence;
+                if window.sequence == 0 {
+                    window.elapsed_time += 1;
+                    let overtime = window.elapsed_time - current;
+                    std::thread::sleep(clock.sleep_time(overtime));
+                }
+            }
+
+            if window.elapsed_time >= 1 << time_bits {
+                return Err(Error::TimeOverflow);
+            }
+
+            Ok((window.slot, window.elapsed_time, window.sequence))
+        })
+    }
+
+    fn register(&self) -> Result<u16, Error> {
+        let count = self.slots.count;
+        self.slots
+            .next
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |next| {
+                if next < count {
+                    Some(next + 1)
+                } else {
+                    None
+                }
+            })
+            .map_err(|_| Error::NoFreePartition)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ThreadSlots, WINDOWS};
+    use crate::{UnitClock, FLAKE_TIME_UNIT};
+
+    fn windows() -> usize {
+        WINDOWS.with(|windows| windows.borrow().len())
+    }
+
+    #[test]
+    fn test_thread_slots_purged() {
+        let clock = UnitClock::new(None, FLAKE_TIME_UNIT);
+        let base = windows();
+
+        let slots = ThreadSlots::new(4);
+        let clone = slots.clone();
+        slots.next(&clock, 0, 8, 39).unwrap();
+        assert_eq!(windows(), base + 1);
+        drop(slots);
+        // the clone keeps the window
+        clone.next(&clock, 0, 8, 39).unwrap();
+        assert_eq!(windows(), base + 1);
+        drop(clone);
+        assert_eq!(windows(), base);
+
+        // generators dropped on another thread are purged when this thread next registers
+        let dropped = (0..100)
+            .map(|_| {
+                let slots = ThreadSlots::new(1);
+                slots.next(&clock, 0, 8, 39).unwrap();
+                slots
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(windows(), base + 100);
+        std::thread::spawn(move || drop(dropped)).join().unwrap();
+        assert_eq!(windows(), base + 100);
+
+        let slots = ThreadSlots::new(1);
+        slots.next(&clock, 0, 8, 39).unwrap();
+        assert_eq!(windows(), base + 1);
+        assert_eq!(slots.registered(), 1);
+    }
+}